@@ -0,0 +1,44 @@
+// Builds an Apple-1 from scratch: 4K of RAM, a tiny echo program in ROM and
+// the PIA wired terminal, then types a line and prints the screen.
+//
+//   cargo run --example echo_terminal
+
+use apple1_rst::bus::IoAddressable;
+use apple1_rst::cpu::CpuVariant;
+use apple1_rst::devices::{Ram, Rom};
+use apple1_rst::MachineBuilder;
+
+// Sets up the PIA the way the Woz Monitor does, then echoes every key
+const ECHO: [u8; 32] = [
+    0xA9, 0x7F, 0x8D, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x11, 0xD0, 0x8D, 0x13, 0xD0, 0xAD, 0x11, 0xD0,
+    0x10, 0xFB, 0xAD, 0x10, 0xD0, 0x2C, 0x12, 0xD0, 0x30, 0xFB, 0x8D, 0x12, 0xD0, 0x4C, 0x0D, 0xFF,
+];
+
+fn main() {
+    // ROM images start with a two byte header, the reset vector points at $FF00
+    let mut image = vec![0x00; 2 + 0x100];
+    image[2..2 + ECHO.len()].copy_from_slice(&ECHO);
+    image[2 + 0xFD] = 0xFF;
+    let mut rom = Rom::init_with_size(0x100);
+    rom.flash(&image);
+
+    let mut machine = MachineBuilder::new()
+        .variant(CpuVariant::Cmos65C02)
+        .map(
+            "RAM",
+            [0x0000, 0x0FFF],
+            Box::new(Ram::init_with_size(0x1000)),
+        )
+        .map("ROM", [0xFF00, 0xFFFF], Box::new(rom))
+        .build()
+        .expect("no profile, nothing to fail");
+
+    machine.feeder.type_line("HELLO, APPLE-1");
+    while !machine.feeder.is_idle() {
+        machine.step();
+    }
+    machine.run_cycles(100);
+
+    print!("{}", machine.display.screen_text());
+    println!("{} cycles", machine.cycles());
+}
@@ -0,0 +1,39 @@
+// Runs a headless test script against the Woz Monitor profile.
+//
+//   cargo run --example scripted -- path/to/wozmon.bin
+
+use apple1_rst::testing::Script;
+use apple1_rst::MachineBuilder;
+use std::{env, fs, io, process};
+
+const SCRIPT: &str = r#"
+wait /\\\n/
+type "FF00.FF07\r"
+wait /FF00: D8 58 A0 7F 8C 12 D0 A9/
+screen
+"#;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: scripted <wozmon.bin>");
+        process::exit(2);
+    });
+    let monitor = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+
+    let mut machine = MachineBuilder::new()
+        .profile("woz")
+        .and_then(|builder| builder.image("monitor", &monitor).build())
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+
+    let script = Script::parse(SCRIPT).expect("valid script");
+    if let Err(err) = script.run(&mut machine, &mut io::stdout()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,376 @@
+use super::scheduler::{EventId, Scheduler};
+use super::wav::Wav;
+use super::IoAddressable;
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+// Apple Cassette Interface. Mapped at $C000-$C1FF as a single component:
+// any access to $C0xx toggles the tape output flip-flop, and reads there
+// return the ROM byte with A0 replaced by the tape input level. $C1xx is
+// the plain 256 byte ROM.
+//
+// Tape timing runs on the CPU's scheduler: playback schedules an event per
+// input edge, and output toggles are stamped with the scheduler's cycle.
+// Without a scheduler the tape doesn't move. Toggles are only kept between
+// `start_recording` and `stop_recording`, loading a tape polls $C081 in a
+// tight loop and would otherwise record for as long as the machine runs.
+
+// 14.31818 MHz master clock / 14
+pub const APPLE1_CLOCK_HZ: f64 = 1_022_727.0;
+
+const IO_PAGE_SIZE: usize = 0x100;
+const ROM_SIZE: usize = 0x100;
+const OUTPUT_AMPLITUDE: i16 = 0x6000;
+// Silence appended after the last recorded edge so players don't clip it
+const RECORDING_TAIL_SECONDS: f64 = 0.1;
+
+#[derive(Debug)]
+struct TapeState {
+    cpu_hz: f64,
+    // Input level transitions, in cycles since playback started
+    input: Vec<usize>,
+    playing: bool,
+    level: bool,
+    // Event for the next input edge
+    edge: Option<EventId>,
+    // Output flip-flop toggles, in absolute CPU cycles
+    recording: Vec<usize>,
+    recording_on: bool,
+    scheduler: Option<Scheduler>,
+}
+
+impl TapeState {
+    fn now(&self) -> usize {
+        self.scheduler.as_ref().map_or(0, Scheduler::now)
+    }
+
+    fn stop(&mut self) {
+        if let (Some(scheduler), Some(edge)) = (&self.scheduler, self.edge.take()) {
+            scheduler.cancel(edge);
+        }
+        self.playing = false;
+        self.level = false;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tape {
+    state: Rc<RefCell<TapeState>>,
+}
+
+impl Tape {
+    pub fn init(cpu_hz: f64) -> Tape {
+        Tape {
+            state: Rc::new(RefCell::new(TapeState {
+                cpu_hz,
+                input: Vec::new(),
+                playing: false,
+                level: false,
+                edge: None,
+                recording: Vec::new(),
+                recording_on: false,
+                scheduler: None,
+            })),
+        }
+    }
+
+    pub fn load_wav<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.insert(&Wav::decode(&fs::read(path)?)?);
+        Ok(())
+    }
+
+    // Replaces the tape in the deck. Playback starts with the first read of
+    // the input after this call.
+    pub fn insert(&self, wav: &Wav) {
+        let mut state = self.state.borrow_mut();
+        let peak = wav
+            .samples
+            .iter()
+            .map(|s| (*s as i32).abs())
+            .max()
+            .unwrap_or(0);
+        // Schmitt trigger around zero, so hiss on silent stretches is ignored
+        let threshold = (peak / 8).max(1);
+        let cycles_per_sample = state.cpu_hz / wav.sample_rate as f64;
+
+        let mut level = false;
+        let mut input = Vec::new();
+        for (i, sample) in wav.samples.iter().enumerate() {
+            let sample = *sample as i32;
+            if (!level && sample > threshold) || (level && sample < -threshold) {
+                level = !level;
+                input.push((i as f64 * cycles_per_sample) as usize);
+            }
+        }
+
+        state.stop();
+        state.input = input;
+    }
+
+    pub fn rewind(&self) {
+        self.state.borrow_mut().stop();
+    }
+
+    pub fn attach_scheduler(&self, scheduler: Scheduler) {
+        let mut state = self.state.borrow_mut();
+        state.stop();
+        state.scheduler = Some(scheduler);
+    }
+
+    pub fn save_wav<P: AsRef<Path>>(&self, path: P, sample_rate: u32) -> io::Result<()> {
+        fs::write(path, self.recording(sample_rate).encode())
+    }
+
+    // Renders the output flip-flop history as a square wave
+    pub fn recording(&self, sample_rate: u32) -> Wav {
+        let state = self.state.borrow();
+        let (first, last) = match (state.recording.first(), state.recording.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Wav::init(sample_rate, Vec::new()),
+        };
+
+        let cycles_per_sample = state.cpu_hz / sample_rate as f64;
+        let span = (last - first) as f64 + RECORDING_TAIL_SECONDS * state.cpu_hz;
+        let count = (span / cycles_per_sample) as usize + 1;
+
+        let mut samples = Vec::with_capacity(count);
+        let mut toggles = 0;
+        for i in 0..count {
+            let at = first + (i as f64 * cycles_per_sample) as usize;
+            while toggles < state.recording.len() && state.recording[toggles] <= at {
+                toggles += 1;
+            }
+            samples.push(if toggles % 2 == 1 {
+                OUTPUT_AMPLITUDE
+            } else {
+                -OUTPUT_AMPLITUDE
+            });
+        }
+
+        Wav::init(sample_rate, samples)
+    }
+
+    // Keeps output toggles from now on, after any already recorded
+    pub fn start_recording(&self) {
+        self.state.borrow_mut().recording_on = true;
+    }
+
+    pub fn stop_recording(&self) {
+        self.state.borrow_mut().recording_on = false;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.state.borrow().recording_on
+    }
+
+    pub fn clear_recording(&self) {
+        self.state.borrow_mut().recording.clear();
+    }
+
+    pub fn recorded_edges(&self) -> usize {
+        self.state.borrow().recording.len()
+    }
+
+    fn input_level(&self) -> bool {
+        if !self.state.borrow().playing {
+            self.play();
+        }
+        self.state.borrow().level
+    }
+
+    // Takes the edges due right away, then schedules the next one
+    fn play(&self) {
+        let mut state = self.state.borrow_mut();
+        state.playing = true;
+        let scheduler = match &state.scheduler {
+            Some(scheduler) => scheduler.clone(),
+            None => return,
+        };
+        let start = scheduler.now();
+        let mut next = state.input.partition_point(|edge| *edge == 0);
+        state.level = next % 2 == 1;
+        let first = match state.input.get(next) {
+            Some(edge) => start + edge,
+            None => return,
+        };
+
+        let tape = self.state.clone();
+        state.edge = Some(scheduler.schedule_at(first, move |_| {
+            let mut tape = tape.borrow_mut();
+            tape.level = !tape.level;
+            next += 1;
+            let due = tape.input.get(next).map(|edge| start + edge);
+            if due.is_none() {
+                tape.edge = None;
+            }
+            due
+        }));
+    }
+
+    fn toggle_output(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.recording_on {
+            let now = state.now();
+            state.recording.push(now);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Aci {
+    rom: Vec<u8>,
+    tape: Tape,
+}
+
+impl Aci {
+    pub fn init(tape: Tape) -> Aci {
+        Aci {
+            rom: vec![0x00; ROM_SIZE],
+            tape,
+        }
+    }
+
+    pub fn tape(&self) -> Tape {
+        self.tape.clone()
+    }
+}
+
+impl IoAddressable for Aci {
+    fn read(&mut self, address: usize) -> u8 {
+        if address < IO_PAGE_SIZE {
+            self.tape.toggle_output();
+            let input = self.tape.input_level() as usize;
+            self.rom[(address & 0xFE) | input]
+        } else {
+            self.rom[(address - IO_PAGE_SIZE) % ROM_SIZE]
+        }
+    }
+
+    fn write(&mut self, address: usize, _value: u8) {
+        if address < IO_PAGE_SIZE {
+            self.tape.toggle_output();
+        }
+    }
+
+    fn flash(&mut self, data: &[u8]) {
+        let payload = &data[2..];
+
+        self.rom[..payload.len()].copy_from_slice(payload);
+    }
+
+    fn attach_scheduler(&mut self, scheduler: &Scheduler) {
+        self.tape.attach_scheduler(scheduler.clone());
+    }
+
+    // I/O page peeks see the ROM as if the tape input were low
+    fn peek(&self, address: usize) -> u8 {
+        self.rom[address % ROM_SIZE]
+    }
+
+    fn poke(&mut self, address: usize, value: u8) {
+        self.rom[address % ROM_SIZE] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_aci() -> (Aci, Scheduler) {
+        let mut rom_data = vec![0x00, 0x00];
+        rom_data.extend((0..=255u8).collect::<Vec<u8>>());
+        let mut aci = Aci::init(Tape::init(APPLE1_CLOCK_HZ));
+        aci.flash(&rom_data);
+        let scheduler = Scheduler::init();
+        aci.attach_scheduler(&scheduler);
+        (aci, scheduler)
+    }
+
+    #[test]
+    fn initial_state() {
+        let (mut aci, _) = build_aci();
+        assert_eq!(0x00, aci.read(0x100));
+        assert_eq!(0x81, aci.read(0x181));
+        assert_eq!(0, aci.tape.recorded_edges());
+    }
+
+    #[test]
+    fn io_page_toggles_output() {
+        let (mut aci, scheduler) = build_aci();
+        aci.tape.start_recording();
+        scheduler.run_until(100);
+        aci.write(0x00, 0);
+        scheduler.run_until(600);
+        aci.read(0x81);
+        assert_eq!(vec![100, 600], aci.tape.state.borrow().recording);
+
+        aci.tape.stop_recording();
+        aci.read(0x81);
+        assert_eq!(2, aci.tape.recorded_edges());
+    }
+
+    #[test]
+    fn reading_input_does_not_record() {
+        let (mut aci, scheduler) = build_aci();
+        assert!(!aci.tape.is_recording());
+        for cycles in 0..1000 {
+            scheduler.run_until(cycles);
+            aci.read(0x81);
+        }
+        assert_eq!(0, aci.tape.recorded_edges());
+    }
+
+    #[test]
+    fn io_page_reads_input_on_a0() {
+        let (mut aci, scheduler) = build_aci();
+        let tape = aci.tape();
+        // 1 kHz square wave at 8 kHz
+        let samples = (0..64)
+            .map(|i| if (i / 4) % 2 == 0 { 1000 } else { -1000 })
+            .collect();
+        tape.insert(&Wav::init(8000, samples));
+
+        scheduler.run_until(1000);
+        assert_eq!(0x81, aci.read(0x81));
+        // One event per edge, only the next one is pending
+        assert_eq!(Some(1000 + 511), scheduler.next_event());
+        // Half a period later the level dropped
+        scheduler.run_until(1000 + 600);
+        assert_eq!(0x80, aci.read(0x81));
+        scheduler.run_until(1000 + 1100);
+        assert_eq!(0x81, aci.read(0x81));
+
+        // The last edge ends playback, rewinding starts over
+        scheduler.run_until(1_000_000);
+        assert_eq!(None, scheduler.next_event());
+        tape.rewind();
+        assert_eq!(0x81, aci.read(0x81));
+        assert_eq!(1, scheduler.pending());
+        tape.rewind();
+        assert_eq!(0, scheduler.pending());
+    }
+
+    #[test]
+    fn recording_has_tone_timing() {
+        let tape = Tape::init(1_000_000.0);
+        // 1 kHz: one edge every 500 cycles at 1 MHz
+        let scheduler = Scheduler::init();
+        tape.attach_scheduler(scheduler.clone());
+        tape.start_recording();
+        for i in 0..10 {
+            scheduler.run_until(i * 500);
+            tape.toggle_output();
+        }
+        let wav = tape.recording(10_000);
+        assert_eq!(10_000, wav.sample_rate);
+        assert_eq!(&wav.samples[0..5], &[OUTPUT_AMPLITUDE; 5]);
+        assert_eq!(&wav.samples[5..10], &[-OUTPUT_AMPLITUDE; 5]);
+
+        let replay = Tape::init(1_000_000.0);
+        replay.insert(&wav);
+        assert_eq!(&replay.state.borrow().input[0..3], &[0, 500, 1000]);
+    }
+}
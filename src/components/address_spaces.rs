@@ -1,3 +1,5 @@
+use super::scheduler::Scheduler;
+use super::stats::{AccessKind, AccessStats};
 use super::IoAddressable;
 
 #[derive(Debug)]
@@ -10,25 +12,65 @@ pub struct AddressMap {
 #[derive(Debug)]
 pub struct AddressSpaces {
     address_maps: Vec<AddressMap>,
+    // Off unless asked for, it costs a lookup per access
+    stats: Option<Box<AccessStats>>,
 }
 
 impl AddressSpaces {
     pub fn init(address_maps: Vec<AddressMap>) -> AddressSpaces {
         AddressSpaces {
-            address_maps: address_maps,
+            address_maps,
+            stats: None,
         }
     }
 
+    pub fn enable_stats(&mut self) {
+        if self.stats.is_none() {
+            self.stats = Some(Box::new(AccessStats::init()));
+        }
+    }
+
+    pub fn disable_stats(&mut self) {
+        self.stats = None;
+    }
+
+    pub fn stats(&self) -> Option<&AccessStats> {
+        self.stats.as_deref()
+    }
+
+    pub fn reset_stats(&mut self) {
+        if let Some(stats) = &mut self.stats {
+            stats.reset();
+        }
+    }
+
+    fn record(&mut self, address: u16, kind: AccessKind) {
+        if let Some(stats) = &mut self.stats {
+            stats.record(address, kind);
+        }
+    }
+
+    // Opcode fetch, counted as an execute rather than a read
+    pub fn fetch(&mut self, address: u16) -> u8 {
+        self.record(address, AccessKind::Execute);
+        self.read_component(address)
+    }
+
     fn _find_instance_with_address(&mut self, address: u16) -> Option<&mut AddressMap> {
         let result = self
             .address_maps
             .iter_mut()
             .find(|item| address >= item.addr[0] && address <= item.addr[1]);
 
-        return result;
+        result
     }
 
     pub fn read(&mut self, address: u16) -> u8 {
+        self.record(address, AccessKind::Read);
+        self.read_component(address)
+    }
+
+    fn read_component(&mut self, address: u16) -> u8 {
         let addr_mapping = self._find_instance_with_address(address);
 
         match addr_mapping {
@@ -36,18 +78,54 @@ impl AddressSpaces {
             Some(addr_mapping) => {
                 let res = &mut addr_mapping.component;
                 let relative_addr = address - addr_mapping.addr[0];
-                return res.read(relative_addr as usize);
+                res.read(relative_addr as usize)
             }
         }
     }
 
-    pub fn write(&mut self, address: u16, value: u8) -> () {
+    // Side effect free read for debuggers, unmapped addresses read as 0
+    pub fn peek(&self, address: u16) -> u8 {
+        self.address_maps
+            .iter()
+            .find(|item| address >= item.addr[0] && address <= item.addr[1])
+            .map_or(0, |item| {
+                item.component.peek((address - item.addr[0]) as usize)
+            })
+    }
+
+    // Writes past ROM protection and device side effects
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if let Some(item) = self._find_instance_with_address(address) {
+            let relative_addr = address - item.addr[0];
+            item.component.poke(relative_addr as usize, value);
+        }
+    }
+
+    pub fn sync(&mut self, cycles: usize) {
+        for addr_mapping in self.address_maps.iter_mut() {
+            addr_mapping.component.sync(cycles);
+        }
+    }
+
+    pub fn attach_scheduler(&mut self, scheduler: &Scheduler) {
+        for addr_mapping in self.address_maps.iter_mut() {
+            addr_mapping.component.attach_scheduler(scheduler);
+        }
+    }
+
+    // Wired-OR of every component's IRQ output
+    pub fn irq(&self) -> bool {
+        self.address_maps
+            .iter()
+            .any(|addr_mapping| addr_mapping.component.irq())
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.record(address, AccessKind::Write);
         let addr_mapping = self._find_instance_with_address(address);
 
         match addr_mapping {
-            None => {
-                0;
-            }
+            None => {}
             Some(addr_mapping) => {
                 let res = &mut addr_mapping.component;
                 res.write((address - addr_mapping.addr[0]) as usize, value);
@@ -58,6 +136,8 @@ impl AddressSpaces {
 
 #[cfg(test)]
 mod tests {
+    use super::super::ram::Ram;
+    use super::super::rom::Rom;
     use super::*;
 
     #[derive(Debug)]
@@ -67,8 +147,8 @@ mod tests {
         fn read(&mut self, _address: usize) -> u8 {
             b'a'
         }
-        fn write(&mut self, _address: usize, _value: u8) -> () {}
-        fn flash(&mut self, _data: &Vec<u8>) -> () {}
+        fn write(&mut self, _address: usize, _value: u8) {}
+        fn flash(&mut self, _data: &[u8]) {}
     }
 
     #[test]
@@ -85,4 +165,54 @@ mod tests {
         assert_eq!(0x00, result.read(5));
     }
 
+    #[test]
+    fn peek_and_poke() {
+        let the_mapping = vec![
+            AddressMap {
+                addr: [0x0000, 0x00ff],
+                component: Box::new(Ram::init_with_size(0x100)),
+                name: String::from("RAM"),
+            },
+            AddressMap {
+                addr: [0xff00, 0xffff],
+                component: Box::new(Rom::init_with_size(0x100)),
+                name: String::from("ROM"),
+            },
+        ];
+        let mut bus = AddressSpaces::init(the_mapping);
+
+        bus.write(0xff10, 0x42);
+        assert_eq!(0x00, bus.peek(0xff10));
+        bus.poke(0xff10, 0x42);
+        bus.poke(0x0010, 0x24);
+        assert_eq!(0x42, bus.peek(0xff10));
+        assert_eq!(0x24, bus.peek(0x0010));
+        assert_eq!(0x00, bus.peek(0x8000));
+    }
+
+    #[test]
+    fn collects_stats_when_enabled() {
+        let mut bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0x0000, 0x00ff],
+            component: Box::new(Ram::init_with_size(0x100)),
+            name: String::from("RAM"),
+        }]);
+        bus.read(0x10);
+        assert!(bus.stats().is_none());
+
+        bus.enable_stats();
+        bus.fetch(0x10);
+        bus.read(0x11);
+        bus.write(0x12, 1);
+        bus.peek(0x13);
+        let stats = bus.stats().unwrap();
+        assert_eq!(1, stats.get(0x10).executes);
+        assert_eq!(0, stats.get(0x10).reads);
+        assert_eq!(1, stats.get(0x11).reads);
+        assert_eq!(1, stats.get(0x12).writes);
+        assert_eq!(3, stats.total(AccessKind::Any));
+
+        bus.reset_stats();
+        assert_eq!(0, bus.stats().unwrap().total(AccessKind::Any));
+    }
 }
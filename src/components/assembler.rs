@@ -0,0 +1,776 @@
+use super::address_spaces::AddressSpaces;
+use super::disasm::{self, Mode};
+use super::mc6502::CpuVariant;
+use super::symbols::Symbols;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Two pass 6502 assembler built on the disassembler's opcode table, so every
+// mode exec_op knows is available, undocumented opcodes included (`LAX $10`
+// or `*LAX $10`). Source lines look like
+//
+//   KBD = $D010             constant
+//   loop: lda KBD,X         label, instruction, `; comment`
+//         .org $0300        .byte, .word and .asc take comma separated lists
+//
+// Expressions are numbers ($hex, %binary, decimal, 'c'), symbols and `*` for
+// the current address, combined with + - * / and the `<` / `>` low and high
+// byte operators. Operands that fit in a byte pick zero page modes unless
+// they refer to a label defined further down.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    // Code and data per `.org`, in source order
+    pub segments: Vec<(u16, Vec<u8>)>,
+    pub symbols: Symbols,
+}
+
+impl Assembly {
+    // Writes every segment with poke, so ROM areas can be patched too
+    pub fn load(&self, bus: &mut AddressSpaces) {
+        for (addr, bytes) in &self.segments {
+            for (i, byte) in bytes.iter().enumerate() {
+                bus.poke(addr.wrapping_add(i as u16), *byte);
+            }
+        }
+    }
+
+    // All bytes back to back, for sources with a single `.org`
+    pub fn bytes(&self) -> Vec<u8> {
+        self.segments
+            .iter()
+            .flat_map(|(_, bytes)| bytes.iter().copied())
+            .collect()
+    }
+}
+
+enum Operand<'a> {
+    None,
+    Accumulator,
+    Immediate(&'a str),
+    Plain(&'a str),
+    IndexX(&'a str),
+    IndexY(&'a str),
+    Indirect(&'a str),
+    IndirectX(&'a str),
+    IndirectY(&'a str),
+    // BBRn/BBSn zp,target
+    Pair(&'a str, &'a str),
+}
+
+fn strip_suffix_nocase<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let at = text.len().checked_sub(suffix.len())?;
+    match text.get(at..) {
+        Some(tail) if tail.eq_ignore_ascii_case(suffix) => Some(text[..at].trim()),
+        _ => None,
+    }
+}
+
+fn parse_operand(text: &str) -> Operand<'_> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Operand::None;
+    }
+    if text.eq_ignore_ascii_case("A") {
+        return Operand::Accumulator;
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Operand::Immediate(value.trim());
+    }
+    if let Some(inner) = text.strip_prefix('(') {
+        if let Some(inner) = strip_suffix_nocase(inner, "),Y") {
+            return Operand::IndirectY(inner);
+        }
+        if let Some(inner) = strip_suffix_nocase(inner, ",X)") {
+            return Operand::IndirectX(inner);
+        }
+        if let Some(inner) = inner.strip_suffix(')') {
+            return Operand::Indirect(inner.trim());
+        }
+    }
+    if let Some(value) = strip_suffix_nocase(text, ",X") {
+        return Operand::IndexX(value);
+    }
+    if let Some(value) = strip_suffix_nocase(text, ",Y") {
+        return Operand::IndexY(value);
+    }
+    match text.split_once(',') {
+        Some((zp, target)) => Operand::Pair(zp.trim(), target.trim()),
+        None => Operand::Plain(text),
+    }
+}
+
+// Splits on commas outside quotes
+fn split_list(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, ch) in text.char_indices() {
+        match (quote, ch) {
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(q), _) if q == ch => quote = None,
+            (None, ',') => {
+                items.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(text[start..].trim());
+    items
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, ch) in line.char_indices() {
+        match (quote, ch) {
+            (None, ';') => return &line[..i],
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(q), _) if q == ch => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn overflow() -> String {
+    String::from("expression overflows")
+}
+
+struct Expr<'a> {
+    text: &'a [u8],
+    at: usize,
+    symbols: &'a HashMap<String, i64>,
+    pc: i64,
+    // Set when a symbol isn't defined (yet)
+    unknown: bool,
+}
+
+impl<'a> Expr<'a> {
+    fn skip_spaces(&mut self) {
+        while self.text.get(self.at) == Some(&b' ') {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.text.get(self.at).copied()
+    }
+
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            self.at += 1;
+            let rhs = self.product()?;
+            value = if op == b'+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            }
+            .ok_or_else(overflow)?;
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        while let Some(op @ (b'*' | b'/')) = self.peek() {
+            self.at += 1;
+            let rhs = self.unary()?;
+            value = match op {
+                b'*' => value.checked_mul(rhs).ok_or_else(overflow)?,
+                _ if rhs == 0 && !self.unknown => return Err(String::from("division by zero")),
+                _ if rhs == 0 => 0,
+                _ => value.checked_div(rhs).ok_or_else(overflow)?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some(b'<') => {
+                self.at += 1;
+                Ok(self.unary()? & 0xFF)
+            }
+            Some(b'>') => {
+                self.at += 1;
+                Ok((self.unary()? >> 8) & 0xFF)
+            }
+            Some(b'-') => {
+                self.at += 1;
+                self.unary()?.checked_neg().ok_or_else(overflow)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn take_while<F: Fn(u8) -> bool>(&mut self, accept: F) -> &'a str {
+        let start = self.at;
+        while self.text.get(self.at).is_some_and(|c| accept(*c)) {
+            self.at += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.at]).unwrap_or("")
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let radix = match self.peek() {
+            Some(b'*') => {
+                self.at += 1;
+                return Ok(self.pc);
+            }
+            Some(b'\'') => {
+                let ch = *self
+                    .text
+                    .get(self.at + 1)
+                    .ok_or_else(|| String::from("unterminated character"))?;
+                if !ch.is_ascii() {
+                    return Err(String::from("character literals must be ASCII"));
+                }
+                self.at += 2;
+                if self.text.get(self.at) == Some(&b'\'') {
+                    self.at += 1;
+                }
+                return Ok(ch as i64);
+            }
+            Some(b'$') => 16,
+            Some(b'%') => 2,
+            Some(c) if c.is_ascii_digit() => 10,
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == b'_');
+                return match self.symbols.get(name) {
+                    Some(value) => Ok(*value),
+                    None => {
+                        self.unknown = true;
+                        Ok(0)
+                    }
+                };
+            }
+            _ => return Err(String::from("expected a value")),
+        };
+        if radix != 10 {
+            self.at += 1;
+        }
+        let digits = self.take_while(|c| c.is_ascii_alphanumeric());
+        i64::from_str_radix(digits, radix).map_err(|_| format!("bad number {}", digits))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Assembler {
+    variant: CpuVariant,
+    origin: u16,
+    predefined: Symbols,
+    opcodes: HashMap<(String, Mode), u8>,
+}
+
+#[derive(Default)]
+struct Pass {
+    symbols: HashMap<String, i64>,
+    // Addressing mode chosen per line in the first pass
+    modes: HashMap<usize, Mode>,
+    segments: Vec<(u16, Vec<u8>)>,
+    pc: i64,
+    last: bool,
+}
+
+impl Pass {
+    fn eval(&self, text: &str) -> Result<Option<i64>, String> {
+        let mut expr = Expr {
+            text: text.trim().as_bytes(),
+            at: 0,
+            symbols: &self.symbols,
+            pc: self.pc,
+            unknown: false,
+        };
+        let value = expr.sum()?;
+        if expr.peek().is_some() {
+            return Err(format!("can't parse expression {}", text.trim()));
+        }
+        match expr.unknown {
+            true if self.last => Err(format!("undefined symbol in {}", text.trim())),
+            true => Ok(None),
+            false => Ok(Some(value)),
+        }
+    }
+
+    // Value in the last pass, anything in the first
+    fn value(&self, text: &str) -> Result<i64, String> {
+        Ok(self.eval(text)?.unwrap_or(0))
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.pc + bytes.len() as i64 > 0x10000 {
+            return Err(String::from("code runs past $FFFF"));
+        }
+        if self.last {
+            match self.segments.last_mut() {
+                Some((start, data)) if *start as i64 + data.len() as i64 == self.pc => {
+                    data.extend_from_slice(bytes)
+                }
+                _ => self.segments.push((self.pc as u16, bytes.to_vec())),
+            }
+        }
+        self.pc += bytes.len() as i64;
+        Ok(())
+    }
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("${:X} doesn't fit in a byte", value)),
+    }
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    match value {
+        -32768..=0xFFFF => Ok(value as u16),
+        _ => Err(format!("${:X} doesn't fit in a word", value)),
+    }
+}
+
+impl Assembler {
+    pub fn init(variant: CpuVariant) -> Assembler {
+        let mut opcodes = HashMap::new();
+        // Documented opcodes win over undocumented duplicates like *SBC #
+        let mut ops: Vec<disasm::Opcode> = disasm::opcodes(variant).collect();
+        ops.sort_by_key(|op| op.undocumented());
+        for op in ops {
+            opcodes
+                .entry((op.name().to_string(), op.mode))
+                .or_insert(op.opcode);
+        }
+        Assembler {
+            variant,
+            origin: 0,
+            predefined: Symbols::new(),
+            opcodes,
+        }
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    // Address used until the first `.org`
+    pub fn origin(mut self, origin: u16) -> Assembler {
+        self.origin = origin;
+        self
+    }
+
+    // Symbols the source can use without defining them, e.g. Symbols::woz()
+    pub fn symbols(mut self, symbols: &Symbols) -> Assembler {
+        self.predefined.extend(symbols);
+        self
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Assembly, AsmError> {
+        let mut pass = Pass::default();
+        for (addr, name) in self.predefined.iter() {
+            pass.symbols.insert(name.to_string(), addr as i64);
+        }
+        let predefined = pass.symbols.clone();
+
+        self.run_pass(source, &mut pass)?;
+        let labels = std::mem::take(&mut pass.symbols);
+        let mut last = Pass {
+            symbols: labels,
+            modes: std::mem::take(&mut pass.modes),
+            last: true,
+            ..Pass::default()
+        };
+        self.run_pass(source, &mut last)?;
+
+        let mut symbols = Symbols::new();
+        for (name, value) in &last.symbols {
+            if !predefined.contains_key(name) && (0..=0xFFFF).contains(value) {
+                symbols.insert(name, *value as u16);
+            }
+        }
+        Ok(Assembly {
+            segments: last.segments,
+            symbols,
+        })
+    }
+
+    fn run_pass(&self, source: &str, pass: &mut Pass) -> Result<(), AsmError> {
+        pass.pc = self.origin as i64;
+        for (i, line) in source.lines().enumerate() {
+            self.line(i + 1, strip_comment(line).trim(), pass)
+                .map_err(|message| AsmError {
+                    line: i + 1,
+                    message,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn define(&self, pass: &mut Pass, name: &str, value: i64) -> Result<(), String> {
+        if !is_ident(name) {
+            return Err(format!("bad label {}", name));
+        }
+        match pass.symbols.insert(name.to_string(), value) {
+            Some(old) if !pass.last && old != value && !self.predefined_has(name) => {
+                Err(format!("{} defined twice", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn predefined_has(&self, name: &str) -> bool {
+        self.predefined.get(name).is_some()
+    }
+
+    fn line(&self, number: usize, line: &str, pass: &mut Pass) -> Result<(), String> {
+        let mut line = line;
+        if let Some((label, rest)) = line.split_once(':') {
+            if is_ident(label.trim()) {
+                self.define(pass, label.trim(), pass.pc)?;
+                line = rest.trim();
+            }
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some((name, expr)) = line.split_once('=') {
+            if is_ident(name.trim()) {
+                return match pass.eval(expr)? {
+                    Some(value) => self.define(pass, name.trim(), value),
+                    None => Ok(()),
+                };
+            }
+        }
+
+        let (word, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => (line, ""),
+        };
+        if word.starts_with('.') {
+            return self.directive(&word.to_lowercase(), rest, pass);
+        }
+        self.instruction(number, word, rest, pass)
+    }
+
+    fn directive(&self, name: &str, rest: &str, pass: &mut Pass) -> Result<(), String> {
+        match name {
+            ".org" => {
+                let origin = pass
+                    .eval(rest)?
+                    .ok_or_else(|| String::from(".org needs a value known up front"))?;
+                pass.pc = word(origin)? as i64;
+            }
+            ".byte" | ".asc" => {
+                for item in split_list(rest) {
+                    match item.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                        Some(text) => pass.emit(text.as_bytes())?,
+                        None => {
+                            let value = byte(pass.value(item)?)?;
+                            pass.emit(&[value])?
+                        }
+                    }
+                }
+            }
+            ".word" => {
+                for item in split_list(rest) {
+                    let value = word(pass.value(item)?)?;
+                    pass.emit(&value.to_le_bytes())?;
+                }
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+        Ok(())
+    }
+
+    fn opcode(&self, name: &str, mode: Mode) -> Option<u8> {
+        self.opcodes.get(&(name.to_string(), mode)).copied()
+    }
+
+    fn has(&self, name: &str, mode: Mode) -> bool {
+        self.opcode(name, mode).is_some()
+    }
+
+    // Zero page when the value is known to fit, absolute otherwise
+    fn pick(&self, name: &str, value: Option<i64>, zp: Mode, abs: Mode) -> Mode {
+        let fits = matches!(value, Some(0..=0xFF));
+        if self.has(name, zp) && (fits || !self.has(name, abs)) {
+            zp
+        } else {
+            abs
+        }
+    }
+
+    fn instruction(
+        &self,
+        number: usize,
+        mnemonic: &str,
+        rest: &str,
+        pass: &mut Pass,
+    ) -> Result<(), String> {
+        let name = mnemonic.trim_start_matches('*').to_uppercase();
+        if !self.opcodes.keys().any(|(op, _)| *op == name) {
+            return Err(format!("unknown instruction {}", mnemonic));
+        }
+        let operand = parse_operand(rest);
+
+        let mode = match pass.modes.get(&number) {
+            Some(mode) => *mode,
+            None => {
+                let mode = match operand {
+                    Operand::None if self.has(&name, Mode::Imp) => Mode::Imp,
+                    Operand::None | Operand::Accumulator => Mode::Acc,
+                    Operand::Immediate(_) => Mode::Imm,
+                    Operand::Plain(_) if self.has(&name, Mode::Rel) => Mode::Rel,
+                    Operand::Plain(value) => {
+                        self.pick(&name, pass.eval(value)?, Mode::Zp, Mode::Abs)
+                    }
+                    Operand::IndexX(value) => {
+                        self.pick(&name, pass.eval(value)?, Mode::Zpx, Mode::Abx)
+                    }
+                    Operand::IndexY(value) => {
+                        self.pick(&name, pass.eval(value)?, Mode::Zpy, Mode::Aby)
+                    }
+                    Operand::Indirect(_) if self.has(&name, Mode::Ind) => Mode::Ind,
+                    Operand::Indirect(_) => Mode::Izp,
+                    Operand::IndirectX(_) if self.has(&name, Mode::Iax) => Mode::Iax,
+                    Operand::IndirectX(_) => Mode::Izx,
+                    Operand::IndirectY(_) => Mode::Izy,
+                    Operand::Pair(_, _) => Mode::Zpr,
+                };
+                pass.modes.insert(number, mode);
+                mode
+            }
+        };
+        let opcode = self
+            .opcode(&name, mode)
+            .ok_or_else(|| format!("{} has no {:?} mode", name, mode))?;
+
+        let bytes = match operand {
+            Operand::None | Operand::Accumulator => vec![opcode],
+            Operand::Pair(zp, target) => {
+                let zp = pass.value(zp)?;
+                let offset = self.branch(pass, target, 3)?;
+                vec![opcode, zero_page(zp)?, offset]
+            }
+            Operand::Plain(target) if mode == Mode::Rel => {
+                vec![opcode, self.branch(pass, target, 2)?]
+            }
+            Operand::Immediate(value) => vec![opcode, byte(pass.value(value)?)?],
+            Operand::Plain(value)
+            | Operand::IndexX(value)
+            | Operand::IndexY(value)
+            | Operand::Indirect(value)
+            | Operand::IndirectX(value)
+            | Operand::IndirectY(value) => {
+                let value = pass.value(value)?;
+                match mode.size() {
+                    2 => vec![opcode, zero_page(value)?],
+                    _ => {
+                        let [lo, hi] = word(value)?.to_le_bytes();
+                        vec![opcode, lo, hi]
+                    }
+                }
+            }
+        };
+        pass.emit(&bytes)
+    }
+
+    fn branch(&self, pass: &Pass, target: &str, size: i64) -> Result<u8, String> {
+        let target = match pass.eval(target)? {
+            Some(target) => target,
+            None => return Ok(0),
+        };
+        let offset = target - (pass.pc + size);
+        if !(-128..=127).contains(&offset) {
+            return Err(format!("branch to ${:04X} is out of range", target));
+        }
+        Ok(offset as u8)
+    }
+}
+
+fn zero_page(value: i64) -> Result<u8, String> {
+    match value {
+        0..=0xFF => Ok(value as u8),
+        _ => Err(format!("${:X} is not a zero page address", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::AddressMap;
+    use super::super::ram;
+    use super::super::test_support::echo_machine;
+    use super::*;
+
+    fn assemble(variant: CpuVariant, source: &str) -> Assembly {
+        Assembler::init(variant)
+            .origin(0x0300)
+            .assemble(source)
+            .unwrap()
+    }
+
+    #[test]
+    fn assembles_modes_and_labels() {
+        let source = "
+            COUNT = 3
+            start:  ldx #COUNT          ; X = 3
+            loop:   lda table-1,x
+                    sta $0400,X
+                    sta (ptr),y
+                    asl
+                    dex
+                    bne loop
+                    jmp (vector)
+            vector: .word start, *
+            ptr = $24
+            table:  .byte 1, <$1234, >$1234, 'A'
+                    .asc \"HI\"
+        ";
+        let assembly = assemble(CpuVariant::Nmos6502, source);
+        assert_eq!(
+            vec![
+                0xA2, 0x03, // LDX #3
+                0xBD, 0x14, 0x03, // LDA table-1,X (absolute, table is a label)
+                0x9D, 0x00, 0x04, // STA $0400,X
+                0x91, 0x24, // STA (ptr),Y
+                0x0A, // ASL
+                0xCA, // DEX
+                0xD0, 0xF4, // BNE loop
+                0x6C, 0x11, 0x03, // JMP (vector)
+                0x00, 0x03, 0x13, 0x03, // .word start, *
+                0x01, 0x34, 0x12, 0x41, // .byte
+                b'H', b'I',
+            ],
+            assembly.bytes()
+        );
+        assert_eq!(Some(0x0302), assembly.symbols.get("loop"));
+        assert_eq!(Some(0x0315), assembly.symbols.get("table"));
+        assert_eq!(Some(0x24), assembly.symbols.get("ptr"));
+    }
+
+    #[test]
+    fn assembles_undocumented_and_cmos_ops() {
+        let nmos = assemble(CpuVariant::Nmos6502, "lax $10\n*slo ($20,x)\nsbc #1\nnop");
+        assert_eq!(vec![0xA7, 0x10, 0x03, 0x20, 0xE9, 0x01, 0xEA], nmos.bytes());
+
+        let cmos = assemble(
+            CpuVariant::Cmos65C02,
+            "skip: bbr3 $20,skip\nlda ($30)\njmp ($1000,x)\ninc a\nstz $10,x",
+        );
+        assert_eq!(
+            vec![0x3F, 0x20, 0xFD, 0xB2, 0x30, 0x7C, 0x00, 0x10, 0x1A, 0x74, 0x10],
+            cmos.bytes()
+        );
+    }
+
+    #[test]
+    fn round_trips_every_opcode() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02] {
+            let assembler = Assembler::init(variant).origin(0x1000);
+            for op in disasm::opcodes(variant) {
+                let code = [op.opcode, 0x42, 0x12];
+                let ins = disasm::disassemble(|a| code[(a - 0x1000) as usize], variant, 0x1000);
+                let source = format!("{} ; {:02X}", ins.text(), op.opcode);
+                let bytes = assembler.assemble(&source).unwrap().bytes();
+                // Duplicates assemble to the canonical opcode for the same mode
+                let again = disasm::decode(variant, bytes[0]);
+                assert_eq!(
+                    (op.name(), op.mode),
+                    (again.name(), again.mode),
+                    "{}",
+                    source
+                );
+                assert_eq!(&code[1..ins.size() as usize], &bytes[1..], "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let assembler = Assembler::init(CpuVariant::Nmos6502);
+        let err = assembler.assemble("nop\nfoo #1").unwrap_err();
+        assert_eq!(2, err.line);
+        let err = assembler.assemble("jmp nowhere").unwrap_err();
+        assert!(err.message.contains("undefined"), "{}", err);
+        let err = assembler
+            .assemble(".org $0300\nbeq far\n.org $0400\nfar: rts")
+            .unwrap_err();
+        assert!(err.message.contains("out of range"), "{}", err);
+        let err = assembler.assemble("lda #$100").unwrap_err();
+        assert!(err.message.contains("byte"), "{}", err);
+        for source in [
+            "lda #$7FFFFFFFFFFFFFFF*2",
+            "lda #$7FFFFFFFFFFFFFFF+1",
+            "lda #-$7FFFFFFFFFFFFFFF-2",
+            "MIN = -$7FFFFFFFFFFFFFFF-1\nlda #-MIN",
+            "MIN = -$7FFFFFFFFFFFFFFF-1\nlda #MIN/-1",
+        ] {
+            let err = assembler.assemble(source).unwrap_err();
+            assert!(err.message.contains("overflows"), "{}: {}", source, err);
+        }
+        let err = assembler.assemble("lda #'é'").unwrap_err();
+        assert!(err.message.contains("ASCII"), "{}", err);
+    }
+
+    #[test]
+    fn loads_through_address_spaces() {
+        let assembly = Assembler::init(CpuVariant::Nmos6502)
+            .symbols(&Symbols::woz())
+            .assemble(".org $0300\nlda #'A'+$80\njsr ECHO\n.org $0400\n.byte $EA")
+            .unwrap();
+        assert_eq!(2, assembly.segments.len());
+        assert_eq!(None, assembly.symbols.get("ECHO"));
+
+        let mut bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0x0000, 0x0fff],
+            component: Box::new(ram::Ram::init_with_size(0x1000)),
+            name: String::from("RAM"),
+        }]);
+        assembly.load(&mut bus);
+        assert_eq!(0xC1, bus.peek(0x0301));
+        assert_eq!(0xFF, bus.peek(0x0304));
+        assert_eq!(0xEA, bus.peek(0x0400));
+    }
+
+    #[test]
+    fn assembles_the_test_monitor() {
+        // Same bytes as the hand assembled fixture the tests used before
+        let program = [
+            0xA9, 0x7F, 0x8D, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x11, 0xD0, 0x8D, 0x13, 0xD0, 0xA9,
+            0xDC, 0x8D, 0x12, 0xD0, 0xAD, 0x11, 0xD0, 0x10, 0xFB, 0xAD, 0x10, 0xD0, 0x2C, 0x12,
+            0xD0, 0x30, 0xFB, 0x8D, 0x12, 0xD0, 0x4C, 0x12, 0xFF,
+        ];
+        let machine = echo_machine();
+        for (i, byte) in program.iter().enumerate() {
+            assert_eq!(
+                *byte,
+                machine.peek(0xFF00 + i as u16),
+                "${:04X}",
+                0xFF00 + i
+            );
+        }
+        assert_eq!(0xFF00, machine.cpu.registers().pc);
+    }
+}
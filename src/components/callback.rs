@@ -0,0 +1,209 @@
+use super::IoAddressable;
+use std::fmt;
+
+// Memory mapped device built from closures, for trying out expansion
+// hardware without writing a component:
+//
+//   let device = CallbackDevice::new("LATCH")
+//       .register(0, "DATA")
+//       .on_read(move |reg| ...)
+//       .on_write(move |reg, value| ...);
+//
+// Closures get the register offset within the mapped range. Reads without a
+// read closure return 0, writes without a write closure are dropped.
+
+type ReadFn = Box<dyn FnMut(usize) -> u8>;
+type WriteFn = Box<dyn FnMut(usize, u8)>;
+type PeekFn = Box<dyn Fn(usize) -> u8>;
+
+pub struct CallbackDevice {
+    name: String,
+    registers: Vec<(usize, String)>,
+    read: Option<ReadFn>,
+    write: Option<WriteFn>,
+    peek: Option<PeekFn>,
+}
+
+impl fmt::Debug for CallbackDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallbackDevice")
+            .field("name", &self.name)
+            .field("registers", &self.registers)
+            .finish()
+    }
+}
+
+impl CallbackDevice {
+    pub fn new(name: &str) -> CallbackDevice {
+        CallbackDevice {
+            name: name.to_string(),
+            registers: Vec::new(),
+            read: None,
+            write: None,
+            peek: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn register(mut self, offset: usize, name: &str) -> CallbackDevice {
+        self.registers.retain(|(reg, _)| *reg != offset);
+        self.registers.push((offset, name.to_string()));
+        self.registers.sort();
+        self
+    }
+
+    pub fn on_read<F: FnMut(usize) -> u8 + 'static>(mut self, read: F) -> CallbackDevice {
+        self.read = Some(Box::new(read));
+        self
+    }
+
+    pub fn on_write<F: FnMut(usize, u8) + 'static>(mut self, write: F) -> CallbackDevice {
+        self.write = Some(Box::new(write));
+        self
+    }
+
+    // Must not have side effects, debuggers and tracers use it to look at
+    // registers without disturbing the device
+    pub fn on_peek<F: Fn(usize) -> u8 + 'static>(mut self, peek: F) -> CallbackDevice {
+        self.peek = Some(Box::new(peek));
+        self
+    }
+
+    pub fn register_name(&self, offset: usize) -> Option<&str> {
+        self.registers
+            .iter()
+            .find(|(reg, _)| *reg == offset)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn registers(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.registers
+            .iter()
+            .map(|(reg, name)| (*reg, name.as_str()))
+    }
+
+    // None when the device has no peek closure
+    pub fn try_peek(&self, offset: usize) -> Option<u8> {
+        self.peek.as_ref().map(|peek| peek(offset))
+    }
+}
+
+impl IoAddressable for CallbackDevice {
+    fn read(&mut self, address: usize) -> u8 {
+        match &mut self.read {
+            Some(read) => read(address),
+            None => 0,
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        if let Some(write) = &mut self.write {
+            write(address, value);
+        }
+    }
+
+    fn flash(&mut self, _data: &[u8]) {}
+
+    fn peek(&self, address: usize) -> u8 {
+        self.try_peek(address).unwrap_or(0)
+    }
+
+    fn register_names(&self) -> Vec<(usize, String)> {
+        self.registers.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::machine::MachineBuilder;
+    use super::super::mc6502::Registers;
+    use super::super::ram::Ram;
+    use super::super::tracer::Tracer;
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn initial_state() {
+        let mut device = CallbackDevice::new("EMPTY");
+        assert_eq!("EMPTY", device.name());
+        assert_eq!(0, device.read(0));
+        device.write(0, 1);
+        assert_eq!(None, device.try_peek(0));
+        assert_eq!(None, device.register_name(0));
+    }
+
+    #[test]
+    fn maps_closures_on_the_bus() {
+        // Data latch plus a status register counting its own reads
+        let latch = Rc::new(Cell::new(0u8));
+        let reads = Rc::new(Cell::new(0u8));
+        let (latch_r, latch_w, latch_p) = (latch.clone(), latch.clone(), latch.clone());
+        let (reads_r, reads_p) = (reads.clone(), reads.clone());
+
+        let device = CallbackDevice::new("LATCH")
+            .register(1, "STATUS")
+            .register(0, "DATA")
+            .on_read(move |reg| match reg {
+                0 => latch_r.get(),
+                _ => {
+                    reads_r.set(reads_r.get() + 1);
+                    reads_r.get()
+                }
+            })
+            .on_write(move |reg, value| {
+                if reg == 0 {
+                    latch_w.set(value)
+                }
+            })
+            .on_peek(move |reg| match reg {
+                0 => latch_p.get(),
+                _ => reads_p.get(),
+            });
+        assert_eq!(Some("STATUS"), device.register_name(1));
+        assert_eq!(
+            vec![(0, "DATA"), (1, "STATUS")],
+            device.registers().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(0), device.try_peek(1));
+
+        let mut bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0xC100, 0xC101],
+            component: Box::new(device),
+            name: String::from("LATCH"),
+        }]);
+        bus.write(0xC100, 0x5A);
+        assert_eq!(0x5A, bus.read(0xC100));
+        assert_eq!(1, bus.read(0xC101));
+        assert_eq!(2, bus.read(0xC101));
+        assert_eq!(2, reads.get());
+    }
+
+    #[test]
+    fn names_registers_in_traces() {
+        let device = CallbackDevice::new("LATCH").register(1, "STATUS");
+        let mut machine = MachineBuilder::new()
+            .map(
+                "RAM",
+                [0x0000, 0x0fff],
+                Box::new(Ram::init_with_size(0x1000)),
+            )
+            .map("LATCH", [0xC100, 0xC101], Box::new(device))
+            .build()
+            .unwrap();
+        assert_eq!(Some(0xC101), machine.symbols.get("STATUS"));
+
+        // LDA STATUS
+        machine.load(0x0300, &[0xAD, 0x01, 0xC1]);
+        machine.cpu.set_registers(Registers {
+            pc: 0x0300,
+            ..machine.cpu.registers()
+        });
+        let line = Tracer::init(machine.symbols.clone()).line(&machine.cpu);
+        assert!(line.contains("LDA STATUS"), "{}", line);
+    }
+}
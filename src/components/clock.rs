@@ -1,98 +1,357 @@
-#[cfg(test)]
-use fake_clock::FakeClock as Instant;
-#[cfg(not(test))]
-use std::time::Instant;
-
+use super::aci::APPLE1_CLOCK_HZ;
+use super::time::{check_speed, SpeedError, SystemTime, TimeSource};
 use super::Clockable;
+use std::time::Duration;
+
+pub const APPLE1_MHZ: f64 = APPLE1_CLOCK_HZ / 1_000_000.0;
+
+pub const MIN_MULTIPLIER: f64 = 0.1;
+pub const MAX_MULTIPLIER: f64 = 100.0;
+
+// Furthest the emulation may fall behind the wall clock before the lost
+// time is written off instead of being caught up at full speed
+pub const MAX_CATCH_UP: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
-pub struct Clock {
-    mhz: usize,
-    step_chunk: usize,
-    prev_cycle_time: Instant,
-    nano_per_cycle: u128,
-    last_cycle_count: u128,
+pub struct Clock<T: TimeSource = SystemTime> {
+    mhz: f64,
+    multiplier: f64,
+    turbo: bool,
+    paused: bool,
+    // Cycles run between two deadline checks
+    batch_cycles: usize,
+    nano_per_cycle: f64,
+    // Deadlines are computed from here, so rounding never accumulates
+    epoch: Duration,
+    epoch_cycles: usize,
+    // Effective speed is measured from here, restarted on any speed change
+    measure_start: Duration,
+    measure_cycles: usize,
     cpu: Box<dyn Clockable>,
+    time: T,
 }
 
 impl Clock {
-    pub fn init(cpu: Box<dyn Clockable>, mhz: usize, step_chunk: usize) -> Clock {
-        Clock {
-            cpu: cpu,
-            mhz: mhz,
-            step_chunk: step_chunk,
-            last_cycle_count: 1,
-            nano_per_cycle: 1000 / mhz as u128,
-            prev_cycle_time: Instant::now(),
+    // `mhz` has to be positive and finite
+    pub fn init(
+        cpu: Box<dyn Clockable>,
+        mhz: f64,
+        batch_cycles: usize,
+    ) -> Result<Clock, SpeedError> {
+        Clock::with_time_source(cpu, mhz, batch_cycles, SystemTime::init())
+    }
+}
+
+impl<T: TimeSource> Clock<T> {
+    pub fn with_time_source(
+        cpu: Box<dyn Clockable>,
+        mhz: f64,
+        batch_cycles: usize,
+        time: T,
+    ) -> Result<Clock<T>, SpeedError> {
+        let mhz = check_speed(mhz)?;
+        let cycles = cpu.get_cycles();
+        let now = time.now();
+        let mut clock = Clock {
+            cpu,
+            mhz,
+            multiplier: 1.0,
+            turbo: false,
+            paused: false,
+            batch_cycles,
+            nano_per_cycle: 0.0,
+            epoch: now,
+            epoch_cycles: cycles,
+            measure_start: now,
+            measure_cycles: cycles,
+            time,
+        };
+        clock.update_speed();
+        Ok(clock)
+    }
+
+    fn update_speed(&mut self) {
+        // 1 MHz is 1000 ns per cycle
+        self.nano_per_cycle = 1000.0 / (self.mhz * self.multiplier);
+        self.reset_epoch();
+        self.reset_measurement();
+    }
+
+    fn reset_epoch(&mut self) {
+        self.epoch = self.time.now();
+        self.epoch_cycles = self.cpu.get_cycles();
+    }
+
+    pub fn reset_measurement(&mut self) {
+        self.measure_start = self.time.now();
+        self.measure_cycles = self.cpu.get_cycles();
+    }
+
+    pub fn time_source(&self) -> &T {
+        &self.time
+    }
+
+    pub fn mhz(&self) -> f64 {
+        self.mhz
+    }
+
+    // Leaves the speed alone unless `mhz` is positive and finite
+    pub fn set_mhz(&mut self, mhz: f64) -> Result<(), SpeedError> {
+        self.mhz = check_speed(mhz)?;
+        self.update_speed();
+        Ok(())
+    }
+
+    pub fn multiplier(&self) -> f64 {
+        self.multiplier
+    }
+
+    // Clamped to 0.1x - 100x, leaves the speed alone unless `multiplier`
+    // is finite
+    pub fn set_multiplier(&mut self, multiplier: f64) -> Result<(), SpeedError> {
+        if !multiplier.is_finite() {
+            return Err(SpeedError { value: multiplier });
         }
+        self.multiplier = multiplier.clamp(MIN_MULTIPLIER, MAX_MULTIPLIER);
+        self.update_speed();
+        Ok(())
+    }
+
+    // Target speed, ignoring turbo
+    pub fn target_mhz(&self) -> f64 {
+        self.mhz * self.multiplier
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    // Unthrottled: steps as fast as the host allows
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.reset_epoch();
+        self.reset_measurement();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 
-    pub fn cycle(&mut self) -> () {
-        for _a in 0..self.step_chunk {
-            let nano_delta = self.prev_cycle_time.elapsed().as_nanos();
-            if nano_delta > self.nano_per_cycle * self.last_cycle_count {
-                self.prev_cycle_time = Instant::now();
-                self.last_cycle_count = self.cpu.step() as u128;
-            }
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.reset_epoch();
+            self.reset_measurement();
+        }
+    }
+
+    // Measured speed since the last speed change or resume
+    pub fn effective_mhz(&self) -> f64 {
+        let nanos = self
+            .time
+            .now()
+            .saturating_sub(self.measure_start)
+            .as_nanos();
+        if nanos == 0 {
+            return 0.0;
+        }
+        let cycles = self.cpu.get_cycles().wrapping_sub(self.measure_cycles);
+        cycles as f64 * 1000.0 / nanos as f64
+    }
+
+    // Wall clock time the emulation should have reached by now
+    fn target(&self) -> Duration {
+        let cycles = self.cpu.get_cycles().wrapping_sub(self.epoch_cycles);
+        Duration::from_nanos((cycles as f64 * self.nano_per_cycle) as u64)
+    }
+
+    // Runs one batch of cycles, then sleeps until the batch is due
+    pub fn cycle(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        let until = self.cpu.get_cycles().wrapping_add(self.batch_cycles);
+        while self.cpu.get_cycles() < until {
+            self.cpu.step();
+        }
+        if self.turbo {
+            return;
+        }
+
+        let now = self.time.now();
+        if now < self.epoch {
+            // The time source went backwards, pace from here
+            self.reset_epoch();
+            self.reset_measurement();
+            return;
+        }
+        let target = self.target();
+        let elapsed = now - self.epoch;
+        if target > elapsed {
+            self.time.sleep(target - elapsed);
+        } else if elapsed - target > MAX_CATCH_UP {
+            self.reset_epoch();
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::time::ManualTime;
     use super::*;
 
-    fn fake_sleep(time: u64) {
-        use fake_clock::FakeClock;
-        FakeClock::advance_time(time);
+    fn millis(time: &ManualTime) -> u128 {
+        time.now().as_millis()
+    }
+
+    #[derive(Debug)]
+    struct CountingClockable {
+        cycles: usize,
+    }
+
+    impl Clockable for CountingClockable {
+        fn get_cycles(&self) -> usize {
+            self.cycles
+        }
+
+        fn step(&mut self) -> usize {
+            self.cycles += 2;
+            2
+        }
+    }
+
+    fn build_clock(mhz: f64, batch_cycles: usize) -> (Clock<ManualTime>, ManualTime) {
+        let time = ManualTime::init();
+        let clock = Clock::with_time_source(
+            Box::new(CountingClockable { cycles: 0 }),
+            mhz,
+            batch_cycles,
+            time.clone(),
+        )
+        .unwrap();
+        (clock, time)
     }
 
     #[test]
     fn initial_state() {
-        #[derive(Debug)]
-        struct FakeClockable {
-            cycles: usize,
-        };
+        let (clock, _) = build_clock(1.0, 1000);
+        assert_eq!(0, clock.cpu.get_cycles(), "get_cycles");
+        assert_eq!(1000.0, clock.nano_per_cycle, "nano_per_cycle");
+        assert_eq!(Duration::from_millis(0), clock.target(), "target");
+    }
 
-        impl Clockable for FakeClockable {
-            fn get_cycles(&self) -> usize {
-                return self.cycles;
-            }
+    #[test]
+    fn sleeps_until_batch_deadline() {
+        // 1000 cycles at 1 MHz take 1 ms
+        let (mut clock, time) = build_clock(1.0, 1000);
 
-            fn step(&mut self) -> usize {
-                self.cycles = self.cycles.wrapping_add(2);
-                return 5;
-            }
+        for _ in 0..10 {
+            clock.cycle();
         }
+        assert_eq!(10_000, clock.cpu.get_cycles());
+        assert_eq!(10, millis(&time));
+    }
 
-        let fake_clockable = FakeClockable { cycles: 0 };
+    #[test]
+    fn catches_up_after_short_stall() {
+        let (mut clock, time) = build_clock(1.0, 1000);
 
-        let mut clock = Clock::init(Box::new(fake_clockable), 1, 1);
-        assert_eq!(0, clock.cpu.get_cycles(), "get_cycles");
-        assert_eq!(1, clock.last_cycle_count, "last_cycle_count");
-        assert_eq!(
-            0,
-            clock.prev_cycle_time.elapsed().as_nanos(),
-            "prev_cycle_time"
-        );
+        // 5 ms behind: the next batches run without sleeping
+        time.advance(Duration::from_millis(5));
+        for _ in 0..5 {
+            clock.cycle();
+        }
+        assert_eq!(5, millis(&time));
+
+        clock.cycle();
+        assert_eq!(6, millis(&time));
+    }
+
+    #[test]
+    fn writes_off_long_stall() {
+        let (mut clock, time) = build_clock(1.0, 1000);
 
-        fake_sleep(1);
+        time.advance(Duration::from_millis(1000));
+        clock.cycle();
+        assert_eq!(1000, millis(&time));
 
-        assert_eq!(
-            1000000,
-            clock.prev_cycle_time.elapsed().as_nanos(),
-            "prev_cycle_time"
-        );
+        // Paced from the stall on, not rushing to make up 999 ms
+        clock.cycle();
+        assert_eq!(1001, millis(&time));
+    }
 
+    #[test]
+    fn survives_time_going_backwards() {
+        let (mut clock, time) = build_clock(1.0, 1000);
+        time.set(Duration::from_millis(1000));
         clock.cycle();
+        assert_eq!(1000, millis(&time));
+        clock.reset_measurement();
 
-        assert_eq!(2, clock.cpu.get_cycles(), "get_cycles");
-        assert_eq!(5, clock.last_cycle_count, "last_cycle_count");
-        assert_eq!(
-            0,
-            clock.prev_cycle_time.elapsed().as_nanos(),
-            "prev_cycle_time"
-        );
+        time.set(Duration::from_millis(10));
+        assert_eq!(0.0, clock.effective_mhz());
+        clock.cycle();
+        assert_eq!(10, millis(&time));
+        clock.cycle();
+        assert_eq!(11, millis(&time));
     }
 
+    #[test]
+    fn speed_settings() {
+        let (mut clock, _) = build_clock(APPLE1_MHZ, 1);
+        assert!((clock.nano_per_cycle - 977.78).abs() < 0.01);
+
+        for mhz in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(clock.set_mhz(mhz).is_err());
+            let cpu = Box::new(CountingClockable { cycles: 0 });
+            assert!(Clock::init(cpu, mhz, 1).is_err());
+        }
+        assert_eq!(APPLE1_MHZ, clock.mhz());
+        clock.set_mhz(2.0).unwrap();
+        assert_eq!(500.0, clock.nano_per_cycle);
+        clock.set_mhz(APPLE1_MHZ).unwrap();
+
+        clock.set_multiplier(2.0).unwrap();
+        assert!((clock.target_mhz() - 2.045454).abs() < 0.0001);
+        for multiplier in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(clock.set_multiplier(multiplier).is_err());
+        }
+        assert_eq!(2.0, clock.multiplier());
+        assert!(clock.nano_per_cycle.is_finite());
+        clock.set_multiplier(1000.0).unwrap();
+        assert_eq!(MAX_MULTIPLIER, clock.multiplier());
+        clock.set_multiplier(0.0).unwrap();
+        assert_eq!(MIN_MULTIPLIER, clock.multiplier());
+    }
+
+    #[test]
+    fn pause_turbo_and_effective_speed() {
+        let (mut clock, time) = build_clock(0.5, 2000);
+
+        clock.pause();
+        time.advance(Duration::from_millis(1));
+        clock.cycle();
+        assert_eq!(0, clock.cpu.get_cycles());
+
+        clock.resume();
+        clock.set_turbo(true);
+        clock.cycle();
+        assert_eq!(2000, clock.cpu.get_cycles());
+        assert_eq!(1, millis(&time));
+
+        // 2000 cycles in 1 ms
+        time.advance(Duration::from_millis(1));
+        assert!((clock.effective_mhz() - 2.0).abs() < 0.001);
+
+        // Throttled again, 2000 cycles at 0.5 MHz take 4 ms
+        clock.set_turbo(false);
+        clock.cycle();
+        assert_eq!(4000, clock.cpu.get_cycles());
+        assert_eq!(6, millis(&time));
+    }
 }
@@ -0,0 +1,521 @@
+use super::aci::{Aci, Tape};
+use super::address_spaces::AddressMap;
+use super::clock::APPLE1_MHZ;
+use super::machine::PIA_ADDR;
+use super::mc6502::CpuVariant;
+use super::ram::Ram;
+use super::rom::Rom;
+use super::symbols::Symbols;
+use super::IoAddressable;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Machine description in TOML, for memory maps the built-in profiles don't
+// cover. File paths are relative to the config file.
+//
+//   name = "Replica-1"
+//   cpu = "65c02"                 # or "6502", the default
+//   clock_mhz = 1.0227            # the default
+//   symbols = ["krusader.sym"]    # on top of the Woz Monitor ones
+//
+//   [[ram]]
+//   start = 0x0000
+//   size = 0x8000                 # or end = 0x7FFF
+//
+//   [[rom]]
+//   file = "a1rom.bin"
+//   start = 0xE000                # end defaults to the image size
+//   writable = false              # true loads the image into RAM
+//
+//   [pia]
+//   start = 0xD010                # keyboard/display PIA, the default
+//
+//   [[card]]
+//   type = "aci"                  # cassette interface, $C000-$C1FF
+//   rom = "aci.bin"
+//   tape = "program.wav"
+//
+// `Config::address_maps` loads the images and checks the layout: regions
+// must fit in 64K, not overlap, and something has to answer at the reset
+// vector.
+
+const ACI_SIZE: u32 = 0x200;
+const PIA_SIZE: u32 = 4;
+const RESET_VECTOR: u16 = 0xFFFC;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    // 0 when the problem isn't tied to a line of the file
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn config_error(message: String) -> ConfigError {
+    ConfigError { line: 0, message }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum Cpu {
+    #[default]
+    #[serde(rename = "6502", alias = "nmos")]
+    Nmos6502,
+    #[serde(rename = "65c02", alias = "65C02", alias = "cmos")]
+    Cmos65C02,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RamRegion {
+    pub start: u16,
+    pub end: Option<u16>,
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomImage {
+    pub file: String,
+    pub start: u16,
+    pub end: Option<u16>,
+    #[serde(default)]
+    pub writable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pia {
+    pub start: u16,
+}
+
+impl Default for Pia {
+    fn default() -> Self {
+        Pia { start: PIA_ADDR[0] }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Card {
+    Aci {
+        #[serde(default = "aci_start")]
+        start: u16,
+        rom: Option<String>,
+        tape: Option<String>,
+    },
+}
+
+fn aci_start() -> u16 {
+    0xC000
+}
+
+fn default_clock() -> f64 {
+    APPLE1_MHZ
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub cpu: Cpu,
+    #[serde(default = "default_clock")]
+    pub clock_mhz: f64,
+    // Busy time of the terminal per character, off when missing
+    pub display_cycles: Option<usize>,
+    #[serde(default)]
+    pub ram: Vec<RamRegion>,
+    #[serde(default)]
+    pub rom: Vec<RomImage>,
+    #[serde(default)]
+    pub pia: Pia,
+    #[serde(default, rename = "card")]
+    pub cards: Vec<Card>,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    // Directory relative paths are resolved against
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+// [first, last] for `size` bytes from `start`, if that fits in 64K
+fn span(start: u16, size: u32) -> Option<[u16; 2]> {
+    let last = start as u32 + size.checked_sub(1)?;
+    if last > 0xFFFF {
+        return None;
+    }
+    Some([start, last as u16])
+}
+
+fn describe(name: &str, addr: [u16; 2]) -> String {
+    format!("{} ${:04X}-${:04X}", name, addr[0], addr[1])
+}
+
+impl RamRegion {
+    fn addr(&self) -> Result<[u16; 2], ConfigError> {
+        let addr = match (self.end, self.size) {
+            (Some(end), None) if end >= self.start => Some([self.start, end]),
+            (None, Some(size)) => span(self.start, size),
+            (Some(_), Some(_)) => {
+                return Err(config_error(format!(
+                    "ram at ${:04X} has both end and size",
+                    self.start
+                )))
+            }
+            (None, None) => {
+                return Err(config_error(format!(
+                    "ram at ${:04X} needs an end or a size",
+                    self.start
+                )))
+            }
+            _ => None,
+        };
+        addr.ok_or_else(|| config_error(format!("ram at ${:04X} doesn't fit", self.start)))
+    }
+}
+
+impl RomImage {
+    fn addr(&self, image_len: usize) -> Result<[u16; 2], ConfigError> {
+        let addr = match self.end {
+            Some(end) if end >= self.start => [self.start, end],
+            Some(end) => {
+                return Err(config_error(format!(
+                    "rom {} ends at ${:04X}, before its start ${:04X}",
+                    self.file, end, self.start
+                )))
+            }
+            None => span(self.start, image_len as u32).ok_or_else(|| {
+                config_error(format!(
+                    "rom {} ({} bytes) doesn't fit at ${:04X}",
+                    self.file, image_len, self.start
+                ))
+            })?,
+        };
+        let size = (addr[1] - addr[0]) as usize + 1;
+        if image_len > size {
+            return Err(config_error(format!(
+                "rom {} is {} bytes, {} holds {}",
+                self.file,
+                image_len,
+                describe("the region", addr),
+                size
+            )));
+        }
+        Ok(addr)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    fs::read(path).map_err(|err| config_error(format!("{}: {}", path.display(), err)))
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(source).map_err(|err| ConfigError {
+            line: err
+                .span()
+                .map(|span| source[..span.start].matches('\n').count() + 1)
+                .unwrap_or(0),
+            message: err.message().to_string(),
+        })?;
+        if !config.clock_mhz.is_finite() || config.clock_mhz <= 0.0 {
+            return Err(config_error(format!(
+                "clock_mhz must be positive, got {}",
+                config.clock_mhz
+            )));
+        }
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| config_error(format!("{}: {}", path.display(), err)))?;
+        let mut config = Config::parse(&source)?;
+        config.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        match self.cpu {
+            Cpu::Nmos6502 => CpuVariant::Nmos6502,
+            Cpu::Cmos65C02 => CpuVariant::Cmos65C02,
+        }
+    }
+
+    pub fn pia_addr(&self) -> [u16; 2] {
+        [
+            self.pia.start,
+            self.pia.start.saturating_add(PIA_SIZE as u16 - 1),
+        ]
+    }
+
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.base.join(file)
+    }
+
+    // Every mapped region, in the order it is declared, given the size of
+    // each ROM image. Checks they fit, don't overlap and cover the reset
+    // vector.
+    pub fn regions(&self, image_lens: &[usize]) -> Result<Vec<(String, [u16; 2])>, ConfigError> {
+        let mut regions = Vec::new();
+        for ram in &self.ram {
+            regions.push((String::from("RAM"), ram.addr()?));
+        }
+        for (rom, len) in self.rom.iter().zip(image_lens) {
+            regions.push((rom.file.clone(), rom.addr(*len)?));
+        }
+        let pia = span(self.pia.start, PIA_SIZE)
+            .ok_or_else(|| config_error(format!("pia at ${:04X} doesn't fit", self.pia.start)))?;
+        regions.push((String::from("PIA"), pia));
+        for card in &self.cards {
+            match card {
+                Card::Aci { start, .. } => {
+                    let addr = span(*start, ACI_SIZE).ok_or_else(|| {
+                        config_error(format!("aci at ${:04X} doesn't fit", start))
+                    })?;
+                    regions.push((String::from("ACI"), addr));
+                }
+            }
+        }
+
+        let mut sorted: Vec<&(String, [u16; 2])> = regions.iter().collect();
+        sorted.sort_by_key(|(_, addr)| addr[0]);
+        for pair in sorted.windows(2) {
+            let ((first, a), (second, b)) = (pair[0], pair[1]);
+            if a[1] >= b[0] {
+                return Err(config_error(format!(
+                    "{} overlaps {}",
+                    describe(first, *a),
+                    describe(second, *b)
+                )));
+            }
+        }
+        if !regions
+            .iter()
+            .any(|(_, addr)| addr[0] <= RESET_VECTOR && RESET_VECTOR < addr[1])
+        {
+            return Err(config_error(format!(
+                "nothing is mapped at the reset vector ${:04X}",
+                RESET_VECTOR
+            )));
+        }
+        Ok(regions)
+    }
+
+    // Loads the images and builds the memory map, without the keyboard
+    // PIA, which the machine adds at `pia_addr`
+    pub fn address_maps(&self) -> Result<Vec<AddressMap>, ConfigError> {
+        let images = self
+            .rom
+            .iter()
+            .map(|rom| read(&self.path(&rom.file)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let lens: Vec<usize> = images.iter().map(Vec::len).collect();
+        let regions = self.regions(&lens)?;
+
+        let mut the_mapping = Vec::new();
+        for (name, addr) in regions.iter().take(self.ram.len()) {
+            the_mapping.push(AddressMap {
+                addr: *addr,
+                component: Box::new(Ram::init_with_size((addr[1] - addr[0]) as usize + 1)),
+                name: name.clone(),
+            });
+        }
+
+        let rom_regions = regions.iter().skip(self.ram.len());
+        for ((rom, image), (_, addr)) in self.rom.iter().zip(&images).zip(rom_regions) {
+            let size = (addr[1] - addr[0]) as usize + 1;
+            let mut component: Box<dyn IoAddressable> = if rom.writable {
+                Box::new(Ram::init_with_size(size))
+            } else {
+                Box::new(Rom::init_with_size(size))
+            };
+            let mut data = vec![0x00, 0x00];
+            data.extend_from_slice(image);
+            component.flash(&data);
+            the_mapping.push(AddressMap {
+                addr: *addr,
+                component,
+                name: rom.file.clone(),
+            });
+        }
+
+        for card in &self.cards {
+            match card {
+                Card::Aci { start, rom, tape } => {
+                    let cassette = Tape::init(self.clock_mhz * 1_000_000.0);
+                    if let Some(tape) = tape {
+                        let path = self.path(tape);
+                        cassette
+                            .load_wav(&path)
+                            .map_err(|err| config_error(format!("{}: {}", path.display(), err)))?;
+                    }
+                    let mut aci = Aci::init(cassette);
+                    if let Some(rom) = rom {
+                        let image = read(&self.path(rom))?;
+                        if image.len() > 0x100 {
+                            return Err(config_error(format!(
+                                "aci rom {} is {} bytes, the card holds 256",
+                                rom,
+                                image.len()
+                            )));
+                        }
+                        let mut data = vec![0x00, 0x00];
+                        data.extend_from_slice(&image);
+                        aci.flash(&data);
+                    }
+                    the_mapping.push(AddressMap {
+                        addr: [*start, *start + (ACI_SIZE - 1) as u16],
+                        component: Box::new(aci),
+                        name: String::from("ACI"),
+                    });
+                }
+            }
+        }
+        Ok(the_mapping)
+    }
+
+    // Woz Monitor symbols plus the configured files
+    pub fn load_symbols(&self) -> Result<Symbols, ConfigError> {
+        let mut symbols = Symbols::woz();
+        for file in &self.symbols {
+            let path = self.path(file);
+            let loaded = Symbols::load(&path)
+                .map_err(|err| config_error(format!("{}: {}", path.display(), err)))?;
+            symbols.extend(&loaded);
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::AddressSpaces;
+    use super::*;
+
+    const APPLE1_4K: &str = include_str!("../../examples/configs/apple1-4k.toml");
+    const APPLE1_8K: &str = include_str!("../../examples/configs/apple1-8k-basic.toml");
+    const REPLICA1: &str = include_str!("../../examples/configs/replica1-32k.toml");
+
+    fn ranges(regions: &[(String, [u16; 2])]) -> Vec<[u16; 2]> {
+        regions.iter().map(|(_, addr)| *addr).collect()
+    }
+
+    #[test]
+    fn describes_known_machines() {
+        let config = Config::parse(APPLE1_4K).unwrap();
+        assert_eq!(CpuVariant::Nmos6502, config.variant());
+        assert_eq!(APPLE1_MHZ, config.clock_mhz);
+        assert_eq!(
+            vec![[0x0000, 0x0FFF], [0xFF00, 0xFFFF], [0xD010, 0xD013]],
+            ranges(&config.regions(&[0x100]).unwrap())
+        );
+
+        let config = Config::parse(APPLE1_8K).unwrap();
+        assert_eq!(
+            vec![
+                [0x0000, 0x0FFF],
+                [0xE000, 0xEFFF],
+                [0xFF00, 0xFFFF],
+                [0xD010, 0xD013],
+                [0xC000, 0xC1FF]
+            ],
+            ranges(&config.regions(&[0x1000, 0x100]).unwrap())
+        );
+        assert!(config.rom[0].writable);
+
+        let config = Config::parse(REPLICA1).unwrap();
+        assert_eq!(CpuVariant::Cmos65C02, config.variant());
+        assert_eq!(Some("Replica-1"), config.name.as_deref());
+        assert_eq!(vec![String::from("krusader.sym")], config.symbols);
+        assert_eq!(
+            vec![[0x0000, 0x7FFF], [0xE000, 0xFFFF], [0xD010, 0xD013]],
+            ranges(&config.regions(&[0x2000]).unwrap())
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let err =
+            Config::parse("cpu = \"6502\"\n\n[[ram]]\nstart = 0x10000\nsize = 1\n").unwrap_err();
+        assert_eq!(4, err.line);
+        let err = Config::parse("cpu = \"z80\"\n").unwrap_err();
+        assert_eq!(1, err.line);
+        assert!(Config::parse("[[ram]]\nstart = 0\nsize = 16\nbank = 2\n").is_err());
+        assert!(Config::parse("[[card]]\ntype = \"floppy\"\n").is_err());
+        assert!(Config::parse("clock_mhz = 0.0\n").is_err());
+
+        let config = Config::parse(
+            "[[ram]]\nstart = 0\nsize = 0x10000\n[[rom]]\nfile = \"m.bin\"\nstart = 0xFF00\n",
+        )
+        .unwrap();
+        let err = config.regions(&[0x100]).unwrap_err();
+        assert_eq!("RAM $0000-$FFFF overlaps PIA $D010-$D013", err.to_string());
+
+        let config = Config::parse("[[ram]]\nstart = 0\nend = 0x0FFF\n").unwrap();
+        assert!(config
+            .regions(&[])
+            .unwrap_err()
+            .message
+            .contains("reset vector"));
+
+        let config =
+            Config::parse("[[rom]]\nfile = \"m.bin\"\nstart = 0xFF00\nend = 0xFFFF\n").unwrap();
+        assert!(config.regions(&[0x101]).is_err());
+        assert!(config.regions(&[0x100]).is_ok());
+        let config = Config::parse("[[rom]]\nfile = \"m.bin\"\nstart = 0xFF00\n").unwrap();
+        assert!(config.regions(&[0x101]).is_err());
+
+        let err = Config::load("no/such/machine.toml").unwrap_err();
+        assert!(err.message.contains("machine.toml"), "{}", err);
+    }
+
+    #[test]
+    fn builds_from_files() {
+        let dir = std::env::temp_dir().join(format!("config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut monitor = vec![0xEA; 0x100];
+        monitor[0xFC] = 0x00;
+        monitor[0xFD] = 0xFF;
+        fs::write(dir.join("monitor.bin"), &monitor).unwrap();
+        fs::write(dir.join("machine.sym"), "START = $0300\n").unwrap();
+        fs::write(
+            dir.join("machine.toml"),
+            "symbols = [\"machine.sym\"]\n\
+             [[ram]]\nstart = 0\nsize = 0x1000\n\
+             [[rom]]\nfile = \"monitor.bin\"\nstart = 0xFF00\n\
+             [[card]]\ntype = \"aci\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(dir.join("machine.toml")).unwrap();
+        let mut spaces = AddressSpaces::init(config.address_maps().unwrap());
+        assert_eq!(0xFF, spaces.read(0xFFFD));
+        spaces.write(0xFF00, 0x00);
+        assert_eq!(0xEA, spaces.read(0xFF00));
+        spaces.write(0x0300, 0x42);
+        assert_eq!(0x42, spaces.read(0x0300));
+
+        let symbols = config.load_symbols().unwrap();
+        assert_eq!(Some(0x0300), symbols.get("START"));
+        assert_eq!(Some(0xFFEF), symbols.get("ECHO"));
+
+        fs::remove_file(dir.join("monitor.bin")).unwrap();
+        assert!(config.address_maps().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use super::disasm;
+use super::mc6502::{CpuVariant, CPU6502};
+use super::stats::AccessStats;
+use super::symbols::Symbols;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Code coverage built from the opcode fetches AccessStats counts as
+// executes. Operand bytes are the rest of each executed instruction, decoded
+// from a snapshot of memory taken when the coverage is collected, so code
+// that patches its own operands may come out slightly off.
+
+const SPACE: usize = 0x10000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub from: u16,
+    pub to: u16,
+}
+
+impl Region {
+    pub fn new(name: &str, from: u16, to: u16) -> Region {
+        Region {
+            name: name.to_string(),
+            from,
+            to,
+        }
+    }
+
+    // Each symbol in [from, to] runs up to the next one, the last one up to
+    // `to`. Addresses with several names only start one region.
+    pub fn from_symbols(symbols: &Symbols, from: u16, to: u16) -> Vec<Region> {
+        let mut starts: Vec<(u16, &str)> = symbols
+            .iter()
+            .filter(|(addr, _)| *addr >= from && *addr <= to)
+            .collect();
+        starts.dedup_by_key(|(addr, _)| *addr);
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, (addr, name))| {
+                let end = match starts.get(i + 1) {
+                    Some((next, _)) => next - 1,
+                    None => to,
+                };
+                Region::new(name, *addr, end)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionCoverage {
+    pub bytes: usize,
+    pub covered: usize,
+}
+
+impl RegionCoverage {
+    pub fn percent(&self) -> f64 {
+        if self.bytes == 0 {
+            return 0.0;
+        }
+        100.0 * self.covered as f64 / self.bytes as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    variant: CpuVariant,
+    memory: Vec<u8>,
+    executes: Vec<u64>,
+    operand: Vec<bool>,
+}
+
+impl Coverage {
+    pub fn from_stats<F: Fn(u16) -> u8>(
+        stats: &AccessStats,
+        variant: CpuVariant,
+        peek: F,
+    ) -> Coverage {
+        let mut coverage = Coverage {
+            variant,
+            memory: (0..SPACE).map(|addr| peek(addr as u16)).collect(),
+            executes: vec![0; SPACE],
+            operand: vec![false; SPACE],
+        };
+        for (addr, counts) in stats.touched() {
+            if counts.executes > 0 {
+                coverage.record(addr, counts.executes);
+            }
+        }
+        coverage
+    }
+
+    // None unless stats were enabled on the CPU's address spaces
+    pub fn collect(cpu: &mut CPU6502) -> Option<Coverage> {
+        let variant = cpu.variant();
+        let memory: Vec<u8> = (0..SPACE).map(|addr| cpu.peek(addr as u16)).collect();
+        let stats = cpu.address_spaces().stats()?;
+        Some(Coverage::from_stats(stats, variant, |addr| {
+            memory[addr as usize]
+        }))
+    }
+
+    fn record(&mut self, addr: u16, executes: u64) {
+        self.executes[addr as usize] = executes;
+        let size = disasm::decode(self.variant, self.memory[addr as usize])
+            .mode
+            .size();
+        for i in 1..size {
+            self.operand[addr.wrapping_add(i) as usize] = true;
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    // Times an opcode was fetched from addr
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executes[addr as usize]
+    }
+
+    pub fn is_opcode(&self, addr: u16) -> bool {
+        self.executes[addr as usize] > 0
+    }
+
+    pub fn is_operand(&self, addr: u16) -> bool {
+        self.operand[addr as usize]
+    }
+
+    pub fn is_covered(&self, addr: u16) -> bool {
+        self.is_opcode(addr) || self.is_operand(addr)
+    }
+
+    pub fn region(&self, from: u16, to: u16) -> RegionCoverage {
+        let range = from..=to;
+        RegionCoverage {
+            bytes: range.clone().count(),
+            covered: range.filter(|addr| self.is_covered(*addr)).count(),
+        }
+    }
+
+    // Covered bytes per region plus a total line
+    pub fn report(&self, regions: &[Region]) -> String {
+        let mut out = format!(
+            "{:<16} {:<11} {:>7} {:>7} {:>8}\n",
+            "region", "range", "covered", "bytes", "%"
+        );
+        let mut total = RegionCoverage {
+            bytes: 0,
+            covered: 0,
+        };
+        for region in regions {
+            let coverage = self.region(region.from, region.to);
+            total.bytes += coverage.bytes;
+            total.covered += coverage.covered;
+            out.push_str(&format!(
+                "{:<16} ${:04X}-${:04X} {:>7} {:>7} {:>7.2}%\n",
+                region.name,
+                region.from,
+                region.to,
+                coverage.covered,
+                coverage.bytes,
+                coverage.percent()
+            ));
+        }
+        out.push_str(&format!(
+            "{:<16} {:<11} {:>7} {:>7} {:>7.2}%\n",
+            "total",
+            "",
+            total.covered,
+            total.bytes,
+            total.percent()
+        ));
+        out
+    }
+
+    // Disassembly of [from, to] with the execution count of every
+    // instruction, gcov style: never executed lines are marked `#####`. The
+    // sweep falls back to single bytes wherever decoding would run over an
+    // executed opcode, so it stays in step with the code that actually ran.
+    pub fn annotate(&self, from: u16, to: u16, symbols: &Symbols) -> String {
+        let mut out = String::new();
+        let mut addr = from as u32;
+        while addr <= to as u32 {
+            let at = addr as u16;
+            if let Some(name) = symbols.name(at) {
+                out.push_str(&format!("{}:\n", name));
+            }
+
+            let ins = disasm::disassemble(|a| self.peek(a), self.variant, at);
+            let overlaps = (1..ins.size()).any(|i| self.is_opcode(at.wrapping_add(i)));
+            let (size, line) = if self.is_opcode(at) || !(overlaps || self.is_operand(at)) {
+                let text = format!("{:04X}  {:<8}  {}", at, ins.hex(), ins.text_with(symbols));
+                (ins.size(), text)
+            } else {
+                let byte = self.peek(at);
+                (
+                    1,
+                    format!("{:04X}  {:02X}        .BYTE ${:02X}", at, byte, byte),
+                )
+            };
+            let marker = match self.executions(at) {
+                0 if self.is_operand(at) => String::from("-"),
+                0 => String::from("#####"),
+                n => n.to_string(),
+            };
+            out.push_str(&format!("{:>9}  {}\n", marker, line));
+            addr += size as u32;
+        }
+        out
+    }
+
+    pub fn write_annotated<P: AsRef<Path>>(
+        &self,
+        path: P,
+        from: u16,
+        to: u16,
+        symbols: &Symbols,
+    ) -> io::Result<()> {
+        fs::write(path, self.annotate(from, to, symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::{ram, Clockable, IoAddressable};
+    use super::*;
+
+    fn run(program: &[u8], steps: usize) -> CPU6502 {
+        let mut memory = ram::Ram::init_with_size(0x10000);
+        let mut image = vec![0x03, 0x00];
+        image.extend_from_slice(program);
+        memory.flash(&image);
+        memory.poke(0xFFFC, 0x00);
+        memory.poke(0xFFFD, 0x03);
+
+        let mut bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0x0000, 0xffff],
+            component: Box::new(memory),
+            name: String::from("RAM"),
+        }]);
+        bus.enable_stats();
+        let mut cpu = CPU6502::init(bus, CpuVariant::Nmos6502);
+        cpu.reset();
+        for _ in 0..steps {
+            cpu.step();
+        }
+        cpu
+    }
+
+    #[test]
+    fn marks_dead_code() {
+        let mut cpu = run(
+            &[
+                0xA2, 0x02, // $0300 LDX #$02
+                0xCA, //       $0302 LOOP: DEX
+                0xD0, 0xFD, // $0303 BNE LOOP
+                0xF0, 0x02, // $0305 BEQ DONE
+                0xE8, //       $0307 INX (never runs)
+                0xE8, //       $0308 INX (never runs)
+                0x4C, 0x09, 0x03, // $0309 DONE: JMP DONE
+            ],
+            7,
+        );
+        let coverage = Coverage::collect(&mut cpu).unwrap();
+        assert_eq!(2, coverage.executions(0x0302));
+        assert!(coverage.is_operand(0x0301));
+        assert!(!coverage.is_covered(0x0307));
+        assert_eq!(10, coverage.region(0x0300, 0x030B).covered);
+
+        let symbols = Symbols::parse("START = $0300\nLOOP = $0302\nDONE = $0309\n").unwrap();
+        assert_eq!(
+            "START:\n\
+             \x20       1  0300  A2 02     LDX #$02\n\
+             LOOP:\n\
+             \x20       2  0302  CA        DEX\n\
+             \x20       2  0303  D0 FD     BNE LOOP\n\
+             \x20       1  0305  F0 02     BEQ DONE\n\
+             \x20   #####  0307  E8        INX\n\
+             \x20   #####  0308  E8        INX\n\
+             DONE:\n\
+             \x20       1  0309  4C 09 03  JMP DONE\n",
+            coverage.annotate(0x0300, 0x030B, &symbols)
+        );
+
+        let regions = Region::from_symbols(&symbols, 0x0300, 0x030B);
+        assert_eq!(Region::new("START", 0x0300, 0x0301), regions[0]);
+        assert_eq!(Region::new("DONE", 0x0309, 0x030B), regions[2]);
+        let report = coverage.report(&regions);
+        assert!(
+            report.contains("LOOP             $0302-$0308       5       7   71.43%"),
+            "{}",
+            report
+        );
+        assert!(report.contains("total"));
+    }
+
+    #[test]
+    fn resyncs_on_executed_opcodes() {
+        // Jumps into the middle of what a sweep from $0300 decodes as LDA abs
+        let mut cpu = run(&[0x4C, 0x04, 0x03, 0xAD, 0xEA, 0x4C, 0x04, 0x03], 3);
+        let coverage = Coverage::collect(&mut cpu).unwrap();
+        let lines = coverage.annotate(0x0300, 0x0307, &Symbols::new());
+        assert!(
+            lines.contains("#####  0303  AD        .BYTE $AD\n"),
+            "{}",
+            lines
+        );
+        assert!(lines.contains("    1  0304  EA        NOP\n"), "{}", lines);
+    }
+}
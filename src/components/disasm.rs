@@ -0,0 +1,462 @@
+use super::mc6502::CpuVariant;
+use super::symbols::Symbols;
+use std::fmt;
+
+// Opcode metadata for both CPU variants. The tests step every opcode to keep
+// the sizes in step with exec_op and exec_cmos_op. Undocumented NMOS opcodes
+// carry a leading `*` the way the comments in mc6502 name them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Imp,
+    // ASL A and friends
+    Acc,
+    Imm,
+    Zp,
+    Zpx,
+    Zpy,
+    Izx,
+    Izy,
+    Abs,
+    Abx,
+    Aby,
+    Ind,
+    Rel,
+    // 65C02 (zp)
+    Izp,
+    // 65C02 JMP (abs,X)
+    Iax,
+    // 65C02 BBRn/BBSn zp,rel
+    Zpr,
+}
+
+impl Mode {
+    // Instruction size including the opcode
+    pub fn size(self) -> u16 {
+        match self {
+            Mode::Imp | Mode::Acc => 1,
+            Mode::Imm
+            | Mode::Zp
+            | Mode::Zpx
+            | Mode::Zpy
+            | Mode::Izx
+            | Mode::Izy
+            | Mode::Rel
+            | Mode::Izp => 2,
+            Mode::Abs | Mode::Abx | Mode::Aby | Mode::Ind | Mode::Iax | Mode::Zpr => 3,
+        }
+    }
+}
+
+use Mode::*;
+
+#[rustfmt::skip]
+const NMOS: [(&str, Mode); 256] = [
+    ("BRK", Imp), ("ORA", Izx), ("*KIL", Imp), ("*SLO", Izx),
+    ("*NOP", Zp), ("ORA", Zp), ("ASL", Zp), ("*SLO", Zp),
+    ("PHP", Imp), ("ORA", Imm), ("ASL", Acc), ("*ANC", Imm),
+    ("*NOP", Abs), ("ORA", Abs), ("ASL", Abs), ("*SLO", Abs),
+    ("BPL", Rel), ("ORA", Izy), ("*KIL", Imp), ("*SLO", Izy),
+    ("*NOP", Zpx), ("ORA", Zpx), ("ASL", Zpx), ("*SLO", Zpx),
+    ("CLC", Imp), ("ORA", Aby), ("*NOP", Imp), ("*SLO", Aby),
+    ("*NOP", Abx), ("ORA", Abx), ("ASL", Abx), ("*SLO", Abx),
+    ("JSR", Abs), ("AND", Izx), ("*KIL", Imp), ("*RLA", Izx),
+    ("BIT", Zp), ("AND", Zp), ("ROL", Zp), ("*RLA", Zp),
+    ("PLP", Imp), ("AND", Imm), ("ROL", Acc), ("*ANC", Imm),
+    ("BIT", Abs), ("AND", Abs), ("ROL", Abs), ("*RLA", Abs),
+    ("BMI", Rel), ("AND", Izy), ("*KIL", Imp), ("*RLA", Izy),
+    ("*NOP", Zpx), ("AND", Zpx), ("ROL", Zpx), ("*RLA", Zpx),
+    ("SEC", Imp), ("AND", Aby), ("*NOP", Imp), ("*RLA", Aby),
+    ("*NOP", Abx), ("AND", Abx), ("ROL", Abx), ("*RLA", Abx),
+    ("RTI", Imp), ("EOR", Izx), ("*KIL", Imp), ("*SRE", Izx),
+    ("*NOP", Zp), ("EOR", Zp), ("LSR", Zp), ("*SRE", Zp),
+    ("PHA", Imp), ("EOR", Imm), ("LSR", Acc), ("*ALR", Imm),
+    ("JMP", Abs), ("EOR", Abs), ("LSR", Abs), ("*SRE", Abs),
+    ("BVC", Rel), ("EOR", Izy), ("*KIL", Imp), ("*SRE", Izy),
+    ("*NOP", Zpx), ("EOR", Zpx), ("LSR", Zpx), ("*SRE", Zpx),
+    ("CLI", Imp), ("EOR", Aby), ("*NOP", Imp), ("*SRE", Aby),
+    ("*NOP", Abx), ("EOR", Abx), ("LSR", Abx), ("*SRE", Abx),
+    ("RTS", Imp), ("ADC", Izx), ("*KIL", Imp), ("*RRA", Izx),
+    ("*NOP", Zp), ("ADC", Zp), ("ROR", Zp), ("*RRA", Zp),
+    ("PLA", Imp), ("ADC", Imm), ("ROR", Acc), ("*ARR", Imm),
+    ("JMP", Ind), ("ADC", Abs), ("ROR", Abs), ("*RRA", Abs),
+    ("BVS", Rel), ("ADC", Izy), ("*KIL", Imp), ("*RRA", Izy),
+    ("*NOP", Zpx), ("ADC", Zpx), ("ROR", Zpx), ("*RRA", Zpx),
+    ("SEI", Imp), ("ADC", Aby), ("*NOP", Imp), ("*RRA", Aby),
+    ("*NOP", Abx), ("ADC", Abx), ("ROR", Abx), ("*RRA", Abx),
+    ("*NOP", Imm), ("STA", Izx), ("*NOP", Imm), ("*SAX", Izx),
+    ("STY", Zp), ("STA", Zp), ("STX", Zp), ("*SAX", Zp),
+    ("DEY", Imp), ("*NOP", Imm), ("TXA", Imp), ("*ANE", Imm),
+    ("STY", Abs), ("STA", Abs), ("STX", Abs), ("*SAX", Abs),
+    ("BCC", Rel), ("STA", Izy), ("*KIL", Imp), ("*AHX", Izy),
+    ("STY", Zpx), ("STA", Zpx), ("STX", Zpy), ("*SAX", Zpy),
+    ("TYA", Imp), ("STA", Aby), ("TXS", Imp), ("*SHS", Aby),
+    ("*SHY", Abx), ("STA", Abx), ("*SHX", Aby), ("*AHX", Aby),
+    ("LDY", Imm), ("LDA", Izx), ("LDX", Imm), ("*LAX", Izx),
+    ("LDY", Zp), ("LDA", Zp), ("LDX", Zp), ("*LAX", Zp),
+    ("TAY", Imp), ("LDA", Imm), ("TAX", Imp), ("*LAX", Imm),
+    ("LDY", Abs), ("LDA", Abs), ("LDX", Abs), ("*LAX", Abs),
+    ("BCS", Rel), ("LDA", Izy), ("*KIL", Imp), ("*LAX", Izy),
+    ("LDY", Zpx), ("LDA", Zpx), ("LDX", Zpy), ("*LAX", Zpy),
+    ("CLV", Imp), ("LDA", Aby), ("TSX", Imp), ("*LAS", Aby),
+    ("LDY", Abx), ("LDA", Abx), ("LDX", Aby), ("*LAX", Aby),
+    ("CPY", Imm), ("CMP", Izx), ("*NOP", Imm), ("*DCP", Izx),
+    ("CPY", Zp), ("CMP", Zp), ("DEC", Zp), ("*DCP", Zp),
+    ("INY", Imp), ("CMP", Imm), ("DEX", Imp), ("*SBX", Imm),
+    ("CPY", Abs), ("CMP", Abs), ("DEC", Abs), ("*DCP", Abs),
+    ("BNE", Rel), ("CMP", Izy), ("*KIL", Imp), ("*DCP", Izy),
+    ("*NOP", Zpx), ("CMP", Zpx), ("DEC", Zpx), ("*DCP", Zpx),
+    ("CLD", Imp), ("CMP", Aby), ("*NOP", Imp), ("*DCP", Aby),
+    ("*NOP", Abx), ("CMP", Abx), ("DEC", Abx), ("*DCP", Abx),
+    ("CPX", Imm), ("SBC", Izx), ("*NOP", Imm), ("*ISC", Izx),
+    ("CPX", Zp), ("SBC", Zp), ("INC", Zp), ("*ISC", Zp),
+    ("INX", Imp), ("SBC", Imm), ("NOP", Imp), ("*SBC", Imm),
+    ("CPX", Abs), ("SBC", Abs), ("INC", Abs), ("*ISC", Abs),
+    ("BEQ", Rel), ("SBC", Izy), ("*KIL", Imp), ("*ISC", Izy),
+    ("*NOP", Zpx), ("SBC", Zpx), ("INC", Zpx), ("*ISC", Zpx),
+    ("SED", Imp), ("SBC", Aby), ("*NOP", Imp), ("*ISC", Aby),
+    ("*NOP", Abx), ("SBC", Abx), ("INC", Abx), ("*ISC", Abx),
+];
+
+const BIT_OPS: [&str; 32] = [
+    "RMB0", "RMB1", "RMB2", "RMB3", "RMB4", "RMB5", "RMB6", "RMB7", "SMB0", "SMB1", "SMB2", "SMB3",
+    "SMB4", "SMB5", "SMB6", "SMB7", "BBR0", "BBR1", "BBR2", "BBR3", "BBR4", "BBR5", "BBR6", "BBR7",
+    "BBS0", "BBS1", "BBS2", "BBS3", "BBS4", "BBS5", "BBS6", "BBS7",
+];
+
+fn cmos(opcode: u8) -> Option<(&'static str, Mode)> {
+    let op = match opcode {
+        0x04 => ("TSB", Zp),
+        0x0C => ("TSB", Abs),
+        0x14 => ("TRB", Zp),
+        0x1C => ("TRB", Abs),
+        0x12 => ("ORA", Izp),
+        0x32 => ("AND", Izp),
+        0x52 => ("EOR", Izp),
+        0x72 => ("ADC", Izp),
+        0x92 => ("STA", Izp),
+        0xB2 => ("LDA", Izp),
+        0xD2 => ("CMP", Izp),
+        0xF2 => ("SBC", Izp),
+        0x02 | 0x22 | 0x42 | 0x62 => ("NOP", Imm),
+        0x1A => ("INC", Acc),
+        0x3A => ("DEC", Acc),
+        0x34 => ("BIT", Zpx),
+        0x3C => ("BIT", Abx),
+        0x89 => ("BIT", Imm),
+        0x5A => ("PHY", Imp),
+        0x7A => ("PLY", Imp),
+        0xDA => ("PHX", Imp),
+        0xFA => ("PLX", Imp),
+        0x5C | 0xDC | 0xFC => ("NOP", Abs),
+        0x64 => ("STZ", Zp),
+        0x74 => ("STZ", Zpx),
+        0x9C => ("STZ", Abs),
+        0x9E => ("STZ", Abx),
+        0x7C => ("JMP", Iax),
+        0x80 => ("BRA", Rel),
+        0xCB => ("WAI", Imp),
+        0xDB => ("STP", Imp),
+        op if op & 0x0F == 0x07 => (BIT_OPS[(op >> 4) as usize], Zp),
+        op if op & 0x0F == 0x0F => (BIT_OPS[16 + (op >> 4) as usize], Zpr),
+        op if op & 0x07 == 0x03 => ("NOP", Imp),
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+}
+
+impl Opcode {
+    pub fn undocumented(&self) -> bool {
+        self.mnemonic.starts_with('*')
+    }
+
+    // Mnemonic without the undocumented marker
+    pub fn name(&self) -> &'static str {
+        self.mnemonic.trim_start_matches('*')
+    }
+}
+
+pub fn decode(variant: CpuVariant, opcode: u8) -> Opcode {
+    let cmos = match variant {
+        CpuVariant::Cmos65C02 => cmos(opcode),
+        _ => None,
+    };
+    let (mnemonic, mode) = cmos.unwrap_or(NMOS[opcode as usize]);
+    Opcode {
+        opcode,
+        mnemonic,
+        mode,
+    }
+}
+
+// Every opcode of a variant, for tools that go from mnemonic to bytes
+pub fn opcodes(variant: CpuVariant) -> impl Iterator<Item = Opcode> {
+    (0..=0xFF).map(move |opcode| decode(variant, opcode))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub op: Opcode,
+    pub bytes: Vec<u8>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> u16 {
+        self.op.mode.size()
+    }
+
+    // Operand as a value: a byte, a word or the branch target
+    pub fn operand(&self) -> u16 {
+        let lo = self.bytes.get(1).copied().unwrap_or(0) as u16;
+        let hi = self.bytes.get(2).copied().unwrap_or(0) as u16;
+        match self.op.mode {
+            Imp | Acc => 0,
+            Rel => self.branch_target(2, lo as u8),
+            Zpr => lo,
+            Abs | Abx | Aby | Ind | Iax => lo | hi << 8,
+            _ => lo,
+        }
+    }
+
+    fn branch_target(&self, size: u16, offset: u8) -> u16 {
+        self.addr
+            .wrapping_add(size)
+            .wrapping_add(offset as i8 as u16)
+    }
+
+    // Where a branch, jump or call goes, if it's known statically
+    pub fn target(&self) -> Option<u16> {
+        match (self.op.name(), self.op.mode) {
+            (_, Rel) => Some(self.operand()),
+            (_, Zpr) => Some(self.branch_target(3, *self.bytes.get(2)?)),
+            ("JMP", Abs) | ("JSR", Abs) => Some(self.operand()),
+            _ => None,
+        }
+    }
+
+    pub fn operand_text(&self) -> String {
+        self.format_operand(None)
+    }
+
+    // Operand with addresses replaced by their symbol where there is one
+    pub fn operand_text_with(&self, symbols: &Symbols) -> String {
+        self.format_operand(Some(symbols))
+    }
+
+    fn format_operand(&self, symbols: Option<&Symbols>) -> String {
+        let value = self.operand();
+        let name = |addr: u16, hex: String| match symbols.and_then(|s| s.name(addr)) {
+            Some(name) => name.to_string(),
+            None => hex,
+        };
+        let zp = name(value, format!("${:02X}", value));
+        let abs = name(value, format!("${:04X}", value));
+        match self.op.mode {
+            Imp => String::new(),
+            Acc => String::from("A"),
+            Imm => format!("#${:02X}", value),
+            Zp => zp,
+            Zpx => format!("{},X", zp),
+            Zpy => format!("{},Y", zp),
+            Izx => format!("({},X)", zp),
+            Izy => format!("({}),Y", zp),
+            Izp => format!("({})", zp),
+            Abs | Rel => abs,
+            Abx => format!("{},X", abs),
+            Aby => format!("{},Y", abs),
+            Ind => format!("({})", abs),
+            Iax => format!("({},X)", abs),
+            Zpr => {
+                let target = self.target().unwrap_or(0);
+                format!("{},{}", zp, name(target, format!("${:04X}", target)))
+            }
+        }
+    }
+
+    // Mnemonic and operand, e.g. `LDA ($24),Y`
+    pub fn text(&self) -> String {
+        self.join(self.operand_text())
+    }
+
+    // Same with symbols, e.g. `LDA (XAML),Y`
+    pub fn text_with(&self, symbols: &Symbols) -> String {
+        self.join(self.operand_text_with(symbols))
+    }
+
+    fn join(&self, operand: String) -> String {
+        match self.op.mode {
+            Imp => self.op.mnemonic.to_string(),
+            _ => format!("{} {}", self.op.mnemonic, operand),
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        bytes.join(" ")
+    }
+}
+
+// Address, raw bytes and text, one monitor-style line
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  {:<8}  {}", self.addr, self.hex(), self.text())
+    }
+}
+
+// Decodes the instruction at addr. Reads through peek so disassembling never
+// disturbs I/O registers.
+pub fn disassemble<F: Fn(u16) -> u8>(peek: F, variant: CpuVariant, addr: u16) -> Instruction {
+    let op = decode(variant, peek(addr));
+    let bytes = (0..op.mode.size())
+        .map(|i| peek(addr.wrapping_add(i)))
+        .collect();
+    Instruction { addr, op, bytes }
+}
+
+// Linear sweep over [from, to]
+pub fn disassemble_range<F: Fn(u16) -> u8>(
+    peek: F,
+    variant: CpuVariant,
+    from: u16,
+    to: u16,
+) -> Vec<Instruction> {
+    let mut out = Vec::new();
+    let mut addr = from as u32;
+    while addr <= to as u32 {
+        let ins = disassemble(&peek, variant, addr as u16);
+        addr += ins.size() as u32;
+        out.push(ins);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::mc6502::{Registers, CPU6502};
+    use super::super::{ram, Clockable};
+    use super::*;
+
+    fn memory(at: u16, code: &[u8]) -> impl Fn(u16) -> u8 + '_ {
+        move |addr| {
+            let offset = addr.wrapping_sub(at) as usize;
+            code.get(offset).copied().unwrap_or(0)
+        }
+    }
+
+    #[test]
+    fn decodes_nmos_modes() {
+        let code = [
+            0xA9, 0x8D, // LDA #$8D
+            0xB1, 0x24, // LDA ($24),Y
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0x0A, // ASL A
+            0xD0, 0xF6, // BNE $FF00
+            0xA7, 0x10, // *LAX $10
+        ];
+        let text: Vec<String> =
+            disassemble_range(memory(0xFF00, &code), CpuVariant::Nmos6502, 0xFF00, 0xFF0B)
+                .iter()
+                .map(|ins| ins.to_string())
+                .collect();
+        assert_eq!(
+            vec![
+                "FF00  A9 8D     LDA #$8D",
+                "FF02  B1 24     LDA ($24),Y",
+                "FF04  6C FC FF  JMP ($FFFC)",
+                "FF07  0A        ASL A",
+                "FF08  D0 F6     BNE $FF00",
+                "FF0A  A7 10     *LAX $10",
+            ],
+            text
+        );
+    }
+
+    #[test]
+    fn decodes_cmos_overrides() {
+        let code = [0x0F, 0x24, 0xFD, 0x7C, 0x00, 0x10, 0xB2, 0x30];
+        let peek = memory(0x0300, &code);
+        let bbr = disassemble(&peek, CpuVariant::Cmos65C02, 0x0300);
+        assert_eq!("BBR0 $24,$0300", bbr.text());
+        assert_eq!(Some(0x0300), bbr.target());
+        assert_eq!(
+            "JMP ($1000,X)",
+            disassemble(&peek, CpuVariant::Cmos65C02, 0x0303).text()
+        );
+        assert_eq!(
+            "LDA ($30)",
+            disassemble(&peek, CpuVariant::Cmos65C02, 0x0306).text()
+        );
+        assert_eq!(
+            "*KIL",
+            disassemble(&peek, CpuVariant::Nmos6502, 0x0306).text()
+        );
+        assert_eq!(256, opcodes(CpuVariant::Cmos65C02).count());
+    }
+
+    #[test]
+    fn names_operands_from_symbols() {
+        let code = [0xAD, 0x11, 0xD0, 0x10, 0xFB, 0xB1, 0x24];
+        let symbols = Symbols::woz();
+        let text: Vec<String> =
+            disassemble_range(memory(0xFF29, &code), CpuVariant::Nmos6502, 0xFF29, 0xFF2E)
+                .iter()
+                .map(|ins| ins.text_with(&symbols))
+                .collect();
+        assert_eq!(vec!["LDA KBDCR", "BPL NEXTCHAR", "LDA (XAML),Y"], text);
+    }
+
+    #[test]
+    fn target_of_short_instruction() {
+        let ins = Instruction {
+            addr: 0x0300,
+            op: decode(CpuVariant::Cmos65C02, 0x0F),
+            bytes: vec![0x0F, 0x24],
+        };
+        assert_eq!(None, ins.target());
+    }
+
+    #[test]
+    fn sizes_match_the_cpu() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02] {
+            for op in opcodes(variant) {
+                // These set the PC themselves
+                let jumps = ["JMP", "JSR", "RTS", "RTI", "BRK"];
+                if matches!(op.mode, Rel | Zpr) || jumps.contains(&op.name()) {
+                    continue;
+                }
+                let bus = AddressSpaces::init(vec![AddressMap {
+                    addr: [0x0000, 0xffff],
+                    component: Box::new(ram::Ram::init_with_size(0x10000)),
+                    name: String::from("RAM"),
+                }]);
+                let mut cpu = CPU6502::init(bus, variant);
+                for (i, byte) in [op.opcode, 0x10, 0x20].iter().enumerate() {
+                    cpu.poke(0x0200 + i as u16, *byte);
+                }
+                cpu.set_registers(Registers {
+                    pc: 0x0200,
+                    s: 0xFF,
+                    ..cpu.registers()
+                });
+                cpu.step();
+                assert_eq!(
+                    0x0200 + op.mode.size(),
+                    cpu.registers().pc,
+                    "{:?} ${:02X} {}",
+                    variant,
+                    op.opcode,
+                    op.mnemonic
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use super::scheduler::Scheduler;
+use super::IoComponent;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::{Rc, Weak};
+
+// Apple-1 terminal section, wired to PIA port B. PB0-6 carry the character,
+// PB7 reads back as the "display busy" line.
+
+const CR: u8 = 0x0D;
+
+pub const COLUMNS: usize = 40;
+pub const ROWS: usize = 24;
+
+// The terminal takes one character per video frame, about 60 per second
+pub const CYCLES_PER_CHAR: usize = 17_045;
+
+#[derive(Debug)]
+struct DisplayState {
+    transcript: String,
+    keep_transcript: bool,
+    // Queues of the live `DisplayOutput` handles
+    outputs: Vec<Weak<RefCell<String>>>,
+    echo: bool,
+    screen: [[u8; COLUMNS]; ROWS],
+    cursor: (usize, usize),
+    busy: bool,
+    // Without a scheduler the display is never busy
+    timing: Option<(Scheduler, usize)>,
+}
+
+impl Default for DisplayState {
+    fn default() -> Self {
+        DisplayState {
+            transcript: String::new(),
+            keep_transcript: true,
+            outputs: Vec::new(),
+            echo: false,
+            screen: [[b' '; COLUMNS]; ROWS],
+            cursor: (0, 0),
+            busy: false,
+            timing: None,
+        }
+    }
+}
+
+impl DisplayState {
+    // The cursor wraps at column 40 and the screen scrolls up from the bottom
+    fn new_line(&mut self) {
+        self.cursor.0 = 0;
+        if self.cursor.1 + 1 < ROWS {
+            self.cursor.1 += 1;
+        } else {
+            self.screen.rotate_left(1);
+            self.screen[ROWS - 1] = [b' '; COLUMNS];
+        }
+    }
+
+    fn put(&mut self, value: u8) {
+        if value == CR {
+            self.new_line();
+            return;
+        }
+        let (column, row) = self.cursor;
+        self.screen[row][column] = value;
+        if column + 1 < COLUMNS {
+            self.cursor.0 += 1;
+        } else {
+            self.new_line();
+        }
+    }
+}
+
+// Output printed since the handle was made, for consumers that run as long
+// as the machine does and can't keep the whole transcript around
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOutput {
+    queue: Rc<RefCell<String>>,
+}
+
+impl DisplayOutput {
+    // Everything printed since the last call, with CR translated to '\n'
+    pub fn take(&self) -> String {
+        self.queue.take()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Display {
+    state: Rc<RefCell<DisplayState>>,
+}
+
+impl Display {
+    pub fn init(echo: bool) -> Display {
+        Display {
+            state: Rc::new(RefCell::new(DisplayState {
+                echo,
+                ..DisplayState::default()
+            })),
+        }
+    }
+
+    // Everything printed since power on, with CR translated to '\n'
+    pub fn transcript(&self) -> String {
+        self.state.borrow().transcript.clone()
+    }
+
+    pub fn transcript_len(&self) -> usize {
+        self.state.borrow().transcript.len()
+    }
+
+    // Empty for offsets past the end, e.g. taken before the transcript was
+    // dropped
+    pub fn transcript_since(&self, at: usize) -> String {
+        let state = self.state.borrow();
+        state.transcript.get(at..).unwrap_or("").to_string()
+    }
+
+    // Stops (and forgets) the transcript, so it doesn't grow for as long as
+    // the machine runs
+    pub fn keep_transcript(&self, keep: bool) {
+        let mut state = self.state.borrow_mut();
+        state.keep_transcript = keep;
+        if !keep {
+            state.transcript = String::new();
+        }
+    }
+
+    pub fn subscribe(&self) -> DisplayOutput {
+        let output = DisplayOutput::default();
+        let mut state = self.state.borrow_mut();
+        state.outputs.push(Rc::downgrade(&output.queue));
+        output
+    }
+
+    // The 40x24 screen, one line per row with trailing blanks removed
+    pub fn screen_text(&self) -> String {
+        let state = self.state.borrow();
+        let mut text = String::new();
+        for row in state.screen.iter() {
+            text.push_str(String::from_utf8_lossy(row).trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    // The raw screen codes, rows top to bottom
+    pub fn screen(&self) -> [[u8; COLUMNS]; ROWS] {
+        self.state.borrow().screen
+    }
+
+    // Holds PB7 high for `busy_cycles` after every character
+    pub fn attach_scheduler(&self, scheduler: Scheduler, busy_cycles: usize) {
+        self.state.borrow_mut().timing = Some((scheduler, busy_cycles));
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.state.borrow().busy
+    }
+
+    fn start_busy(&self) {
+        let timing = self.state.borrow().timing.clone();
+        if let Some((scheduler, busy_cycles)) = timing {
+            self.state.borrow_mut().busy = true;
+            let state = self.state.clone();
+            scheduler.schedule_in(busy_cycles, move |_| {
+                state.borrow_mut().busy = false;
+                None
+            });
+        }
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.state.borrow().cursor
+    }
+
+    fn print(&self, value: u8) {
+        let ch = match value & 0x7F {
+            CR => '\n',
+            c @ 0x20..=0x7E => c as char,
+            _ => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        if state.keep_transcript {
+            state.transcript.push(ch);
+        }
+        state.outputs.retain(|queue| match queue.upgrade() {
+            Some(queue) => {
+                queue.borrow_mut().push(ch);
+                true
+            }
+            None => false,
+        });
+        state.put(value & 0x7F);
+        if state.echo {
+            let mut stdout = io::stdout();
+            let _ = write!(stdout, "{}", ch);
+            let _ = stdout.flush();
+        }
+    }
+}
+
+impl IoComponent for Display {
+    // PB7 high while the terminal is still busy with the last character
+    fn input(&mut self) -> u8 {
+        if self.is_busy() {
+            0x80
+        } else {
+            0x00
+        }
+    }
+
+    fn output(&mut self, lines: u8) {
+        if self.is_busy() {
+            return;
+        }
+        self.print(lines);
+        self.start_busy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let display = Display::init(false);
+        assert_eq!("", display.transcript());
+    }
+
+    #[test]
+    fn prints_characters() {
+        let mut display = Display::init(false);
+        for value in b"\\\r" {
+            display.output(*value);
+        }
+        display.output(0x07);
+        display.output(b'A');
+        assert_eq!("\\\nA", display.transcript());
+        assert_eq!("A", display.transcript_since(2));
+        assert_eq!((1, 1), display.cursor());
+        assert!(display.screen_text().starts_with("\\\nA\n\n"));
+    }
+
+    #[test]
+    fn drains_output_queues() {
+        let mut display = Display::init(false);
+        display.output(b'A');
+        let output = display.subscribe();
+        let dropped = display.subscribe();
+        drop(dropped);
+        let cursor = display.transcript_len();
+        display.keep_transcript(false);
+        assert_eq!("", display.transcript_since(cursor));
+        display.output(CR);
+        display.output(b'B');
+        assert_eq!("\nB", output.take());
+        assert_eq!("", output.take());
+        assert_eq!("", display.transcript());
+        assert_eq!(1, display.state.borrow().outputs.len());
+    }
+
+    #[test]
+    fn busy_until_scheduled_timeout() {
+        let scheduler = Scheduler::init();
+        let mut display = Display::init(false);
+        display.attach_scheduler(scheduler.clone(), 100);
+
+        display.output(b'A');
+        assert_eq!(0x80, display.input());
+        // Dropped, the terminal wasn't ready
+        display.output(b'B');
+
+        scheduler.run_until(99);
+        assert!(display.is_busy());
+        scheduler.run_until(100);
+        assert_eq!(0x00, display.input());
+        display.output(b'C');
+        assert_eq!("AC", display.transcript());
+    }
+
+    #[test]
+    fn wraps_and_scrolls() {
+        let mut display = Display::init(false);
+        for row in 0..ROWS {
+            display.output(b'A' + row as u8);
+            display.output(CR);
+        }
+        for _ in 0..COLUMNS + 1 {
+            display.output(b'*');
+        }
+
+        let screen = display.screen_text();
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(ROWS, lines.len());
+        assert_eq!("C", lines[0]);
+        assert_eq!("*".repeat(COLUMNS), lines[ROWS - 2]);
+        assert_eq!("*", lines[ROWS - 1]);
+        assert_eq!((1, ROWS - 1), display.cursor());
+    }
+}
@@ -0,0 +1,352 @@
+use super::display::{Display, DisplayOutput};
+use super::{ControlLines, IoComponent};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+// Keyboard input feeder, wired to PIA port A. Queued text is released one
+// key at a time: the next key is only strobed once the program has read the
+// previous one from KBD, which is what clears KBDCR bit 7 on the PIA.
+
+const CR: u8 = 0x0D;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedStep {
+    Text(String),
+    Return,
+    // Pause for a number of CPU cycles
+    Delay(usize),
+    // Hold the queue until the display prints this string
+    WaitFor(String),
+}
+
+#[derive(Debug, Default)]
+struct FeederState {
+    steps: VecDeque<FeedStep>,
+    // Byte offset of the next key in the front `FeedStep::Text`
+    text_at: usize,
+    key: u8,
+    awaiting_read: bool,
+    delay_until: Option<usize>,
+    // Display output since the front `FeedStep::WaitFor` started waiting
+    waiting: Option<(DisplayOutput, String)>,
+    display: Option<Display>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InputFeeder {
+    state: Rc<RefCell<FeederState>>,
+}
+
+// Apple-1 keys are upper case ASCII with bit 7 set
+fn to_key(ch: char) -> Option<u8> {
+    match ch {
+        '\n' | '\r' => Some(CR | 0x80),
+        ch if ch.is_ascii() && !ch.is_ascii_control() => Some(ch.to_ascii_uppercase() as u8 | 0x80),
+        _ => None,
+    }
+}
+
+impl InputFeeder {
+    // `display` is needed for `FeedStep::WaitFor`
+    pub fn init(display: Option<Display>) -> InputFeeder {
+        InputFeeder {
+            state: Rc::new(RefCell::new(FeederState {
+                display,
+                ..FeederState::default()
+            })),
+        }
+    }
+
+    // Panics on `FeedStep::WaitFor` without a display to watch
+    pub fn push(&self, step: FeedStep) {
+        let mut state = self.state.borrow_mut();
+        if let FeedStep::WaitFor(prompt) = &step {
+            assert!(
+                state.display.is_some(),
+                "can't wait for {:?}, the feeder has no display",
+                prompt
+            );
+        }
+        state.steps.push_back(step);
+    }
+
+    pub fn type_text(&self, text: &str) {
+        self.push(FeedStep::Text(text.replace("\r\n", "\n")));
+    }
+
+    pub fn type_line(&self, text: &str) {
+        self.type_text(text);
+        self.push(FeedStep::Return);
+    }
+
+    pub fn delay(&self, cycles: usize) {
+        self.push(FeedStep::Delay(cycles));
+    }
+
+    pub fn wait_for(&self, prompt: &str) {
+        self.push(FeedStep::WaitFor(prompt.to_string()));
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.type_text(&fs::read_to_string(path)?);
+        Ok(())
+    }
+
+    pub fn is_idle(&self) -> bool {
+        let state = self.state.borrow();
+        state.steps.is_empty() && !state.awaiting_read
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.steps.clear();
+        state.text_at = 0;
+        state.delay_until = None;
+        state.waiting = None;
+    }
+
+    // Moves the queue forward, returns true when a new key was latched
+    fn advance(&self, cycles: usize) -> bool {
+        let mut state = self.state.borrow_mut();
+        if state.awaiting_read {
+            return false;
+        }
+
+        let state = &mut *state;
+        while let Some(step) = state.steps.front() {
+            match step {
+                FeedStep::Text(text) => {
+                    let ch = match text[state.text_at..].chars().next() {
+                        Some(ch) => ch,
+                        None => {
+                            state.text_at = 0;
+                            state.steps.pop_front();
+                            continue;
+                        }
+                    };
+                    state.text_at += ch.len_utf8();
+                    if state.text_at == text.len() {
+                        state.text_at = 0;
+                        state.steps.pop_front();
+                    }
+                    if let Some(key) = to_key(ch) {
+                        state.key = key;
+                        state.awaiting_read = true;
+                        return true;
+                    }
+                }
+                FeedStep::Return => {
+                    state.steps.pop_front();
+                    state.key = CR | 0x80;
+                    state.awaiting_read = true;
+                    return true;
+                }
+                FeedStep::Delay(delay) => {
+                    let delay = *delay;
+                    let until = *state.delay_until.get_or_insert(cycles + delay);
+                    if cycles < until {
+                        return false;
+                    }
+                    state.delay_until = None;
+                    state.steps.pop_front();
+                }
+                FeedStep::WaitFor(prompt) => {
+                    let display = state.display.as_ref().expect("checked in push");
+                    let (output, seen) = state
+                        .waiting
+                        .get_or_insert_with(|| (display.subscribe(), String::new()));
+                    seen.push_str(&output.take());
+                    if !seen.contains(prompt.as_str()) {
+                        // Keep just enough for a prompt split across polls
+                        let keep = seen
+                            .char_indices()
+                            .rev()
+                            .nth(prompt.chars().count().saturating_sub(2))
+                            .map_or(0, |(at, _)| at);
+                        seen.drain(..keep);
+                        return false;
+                    }
+                    state.waiting = None;
+                    state.steps.pop_front();
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl IoComponent for InputFeeder {
+    // Reading KBD takes the key, the next one may be strobed
+    fn input(&mut self) -> u8 {
+        let mut state = self.state.borrow_mut();
+        state.awaiting_read = false;
+        state.key
+    }
+
+    // CA1 pulses high for one instruction when a key is latched
+    fn sync(&mut self, cycles: usize) -> ControlLines {
+        ControlLines {
+            c1: self.advance(cycles),
+            c2: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mc6502::{CpuVariant, CPU6502};
+    use super::super::pia6820::Pia6820;
+    use super::super::{address_spaces, ram, rom, Clockable, IoAddressable};
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let mut feeder = InputFeeder::init(None);
+        assert!(feeder.is_idle());
+        assert!(!feeder.sync(0).c1);
+    }
+
+    #[test]
+    fn holds_next_key_until_read() {
+        let mut feeder = InputFeeder::init(None);
+        feeder.type_line("ab");
+
+        assert!(feeder.sync(0).c1);
+        assert!(!feeder.sync(100).c1);
+        assert_eq!(b'A' | 0x80, feeder.input());
+        assert!(feeder.sync(200).c1);
+        assert_eq!(b'B' | 0x80, feeder.input());
+        assert!(feeder.sync(300).c1);
+        assert_eq!(0x8D, feeder.input());
+        assert!(!feeder.sync(400).c1);
+        assert!(feeder.is_idle());
+    }
+
+    #[test]
+    fn delays_and_waits_for_prompt() {
+        let mut display = Display::init(false);
+        let mut feeder = InputFeeder::init(Some(display.clone()));
+        feeder.delay(1000);
+        feeder.wait_for("]");
+        feeder.type_text("x");
+
+        assert!(!feeder.sync(0).c1);
+        assert!(!feeder.sync(999).c1);
+        assert!(!feeder.sync(1000).c1);
+        display.output(b'>');
+        assert!(!feeder.sync(1100).c1);
+        display.output(b']');
+        assert!(feeder.sync(1200).c1);
+        assert_eq!(b'X' | 0x80, feeder.input());
+    }
+
+    #[test]
+    fn waits_for_prompt_split_across_polls() {
+        let mut display = Display::init(false);
+        let mut feeder = InputFeeder::init(Some(display.clone()));
+        display.output(b'>');
+        feeder.wait_for(">>>");
+        feeder.type_text("é!");
+
+        assert!(!feeder.sync(0).c1);
+        display.output(b'>');
+        assert!(!feeder.sync(1).c1);
+        display.output(b'>');
+        assert!(!feeder.sync(2).c1);
+        display.output(b'>');
+        // Keys that don't exist on the Apple-1 are skipped
+        assert!(feeder.sync(3).c1);
+        assert_eq!(b'!' | 0x80, feeder.input());
+        assert!(feeder.is_idle());
+    }
+
+    #[test]
+    #[should_panic(expected = "no display")]
+    fn waiting_needs_a_display() {
+        InputFeeder::init(None).wait_for("]");
+    }
+
+    // Echo loop reading the keyboard the way the Woz Monitor does
+    fn build_echo_cpu(feeder: &InputFeeder, display: &Display) -> CPU6502 {
+        let program = [
+            0xA9, 0x7F, 0x8D, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x11, 0xD0, 0x8D, 0x13, 0xD0, 0xAD,
+            0x11, 0xD0, 0x10, 0xFB, 0xAD, 0x10, 0xD0, 0x2C, 0x12, 0xD0, 0x30, 0xFB, 0x8D, 0x12,
+            0xD0, 0x4C, 0x0D, 0xFF,
+        ];
+        let mut rom_data = vec![0x00; 2 + 0x100];
+        rom_data[2..2 + program.len()].copy_from_slice(&program);
+        rom_data[2 + 0xfd] = 0xFF;
+        let mut rom = rom::Rom::init_with_size(0x100);
+        rom.flash(&rom_data);
+
+        let mut pia = Pia6820::new();
+        pia.wire_ioa(Some(Box::new(feeder.clone())));
+        pia.wire_iob(Some(Box::new(display.clone())));
+
+        let the_mapping = vec![
+            address_spaces::AddressMap {
+                addr: [0x0000, 0x0fff],
+                component: Box::new(ram::Ram::init_with_size(0x1000)),
+                name: String::from("RAM"),
+            },
+            address_spaces::AddressMap {
+                addr: [0xd010, 0xd013],
+                component: Box::new(pia),
+                name: String::from("PIA"),
+            },
+            address_spaces::AddressMap {
+                addr: [0xff00, 0xffff],
+                component: Box::new(rom),
+                name: String::from("ROM"),
+            },
+        ];
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn pastes_without_dropping_characters() {
+        let display = Display::init(false);
+        let feeder = InputFeeder::init(Some(display.clone()));
+        let listing = "10 PRINT \"HELLO\"\n20 GOTO 10\nRUN\n";
+        feeder.type_text(listing);
+
+        let mut cpu = build_echo_cpu(&feeder, &display);
+        while !feeder.is_idle() {
+            cpu.step();
+        }
+        for _ in 0..20 {
+            cpu.step();
+        }
+
+        assert_eq!(listing, display.transcript());
+    }
+
+    #[test]
+    fn waits_for_display_between_characters() {
+        let display = Display::init(false);
+        let feeder = InputFeeder::init(Some(display.clone()));
+        feeder.type_text("ABC");
+
+        let mut cpu = build_echo_cpu(&feeder, &display);
+        display.attach_scheduler(cpu.scheduler(), 1000);
+        while cpu.get_cycles() < 1500 {
+            cpu.step();
+        }
+        assert_eq!("AB", display.transcript());
+
+        while !feeder.is_idle() || cpu.get_cycles() < 2500 {
+            cpu.step();
+        }
+        assert_eq!("ABC", display.transcript());
+    }
+}
@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// An RGB framebuffer with plain PPM (P6) and PNG writers. The PNG is
+// truecolour, 8 bits per channel, with the image data in stored (level 0)
+// deflate blocks: bigger files, but no compression code to get wrong.
+
+// A stored deflate block holds at most 65535 bytes
+const STORED_BLOCK: usize = 0xFFFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = crc32_table();
+    !data.iter().fold(!0u32, |crc, byte| {
+        table[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+// zlib stream with the data in stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let len = chunk.len() as u16;
+        out.push(chunks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn png_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut crc_data = kind.to_vec();
+    crc_data.extend_from_slice(data);
+    out.write_all(&crc_data)?;
+    out.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+impl Frame {
+    pub fn init(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Rows top to bottom, R G B per pixel
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let at = (y * self.width + x) * 3;
+        [self.pixels[at], self.pixels[at + 1], self.pixels[at + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let at = (y * self.width + x) * 3;
+        self.pixels[at..at + 3].copy_from_slice(&rgb);
+    }
+
+    pub fn fill(&mut self, rgb: [u8; 3]) {
+        for pixel in self.pixels.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&rgb);
+        }
+    }
+
+    // 0x00RRGGBB per pixel, the layout most window libraries blit from
+    pub fn to_rgb32(&self) -> Vec<u32> {
+        self.pixels
+            .chunks_exact(3)
+            .map(|p| (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32)
+            .collect()
+    }
+
+    pub fn write_ppm(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.pixels)
+    }
+
+    pub fn write_png(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bit truecolour, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        png_chunk(out, b"IHDR", &header)?;
+
+        // Every scanline starts with filter type 0 (none)
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks_exact(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        png_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+        png_chunk(out, b"IEND", &[])
+    }
+
+    // PNG unless the extension says .ppm
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut out = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ppm") => self.write_ppm(&mut out)?,
+            _ => self.write_png(&mut out)?,
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(data: &[u8]) -> u32 {
+        u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn writes_ppm() {
+        let mut frame = Frame::init(2, 1);
+        frame.set_pixel(1, 0, [0x10, 0x20, 0x30]);
+        let mut out = Vec::new();
+        frame.write_ppm(&mut out).unwrap();
+        assert_eq!(b"P6\n2 1\n255\n\x00\x00\x00\x10\x20\x30".to_vec(), out);
+        assert_eq!(vec![0, 0x102030], frame.to_rgb32());
+    }
+
+    #[test]
+    fn writes_stored_png() {
+        // Big enough to need two stored blocks
+        let mut frame = Frame::init(200, 120);
+        frame.fill([1, 2, 3]);
+        let mut out = Vec::new();
+        frame.write_png(&mut out).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &out[..8]);
+
+        // Walk the chunks, checking each CRC and collecting the image data
+        let mut at = 8;
+        let mut kinds = Vec::new();
+        let mut idat = Vec::new();
+        while at < out.len() {
+            let len = be32(&out[at..]) as usize;
+            let body = &out[at + 4..at + 8 + len];
+            assert_eq!(crc32(body), be32(&out[at + 8 + len..]));
+            kinds.push(String::from_utf8(body[..4].to_vec()).unwrap());
+            if &body[..4] == b"IDAT" {
+                idat.extend_from_slice(&body[4..]);
+            }
+            at += 12 + len;
+        }
+        assert_eq!(vec!["IHDR", "IDAT", "IEND"], kinds);
+
+        // Undo the stored blocks
+        let mut raw = Vec::new();
+        let mut at = 2;
+        loop {
+            let last = idat[at] & 1 == 1;
+            let len = u16::from_le_bytes([idat[at + 1], idat[at + 2]]) as usize;
+            raw.extend_from_slice(&idat[at + 5..at + 5 + len]);
+            at += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(120 * 601, raw.len());
+        assert_eq!(adler32(&raw), be32(&idat[at..]));
+        assert_eq!(&[0, 1, 2, 3], &raw[..4]);
+    }
+}
@@ -0,0 +1,544 @@
+use super::mc6502::{Registers, CPU6502};
+use super::Clockable;
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+// GDB remote serial protocol stub. Listens on loopback only and serves one
+// debugger at a time. Registers go over the wire as A, X, Y, P, S (a byte
+// each) and PC (little endian word), which the target description sent via
+// qXfer spells out for the front-end. Memory is accessed with peek/poke so
+// looking at I/O registers doesn't acknowledge keys or clear PIA flags.
+//
+// Software and hardware breakpoints (Z0/Z1) are both plain PC breakpoints,
+// the stub checks them before every instruction rather than patching BRKs
+// into memory that may be ROM.
+
+// Instructions between checks for a ^C from the debugger while running
+const POLL_EVERY: usize = 1024;
+
+// Largest packet the stub accepts, as advertised in qSupported. Memory
+// transfers are capped at half of it, every byte being two hex digits.
+const PACKET_SIZE: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.apple1.cpu">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8" regnum="1"/>
+    <reg name="y" bitsize="8" regnum="2"/>
+    <reg name="p" bitsize="8" regnum="3"/>
+    <reg name="s" bitsize="8" regnum="4"/>
+    <reg name="pc" bitsize="16" regnum="5" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+// Start and length of an m or M packet, wrapping around the 64K space
+fn memory_range(range: &str) -> Option<(u16, usize)> {
+    let (addr, len) = range.split_once(',')?;
+    let len = number(len)?;
+    if len > PACKET_SIZE / 2 {
+        return None;
+    }
+    Some((address(addr)?, len))
+}
+
+fn register_bytes(registers: &Registers) -> Vec<u8> {
+    let [lo, hi] = registers.pc.to_le_bytes();
+    vec![
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.p,
+        registers.s,
+        lo,
+        hi,
+    ]
+}
+
+// Binds 127.0.0.1, port 0 picks a free one
+pub fn listen(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+}
+
+#[derive(Debug)]
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: BTreeSet<u16>,
+    ack: bool,
+    pending: Vec<u8>,
+}
+
+enum Reply {
+    Packet(String),
+    // Already answered
+    Sent,
+    Close,
+}
+
+impl GdbStub {
+    pub fn accept(listener: &TcpListener) -> io::Result<GdbStub> {
+        let (stream, peer) = listener.accept()?;
+        if !peer.ip().is_loopback() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("refusing debugger connection from {}", peer),
+            ));
+        }
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            breakpoints: BTreeSet::new(),
+            ack: true,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Answers packets until the debugger detaches, kills or hangs up
+    pub fn serve(&mut self, cpu: &mut CPU6502) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(cpu, &packet)? {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Sent => {}
+                Reply::Close => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buf = [0u8; 1024];
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+        Ok(Some(self.pending.remove(0)))
+    }
+
+    // Next packet body, None once the connection is closed. Acks, stray
+    // bytes and ^C outside of `continue` are skipped.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.next_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+            let mut body = Vec::new();
+            loop {
+                match self.next_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => body.push(b),
+                }
+            }
+            let mut sum = [0u8; 2];
+            for digit in sum.iter_mut() {
+                *digit = match self.next_byte()? {
+                    Some(b) => b,
+                    None => return Ok(None),
+                };
+            }
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                == Some(checksum(&body));
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", body, checksum(body.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        if self.ack {
+            // Resend until the debugger acknowledges it
+            loop {
+                match self.next_byte()? {
+                    Some(b'+') | None => break,
+                    Some(b'-') => self.stream.write_all(packet.as_bytes())?,
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // True when the debugger sent ^C, checked without blocking
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; 64];
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(0) => Ok(true),
+            Ok(n) => {
+                let interrupt = buf[..n].contains(&0x03);
+                self.pending.extend(buf[..n].iter().filter(|b| **b != 0x03));
+                Ok(interrupt)
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn resume(&mut self, cpu: &mut CPU6502, args: &str, single: bool) -> io::Result<String> {
+        if let Some(addr) = address(args) {
+            cpu.set_registers(Registers {
+                pc: addr,
+                ..cpu.registers()
+            });
+        }
+        cpu.step();
+        if single {
+            return Ok(format!("S{:02x}", SIGTRAP));
+        }
+        let mut steps: usize = 0;
+        loop {
+            if self.breakpoints.contains(&cpu.registers().pc) {
+                return Ok(format!("S{:02x}", SIGTRAP));
+            }
+            steps += 1;
+            if steps.is_multiple_of(POLL_EVERY) && self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            cpu.step();
+        }
+    }
+
+    fn handle(&mut self, cpu: &mut CPU6502, packet: &str) -> io::Result<Reply> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => hex(&register_bytes(&cpu.registers())),
+            "G" => match unhex(args) {
+                Some(bytes) if bytes.len() == 7 => {
+                    cpu.set_registers(Registers {
+                        a: bytes[0],
+                        x: bytes[1],
+                        y: bytes[2],
+                        p: bytes[3],
+                        s: bytes[4],
+                        pc: u16::from_le_bytes([bytes[5], bytes[6]]),
+                    });
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            "p" => match number(args) {
+                Some(5) => hex(&cpu.registers().pc.to_le_bytes()),
+                Some(reg) if reg < 5 => hex(&register_bytes(&cpu.registers())[reg..reg + 1]),
+                _ => String::from("E01"),
+            },
+            "P" => self.write_register(cpu, args),
+            "m" => match memory_range(args) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| cpu.peek(addr.wrapping_add(i as u16)))
+                        .collect();
+                    hex(&bytes)
+                }
+                None => String::from("E01"),
+            },
+            "M" => self.write_memory(cpu, args),
+            "s" => self.resume(cpu, args, true)?,
+            "c" => self.resume(cpu, args, false)?,
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => String::from("OK"),
+            "k" => return Ok(Reply::Close),
+            "D" => {
+                self.send("OK")?;
+                return Ok(Reply::Close);
+            }
+            "Q" if packet == "QStartNoAckMode" => {
+                // The OK itself is still acknowledged
+                self.send("OK")?;
+                self.ack = false;
+                return Ok(Reply::Sent);
+            }
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Ok(Reply::Packet(reply))
+    }
+
+    fn write_register(&mut self, cpu: &mut CPU6502, args: &str) -> String {
+        let parsed = args
+            .split_once('=')
+            .and_then(|(reg, value)| Some((number(reg)?, unhex(value)?)));
+        let mut registers = cpu.registers();
+        match parsed {
+            Some((5, value)) if value.len() == 2 => {
+                registers.pc = u16::from_le_bytes([value[0], value[1]]);
+            }
+            Some((reg, value)) if reg < 5 && value.len() == 1 => {
+                let field = match reg {
+                    0 => &mut registers.a,
+                    1 => &mut registers.x,
+                    2 => &mut registers.y,
+                    3 => &mut registers.p,
+                    _ => &mut registers.s,
+                };
+                *field = value[0];
+            }
+            _ => return String::from("E01"),
+        }
+        cpu.set_registers(registers);
+        String::from("OK")
+    }
+
+    fn write_memory(&mut self, cpu: &mut CPU6502, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = memory_range(range)?;
+            Some((addr, len, unhex(data)?))
+        });
+        match parsed {
+            Some((addr, len, data)) if data.len() == len => {
+                for (i, byte) in data.iter().enumerate() {
+                    cpu.poke(addr.wrapping_add(i as u16), *byte);
+                }
+                String::from("OK")
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields.next().and_then(address);
+        match (kind, addr) {
+            (Some("0"), Some(addr)) | (Some("1"), Some(addr)) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                String::from("OK")
+            }
+            // Watchpoints aren't supported
+            _ => String::new(),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let parsed = range
+                .split_once(',')
+                .and_then(|(off, len)| Some((number(off)?, number(len)?)));
+            return match parsed {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = (start + len).min(xml.len());
+                    let more = if end < xml.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+                }
+                None => String::from("E01"),
+            };
+        }
+        match packet {
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::mc6502::CpuVariant;
+    use super::super::{ram, IoAddressable};
+    use super::*;
+    use std::thread;
+
+    fn build_cpu() -> CPU6502 {
+        // $0300: INX; INX; JMP $0300
+        let mut memory = ram::Ram::init_with_size(0x10000);
+        memory.flash(&[0x03, 0x00, 0xE8, 0xE8, 0x4C, 0x00, 0x03]);
+        memory.poke(0xFFFD, 0x03);
+        let bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0x0000, 0xffff],
+            component: Box::new(memory),
+            name: String::from("RAM"),
+        }]);
+        let mut cpu = CPU6502::init(bus, CpuVariant::Nmos6502);
+        cpu.reset();
+        cpu
+    }
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, body: &str) -> String {
+            let packet = format!("${}#{:02x}", body, checksum(body.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut reply = Vec::new();
+            let mut byte = [0u8; 1];
+            // Ack, then $body#xx
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut sum = [0u8; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(0x37, checksum(b"qSupported"));
+        assert_eq!(Some(vec![0x12, 0xab]), unhex("12ab"));
+        assert_eq!(None, unhex("123"));
+        assert_eq!("00ff", hex(&[0x00, 0xff]));
+    }
+
+    #[test]
+    fn debugs_over_tcp() {
+        let listener = listen(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut gdb = Client {
+                stream: TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap(),
+            };
+            let mut replies = Vec::new();
+            for request in [
+                "qSupported:swbreak+",
+                "g",
+                "P1=40",
+                "p1",
+                "M0010,2:abcd",
+                "m000f,3",
+                "Z0,302,1",
+                "c",
+                "p5",
+                "z0,302,1",
+                "s",
+                "p5",
+                "qXfer:features:read:target.xml:0,10",
+                "vMustReplyEmpty",
+            ] {
+                replies.push(gdb.request(request));
+            }
+            gdb.request("D");
+            replies
+        });
+
+        let mut cpu = build_cpu();
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        stub.serve(&mut cpu).unwrap();
+        let replies = client.join().unwrap();
+
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(14, replies[1].len());
+        assert!(replies[1].ends_with("000003"), "{}", replies[1]);
+        assert_eq!(vec!["OK", "40", "OK", "00abcd"], replies[2..6].to_vec());
+        // Runs INX; INX, stops at the JMP
+        assert_eq!(vec!["OK", "S05", "0203"], replies[6..9].to_vec());
+        assert_eq!(0x42, cpu.registers().x);
+        assert_eq!(vec!["OK", "S05", "0003"], replies[9..12].to_vec());
+        // Offset and length are hex
+        assert_eq!("m<?xml version=\"1", replies[12]);
+        assert_eq!("", replies[13]);
+        assert_eq!(0, stub.breakpoints().count());
+    }
+
+    #[test]
+    fn rejects_bad_memory_packets() {
+        let listener = listen(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut gdb = Client {
+                stream: TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap(),
+            };
+            let mut replies = Vec::new();
+            for request in [
+                "mffffffffffffffff,2",
+                "m0,ffffffff",
+                "m0,801",
+                "M10000,1:00",
+                "M0,801:00",
+                "Mffff,2:abcd",
+                "m0,1",
+                "mffff,2",
+            ] {
+                replies.push(gdb.request(request));
+            }
+            gdb.request("D");
+            replies
+        });
+
+        let mut cpu = build_cpu();
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        stub.serve(&mut cpu).unwrap();
+        let replies = client.join().unwrap();
+
+        assert_eq!(vec!["E01"; 5], replies[..5].to_vec());
+        // Writes and reads wrap around to $0000
+        assert_eq!(vec!["OK", "cd", "abcd"], replies[5..].to_vec());
+    }
+}
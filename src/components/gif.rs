@@ -0,0 +1,301 @@
+use super::frame::Frame;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Animated GIF writer: a global colour table, the NETSCAPE extension so the
+// animation loops, and one LZW-compressed image per frame with its delay in
+// hundredths of a second. Colours missing from the palette map to the
+// nearest entry.
+
+const MAX_CODE: u16 = 4096;
+
+// Packs variable width codes into bytes, LSB first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_code_size + 1;
+    let mut out = BitWriter::default();
+    out.write(clear, width);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let p = match prefix {
+            Some(p) => p,
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+        };
+        if let Some(&code) = dict.get(&(p, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        out.write(p, width);
+        if next < MAX_CODE {
+            dict.insert((p, index), next);
+            next += 1;
+            if next > 1 << width && width < 12 {
+                width += 1;
+            }
+        } else {
+            out.write(clear, width);
+            dict.clear();
+            next = end + 1;
+            width = min_code_size + 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(p) = prefix {
+        out.write(p, width);
+    }
+    out.write(end, width);
+    out.finish()
+}
+
+pub struct GifWriter {
+    out: Box<dyn Write>,
+    width: usize,
+    height: usize,
+    palette: Vec<[u8; 3]>,
+    lookup: HashMap<[u8; 3], u8>,
+    min_code_size: u8,
+}
+
+impl GifWriter {
+    // Writes the header; the palette holds at most 256 colours
+    pub fn init(
+        mut out: Box<dyn Write>,
+        width: usize,
+        height: usize,
+        palette: &[[u8; 3]],
+    ) -> io::Result<GifWriter> {
+        if palette.is_empty() || palette.len() > 256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("GIF palette needs 1 to 256 colours, got {}", palette.len()),
+            ));
+        }
+        // Table size is 2^(bits), at least 2 entries
+        let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(1) as u8;
+        let mut table = palette.to_vec();
+        table.resize(1 << bits, [0, 0, 0]);
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        out.write_all(&[0x80 | ((bits - 1) << 4) | (bits - 1), 0, 0])?;
+        for rgb in &table {
+            out.write_all(rgb)?;
+        }
+        // Loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        let lookup = palette
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, rgb)| (*rgb, i as u8))
+            .collect();
+        Ok(GifWriter {
+            out,
+            width,
+            height,
+            palette: palette.to_vec(),
+            lookup,
+            min_code_size: bits.max(2),
+        })
+    }
+
+    fn index(&mut self, rgb: [u8; 3]) -> u8 {
+        if let Some(index) = self.lookup.get(&rgb) {
+            return *index;
+        }
+        let distance = |p: &[u8; 3]| -> u32 {
+            (0..3)
+                .map(|i| (p[i] as i32 - rgb[i] as i32).pow(2) as u32)
+                .sum()
+        };
+        let (index, _) = self
+            .palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, p)| distance(p))
+            .unwrap_or((0, &[0, 0, 0]));
+        self.lookup.insert(rgb, index as u8);
+        index as u8
+    }
+
+    // `delay` is in hundredths of a second
+    pub fn frame(&mut self, frame: &Frame, delay: u16) -> io::Result<()> {
+        if frame.width() != self.width || frame.height() != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{}, the GIF is {}x{}",
+                    frame.width(),
+                    frame.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+        let indices: Vec<u8> = frame
+            .pixels()
+            .chunks_exact(3)
+            .map(|p| self.index([p[0], p[1], p[2]]))
+            .collect();
+
+        // Graphic control extension, then the image descriptor
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00, 0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, self.min_code_size])?;
+        for block in lzw(&indices, self.min_code_size).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::Shared;
+    use super::*;
+
+    // Reference decoder, the way GIF readers do it
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let initial: Vec<Vec<u8>> = (0..=end).map(|i| vec![i as u8]).collect();
+        let mut table = initial.clone();
+        let mut width = min_code_size + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let (mut acc, mut bits, mut at) = (0u32, 0u8, 0);
+        loop {
+            while bits < width {
+                acc |= (data[at] as u32) << bits;
+                at += 1;
+                bits += 8;
+            }
+            let code = (acc & ((1 << width) - 1)) as usize;
+            acc >>= width;
+            bits -= width;
+
+            if code == clear {
+                table = initial.clone();
+                width = min_code_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [prev.clone(), vec![prev[0]]].concat(),
+                (None, None) => panic!("bad code {}", code),
+            };
+            if let Some(prev) = prev {
+                if table.len() < MAX_CODE as usize {
+                    table.push([prev, vec![entry[0]]].concat());
+                }
+            }
+            if table.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        // Enough varied data to fill the table and clear it a few times
+        let mut seed = 1u32;
+        let indices: Vec<u8> = (0..50_000)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                if i % 7 < 3 {
+                    0
+                } else {
+                    ((seed >> 16) % 5) as u8
+                }
+            })
+            .collect();
+        assert_eq!(indices, unlzw(&lzw(&indices, 3), 3));
+        assert_eq!(vec![1u8; 10], unlzw(&lzw(&[1; 10], 2), 2));
+        assert!(unlzw(&lzw(&[], 2), 2).is_empty());
+    }
+
+    #[test]
+    fn writes_animation() {
+        let out = Shared::default();
+        let palette = [[0, 0, 0], [0xFF, 0xFF, 0xFF], [0x80, 0x80, 0x80]];
+        let mut gif = GifWriter::init(Box::new(out.clone()), 4, 2, &palette).unwrap();
+        let mut frame = Frame::init(4, 2);
+        gif.frame(&frame, 10).unwrap();
+        frame.set_pixel(1, 1, [0xFF, 0xFF, 0xFF]);
+        frame.set_pixel(2, 1, [0x70, 0x70, 0x90]);
+        gif.frame(&frame, 25).unwrap();
+        assert!(gif.frame(&Frame::init(2, 2), 10).is_err());
+        gif.finish().unwrap();
+
+        let data = out.bytes();
+        assert_eq!(b"GIF89a\x04\x00\x02\x00", &data[..10]);
+        // Four entry table: flag, colour resolution and size of 2 bits
+        assert_eq!(0x91, data[10]);
+        assert_eq!(Some(&0x3B), data.last());
+
+        // Second image: delay 25, then its pixels
+        let second = data
+            .windows(4)
+            .rposition(|w| w == [0x21, 0xF9, 0x04, 0x00])
+            .unwrap();
+        assert_eq!([25, 0], data[second + 4..second + 6]);
+        let image = second + 8;
+        assert_eq!(0x2C, data[image]);
+        let min_code_size = data[image + 10];
+        let len = data[image + 11] as usize;
+        let pixels = unlzw(&data[image + 12..image + 12 + len], min_code_size);
+        assert_eq!(vec![0, 0, 0, 0, 0, 1, 2, 0], pixels);
+
+        assert!(GifWriter::init(Box::new(out), 1, 1, &[]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Golden file snapshots, mostly for `Display::screen_text`. Setting
+// UPDATE_GOLDENS=1 rewrites the stored files instead of comparing.
+
+pub const UPDATE_ENV: &str = "UPDATE_GOLDENS";
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(PathBuf, io::Error),
+    Mismatch(PathBuf, String),
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoldenError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            GoldenError::Mismatch(path, diff) => write!(
+                f,
+                "{} does not match (rerun with {}=1 to accept):\n{}",
+                path.display(),
+                UPDATE_ENV,
+                diff
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+pub fn update_requested() -> bool {
+    matches!(env::var(UPDATE_ENV), Ok(value) if !value.is_empty() && value != "0")
+}
+
+// Line by line diff, `-` for the golden file and `+` for the actual output
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+
+    for i in 0..expected.len().max(actual.len()) {
+        let (want, got) = (expected.get(i), actual.get(i));
+        if want == got {
+            continue;
+        }
+        if let Some(want) = want {
+            out.push_str(&format!("{:>3} -|{}|\n", i + 1, want));
+        }
+        if let Some(got) = got {
+            out.push_str(&format!("{:>3} +|{}|\n", i + 1, got));
+        }
+    }
+    out
+}
+
+pub fn compare_with<P: AsRef<Path>>(
+    actual: &str,
+    path: P,
+    update: bool,
+) -> Result<(), GoldenError> {
+    let path = path.as_ref();
+    if update {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| GoldenError::Io(dir.to_path_buf(), err))?;
+        }
+        return fs::write(path, actual).map_err(|err| GoldenError::Io(path.to_path_buf(), err));
+    }
+
+    let expected =
+        fs::read_to_string(path).map_err(|err| GoldenError::Io(path.to_path_buf(), err))?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let mut report = diff(&expected, actual);
+    if report.is_empty() {
+        report = String::from("(only line endings differ)\n");
+    }
+    Err(GoldenError::Mismatch(path.to_path_buf(), report))
+}
+
+pub fn compare<P: AsRef<Path>>(actual: &str, path: P) -> Result<(), GoldenError> {
+    compare_with(actual, path, update_requested())
+}
+
+pub fn assert_golden<P: AsRef<Path>>(actual: &str, path: P) {
+    if let Err(err) = compare(actual, path) {
+        panic!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::display::Display;
+    use super::super::IoComponent;
+    use super::*;
+
+    fn temp_golden(name: &str) -> PathBuf {
+        env::temp_dir()
+            .join(format!("apple1_rst-{}", std::process::id()))
+            .join(name)
+    }
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        assert_eq!("", diff("A\nB\n", "A\nB\n"));
+        assert_eq!(
+            "  2 -|B|\n  2 +|C|\n  3 +|D|\n",
+            diff("A\nB\n", "A\nC\nD\n")
+        );
+    }
+
+    #[test]
+    fn update_then_compare() {
+        let path = temp_golden("update_then_compare.txt");
+        compare_with("\\\nHELLO\n", &path, true).unwrap();
+        compare_with("\\\nHELLO\n", &path, false).unwrap();
+
+        match compare_with("\\\nWORLD\n", &path, false) {
+            Err(GoldenError::Mismatch(_, report)) => {
+                assert_eq!("  2 -|HELLO|\n  2 +|WORLD|\n", report)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn screen_matches_golden() {
+        let mut display = Display::init(false);
+        for value in b"\\\rE000R\rE000: 4C\r" {
+            display.output(*value);
+        }
+        assert_golden(
+            &display.screen_text(),
+            "testdata/goldens/monitor_examine.txt",
+        );
+    }
+}
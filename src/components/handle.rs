@@ -0,0 +1,135 @@
+use super::scheduler::Scheduler;
+use super::{ControlLines, IoAddressable, IoComponent};
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+// Shares a device between the bus and the host front-end: wire one clone into
+// the PIA or address map and keep another to poke at the device's state.
+#[derive(Debug, Default)]
+pub struct DeviceHandle<T> {
+    device: Rc<RefCell<T>>,
+}
+
+impl<T> Clone for DeviceHandle<T> {
+    fn clone(&self) -> Self {
+        DeviceHandle {
+            device: self.device.clone(),
+        }
+    }
+}
+
+impl<T> DeviceHandle<T> {
+    pub fn new(device: T) -> DeviceHandle<T> {
+        DeviceHandle {
+            device: Rc::new(RefCell::new(device)),
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.device.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.device.borrow_mut()
+    }
+}
+
+impl<T: IoComponent> IoComponent for DeviceHandle<T> {
+    fn input(&mut self) -> u8 {
+        self.device.borrow_mut().input()
+    }
+
+    fn output(&mut self, lines: u8) {
+        self.device.borrow_mut().output(lines);
+    }
+
+    fn c2_output(&mut self, level: bool) {
+        self.device.borrow_mut().c2_output(level);
+    }
+
+    fn sync(&mut self, cycles: usize) -> ControlLines {
+        self.device.borrow_mut().sync(cycles)
+    }
+}
+
+impl<T: IoAddressable> IoAddressable for DeviceHandle<T> {
+    fn read(&mut self, address: usize) -> u8 {
+        self.device.borrow_mut().read(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        self.device.borrow_mut().write(address, value);
+    }
+
+    fn flash(&mut self, data: &[u8]) {
+        self.device.borrow_mut().flash(data);
+    }
+
+    fn sync(&mut self, cycles: usize) {
+        self.device.borrow_mut().sync(cycles);
+    }
+
+    fn attach_scheduler(&mut self, scheduler: &Scheduler) {
+        self.device.borrow_mut().attach_scheduler(scheduler);
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self.device.borrow().peek(address)
+    }
+
+    fn poke(&mut self, address: usize, value: u8) {
+        self.device.borrow_mut().poke(address, value);
+    }
+
+    fn irq(&self) -> bool {
+        self.device.borrow().irq()
+    }
+
+    fn register_names(&self) -> Vec<(usize, String)> {
+        self.device.borrow().register_names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ram::Ram;
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Switches {
+        value: u8,
+        lamps: u8,
+    }
+
+    impl IoComponent for Switches {
+        fn input(&mut self) -> u8 {
+            self.value
+        }
+
+        fn output(&mut self, lines: u8) {
+            self.lamps = lines;
+        }
+    }
+
+    #[test]
+    fn clones_share_device() {
+        let handle = DeviceHandle::new(Switches::default());
+        let mut wired: Box<dyn IoComponent> = Box::new(handle.clone());
+
+        handle.borrow_mut().value = 0x5A;
+        assert_eq!(0x5A, wired.input());
+        wired.output(0x0F);
+        assert_eq!(0x0F, handle.borrow().lamps);
+        assert_eq!(ControlLines::default(), wired.sync(0));
+    }
+
+    #[test]
+    fn shares_bus_devices() {
+        let handle = DeviceHandle::new(Ram::init_with_size(0x10));
+        let mut wired: Box<dyn IoAddressable> = Box::new(handle.clone());
+
+        wired.write(3, 0x42);
+        assert_eq!(0x42, handle.borrow_mut().read(3));
+        assert!(!wired.irq());
+    }
+}
@@ -0,0 +1,325 @@
+use super::address_spaces::{AddressMap, AddressSpaces};
+use super::config::{Config, ConfigError};
+use super::display::Display;
+use super::feeder::InputFeeder;
+use super::mc6502::{CpuVariant, CPU6502};
+use super::pia6820::Pia6820;
+use super::profiles::{Profile, ProfileError};
+use super::symbols::Symbols;
+use super::{Clockable, IoAddressable};
+
+// Keyboard/display PIA, KBD at $D010 through DSPCR at $D013
+pub const PIA_ADDR: [u16; 2] = [0xD010, 0xD013];
+
+// A CPU plus the Apple-1 terminal wired through the PIA
+#[derive(Debug)]
+pub struct Machine {
+    pub cpu: CPU6502,
+    pub feeder: InputFeeder,
+    pub display: Display,
+    // Names for the tracer, profiler and scripts, the Woz Monitor's unless
+    // the builder added more
+    pub symbols: Symbols,
+}
+
+impl Machine {
+    pub fn init(the_mapping: Vec<AddressMap>, variant: CpuVariant, echo: bool) -> Machine {
+        Machine::init_with_pia(the_mapping, variant, echo, PIA_ADDR)
+    }
+
+    // Same, with the keyboard/display PIA somewhere other than $D010
+    pub fn init_with_pia(
+        mut the_mapping: Vec<AddressMap>,
+        variant: CpuVariant,
+        echo: bool,
+        pia_addr: [u16; 2],
+    ) -> Machine {
+        let display = Display::init(echo);
+        let feeder = InputFeeder::init(Some(display.clone()));
+
+        let mut pia = Pia6820::new();
+        pia.wire_ioa(Some(Box::new(feeder.clone())));
+        pia.wire_iob(Some(Box::new(display.clone())));
+        the_mapping.insert(
+            0,
+            AddressMap {
+                addr: pia_addr,
+                component: Box::new(pia),
+                name: String::from("PIA"),
+            },
+        );
+
+        Machine {
+            cpu: CPU6502::init(AddressSpaces::init(the_mapping), variant),
+            feeder,
+            display,
+            symbols: Symbols::woz(),
+        }
+    }
+
+    pub fn from_profile(
+        profile: &Profile,
+        images: &[(&str, &[u8])],
+        variant: CpuVariant,
+        echo: bool,
+    ) -> Result<Machine, ProfileError> {
+        Ok(Machine::init(profile.build(images)?, variant, echo))
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn step(&mut self) -> usize {
+        self.cpu.step()
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cpu.get_cycles()
+    }
+
+    // Runs whole instructions until at least `cycles` more have elapsed
+    pub fn run_cycles(&mut self, cycles: usize) {
+        let until = self.cycles() + cycles;
+        while self.cycles() < until {
+            self.step();
+        }
+    }
+
+    // Pokes, so images can be patched into ROM too
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, value) in data.iter().enumerate() {
+            self.cpu.poke(addr.wrapping_add(i as u16), *value);
+        }
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.peek(addr)
+    }
+}
+
+// Assembles a machine from a profile, a config file and/or hand made
+// address maps:
+//
+//   let machine = MachineBuilder::new()
+//       .profile("woz")?
+//       .image("monitor", &rom)
+//       .build()?;
+//
+//   let machine = MachineBuilder::new()
+//       .config(&Config::load("replica1.toml")?)?
+//       .build()?;
+#[derive(Debug)]
+pub struct MachineBuilder {
+    profile: Option<&'static Profile>,
+    images: Vec<(String, Vec<u8>)>,
+    maps: Vec<AddressMap>,
+    variant: CpuVariant,
+    pia_addr: [u16; 2],
+    echo: bool,
+    display_cycles: Option<usize>,
+    symbols: Symbols,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        MachineBuilder::new()
+    }
+}
+
+impl MachineBuilder {
+    pub fn new() -> MachineBuilder {
+        MachineBuilder {
+            profile: None,
+            images: Vec::new(),
+            maps: Vec::new(),
+            variant: CpuVariant::Nmos6502,
+            pia_addr: PIA_ADDR,
+            echo: false,
+            display_cycles: None,
+            symbols: Symbols::woz(),
+        }
+    }
+
+    pub fn profile(mut self, name: &str) -> Result<MachineBuilder, ProfileError> {
+        self.profile = Some(Profile::by_name(name)?);
+        Ok(self)
+    }
+
+    pub fn with_profile(mut self, profile: &'static Profile) -> MachineBuilder {
+        self.profile = Some(profile);
+        self
+    }
+
+    // Image for one of the profile's slots
+    pub fn image(mut self, slot: &str, data: &[u8]) -> MachineBuilder {
+        self.images.push((slot.to_string(), data.to_vec()));
+        self
+    }
+
+    // Extra component, mapped after the profile's own. Its register names
+    // become symbols.
+    pub fn map(
+        mut self,
+        name: &str,
+        addr: [u16; 2],
+        component: Box<dyn IoAddressable>,
+    ) -> MachineBuilder {
+        for (offset, register) in component.register_names() {
+            self.symbols
+                .insert(&register, addr[0].wrapping_add(offset as u16));
+        }
+        self.maps.push(AddressMap {
+            addr,
+            component,
+            name: name.to_string(),
+        });
+        self
+    }
+
+    pub fn variant(mut self, variant: CpuVariant) -> MachineBuilder {
+        self.variant = variant;
+        self
+    }
+
+    pub fn pia(mut self, addr: [u16; 2]) -> MachineBuilder {
+        self.pia_addr = addr;
+        self
+    }
+
+    // On top of the Woz Monitor symbols
+    pub fn symbols(mut self, symbols: &Symbols) -> MachineBuilder {
+        self.symbols.extend(symbols);
+        self
+    }
+
+    // CPU, PIA, display timing, memory map and symbols from a config file,
+    // loading its images
+    pub fn config(mut self, config: &Config) -> Result<MachineBuilder, ConfigError> {
+        self.maps.extend(config.address_maps()?);
+        self.symbols.extend(&config.load_symbols()?);
+        self.variant = config.variant();
+        self.pia_addr = config.pia_addr();
+        self.display_cycles = config.display_cycles.or(self.display_cycles);
+        Ok(self)
+    }
+
+    // Also print display output to stdout
+    pub fn echo(mut self, echo: bool) -> MachineBuilder {
+        self.echo = echo;
+        self
+    }
+
+    // Keep the display busy for this many cycles per character, like the
+    // real terminal. Off by default so scripted runs go fast.
+    pub fn display_cycles(mut self, cycles: usize) -> MachineBuilder {
+        self.display_cycles = Some(cycles);
+        self
+    }
+
+    // Builds and resets the machine
+    pub fn build(self) -> Result<Machine, ProfileError> {
+        let mut the_mapping = match self.profile {
+            Some(profile) => {
+                let images: Vec<(&str, &[u8])> = self
+                    .images
+                    .iter()
+                    .map(|(slot, data)| (slot.as_str(), data.as_slice()))
+                    .collect();
+                profile.build(&images)?
+            }
+            None => Vec::new(),
+        };
+        the_mapping.extend(self.maps);
+
+        let mut machine =
+            Machine::init_with_pia(the_mapping, self.variant, self.echo, self.pia_addr);
+        if let Some(cycles) = self.display_cycles {
+            machine
+                .display
+                .attach_scheduler(machine.cpu.scheduler(), cycles);
+        }
+        machine.symbols = self.symbols;
+        machine.reset();
+        Ok(machine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ram::Ram;
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let the_mapping = vec![AddressMap {
+            addr: [0x0000, 0x0fff],
+            component: Box::new(Ram::init_with_size(0x1000)),
+            name: String::from("RAM"),
+        }];
+        let mut machine = Machine::init(the_mapping, CpuVariant::Nmos6502, false);
+        assert_eq!(0, machine.cycles());
+
+        machine.load(0x0300, &[0xA9, 0x01]);
+        assert_eq!(0x01, machine.cpu.address_spaces().read(0x0301));
+        // KBDCR
+        assert_eq!(0x00, machine.cpu.address_spaces().read(0xD011));
+    }
+
+    #[test]
+    fn builds_from_profile() {
+        let mut monitor = vec![0xEA; 0x100];
+        monitor[0xFC] = 0x00;
+        monitor[0xFD] = 0xFF;
+
+        let mut machine = MachineBuilder::new()
+            .profile("woz")
+            .unwrap()
+            .image("monitor", &monitor)
+            .map(
+                "EXTRA",
+                [0xE000, 0xE0FF],
+                Box::new(Ram::init_with_size(0x100)),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(0xFF00, machine.cpu.registers().pc);
+
+        machine.load(0xE000, &[0x42]);
+        assert_eq!(0x42, machine.cpu.address_spaces().read(0xE000));
+
+        let err = MachineBuilder::new().profile("apple3").unwrap_err();
+        assert_eq!(ProfileError::UnknownProfile(String::from("apple3")), err);
+    }
+
+    #[test]
+    fn builds_from_config() {
+        let dir = std::env::temp_dir().join(format!("machine-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("machine.sym"), "START = $0300\n").unwrap();
+        let mut config = Config::parse(
+            "cpu = \"65c02\"\n\
+             symbols = [\"machine.sym\"]\n\
+             [[ram]]\nstart = 0x0000\nsize = 0x1000\n\
+             [[ram]]\nstart = 0xF000\nend = 0xFFFF\n\
+             [pia]\nstart = 0xA000\n",
+        )
+        .unwrap();
+        config.base = dir.clone();
+        let mut machine = MachineBuilder::new()
+            .config(&config)
+            .unwrap()
+            .build()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(CpuVariant::Cmos65C02, machine.cpu.variant());
+        assert_eq!(Some(0x0300), machine.symbols.get("START"));
+        assert_eq!(Some(0xFFEF), machine.symbols.get("ECHO"));
+
+        // KBDCR moved with the PIA
+        machine.cpu.address_spaces().write(0xA001, 0x27);
+        assert_eq!(0x27, machine.cpu.address_spaces().read(0xA001));
+        machine.load(0xF000, &[0x42]);
+        assert_eq!(0x42, machine.peek(0xF000));
+    }
+}
@@ -1,10 +1,31 @@
 use super::address_spaces::AddressSpaces;
+use super::scheduler::Scheduler;
 use super::Clockable;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVariant {
+    // Original NMOS part, undocumented opcodes included
+    Nmos6502,
+    // WDC 65C02 with the Rockwell bit instructions
+    Cmos65C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+}
+
 #[derive(Debug)]
 #[allow(non_snake_case)]
 pub struct CPU6502 {
     address_spaces: AddressSpaces,
+    scheduler: Scheduler,
+    variant: CpuVariant,
     PC: u16,
 
     A: u8,
@@ -28,12 +49,19 @@ pub struct CPU6502 {
 
     opcode: u8,
     cycles: usize,
+
+    waiting: bool,
+    stopped: bool,
 }
 
 impl CPU6502 {
-    pub fn init(address_spaces: AddressSpaces) -> CPU6502 {
-        let cpu = CPU6502 {
-            address_spaces: address_spaces,
+    pub fn init(mut address_spaces: AddressSpaces, variant: CpuVariant) -> CPU6502 {
+        let scheduler = Scheduler::init();
+        address_spaces.attach_scheduler(&scheduler);
+        CPU6502 {
+            address_spaces,
+            scheduler,
+            variant,
             PC: 0,
             A: 0,
             X: 0,
@@ -51,9 +79,17 @@ impl CPU6502 {
             addr: 0,
             opcode: 0,
             cycles: 0,
-        };
+            waiting: false,
+            stopped: false,
+        }
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
 
-        return cpu;
+    fn is_cmos(&self) -> bool {
+        self.variant == CpuVariant::Cmos65C02
     }
 
     ////////////////////////////////////////////////////////////////////////////////
@@ -79,43 +115,68 @@ impl CPU6502 {
         }
     }
 
-    fn ind(&mut self) -> () {
+    fn ind(&mut self) {
         let mut a = self.read16(self.PC);
-        a |= (self.read16((self.PC & 0xff00) | ((self.PC.wrapping_add(1)) & 0xff))) << 8;
+        a |= (self.read16(self.PC.wrapping_add(1))) << 8;
         self.addr = self.read16(a);
-        self.addr |= (self.read16(a.wrapping_add(1))) << 8;
+        // NMOS parts don't carry into the pointer high byte when it sits at $xxFF
+        let hi = if self.is_cmos() {
+            self.cycles += 1;
+            a.wrapping_add(1)
+        } else {
+            (a & 0xff00) | (a.wrapping_add(1) & 0xff)
+        };
+        self.addr |= (self.read16(hi)) << 8;
+        self.cycles += 6;
+    }
+
+    // 65C02 (zp)
+    fn izp(&mut self) {
+        let a: u16 = self.read16(self.PC);
+        self.PC += 1;
+        self.addr = (self.read16((a.wrapping_add(1)) & 0xFF) << 8) | self.read16(a);
         self.cycles += 5;
     }
 
-    fn zp(&mut self) -> () {
+    // 65C02 (abs,X)
+    fn iax(&mut self) {
+        let mut a = self.read16(self.PC);
+        a |= (self.read16(self.PC.wrapping_add(1))) << 8;
+        a = a.wrapping_add(self.X as u16);
+        self.addr = self.read16(a);
+        self.addr |= (self.read16(a.wrapping_add(1))) << 8;
+        self.cycles += 7;
+    }
+
+    fn zp(&mut self) {
         self.addr = self.read16(self.PC);
         self.PC += 1;
         self.cycles += 3;
     }
 
-    fn zpx(&mut self) -> () {
+    fn zpx(&mut self) {
         self.addr = (self.read16(self.PC) + self.X as u16) & 0xff;
         self.PC += 1;
         self.cycles += 4;
     }
 
-    fn zpy(&mut self) -> () {
+    fn zpy(&mut self) {
         self.addr = (self.read16(self.PC) + self.Y as u16) & 0xff;
         self.PC += 1;
         self.cycles += 4;
     }
 
-    fn imp(&mut self) -> () {
+    fn imp(&mut self) {
         self.cycles += 2;
     }
 
-    fn imm(&mut self) -> () {
+    fn imm(&mut self) {
         self.addr = self.PC;
         self.PC += 1;
         self.cycles += 2;
     }
 
-    fn abs(&mut self) -> () {
+    fn abs(&mut self) {
         self.addr = self.read16(self.PC);
         self.PC += 1;
         self.addr |= (self.read16(self.PC)) << 8;
@@ -123,7 +184,7 @@ impl CPU6502 {
         self.cycles += 4;
     }
 
-    fn abx(&mut self) -> () {
+    fn abx(&mut self) {
         let mut paddr = self.read16(self.PC);
         self.PC += 1;
         paddr |= (self.read16(self.PC)) << 8;
@@ -136,7 +197,7 @@ impl CPU6502 {
         }
     }
 
-    fn aby(&mut self) -> () {
+    fn aby(&mut self) {
         let mut paddr = self.read16(self.PC);
         self.PC += 1;
         paddr |= (self.read16(self.PC)) << 8;
@@ -149,45 +210,45 @@ impl CPU6502 {
         }
     }
 
-    fn rel(&mut self) -> () {
+    fn rel(&mut self) {
         self.addr = self.read16(self.PC);
         self.PC += 1;
         if self.addr & 0x80 != 0 {
-            self.addr -= 0x100;
+            self.addr = self.addr.wrapping_sub(0x100);
         }
-        self.addr += self.PC;
+        self.addr = self.addr.wrapping_add(self.PC);
         self.cycles += 2;
     }
 
     ////////////////////////////////////////////////////////////////////////////////
 
-    fn rmw(&mut self) -> () {
+    fn rmw(&mut self) {
         self.write(self.addr, (self.tmp & 0xff) as u8);
         self.cycles += 2;
     }
 
     ////////////////////////////////////////////////////////////////////////////////
 
-    fn fnz(&mut self, v: u16) -> () {
+    fn fnz(&mut self, v: u16) {
         self.Z = (v & 0xFF) == 0;
         self.N = (v & 0x80) != 0;
     }
 
     // Borrow
-    fn fnzb(&mut self, v: u16) -> () {
+    fn fnzb(&mut self, v: u16) {
         self.Z = (v & 0xFF) == 0;
         self.N = (v & 0x80) != 0;
         self.C = (v & 0x100) == 0;
     }
 
     // Carry
-    fn fnzc(&mut self, v: u16) -> () {
+    fn fnzc(&mut self, v: u16) {
         self.Z = (v & 0xFF) == 0;
         self.N = (v & 0x80) != 0;
         self.C = (v & 0x100) != 0;
     }
 
-    fn branch(&mut self, taken: bool) -> () {
+    fn branch(&mut self, taken: bool) {
         if taken {
             if (self.addr & 0x100) != (self.PC & 0x100) {
                 self.cycles += 2;
@@ -201,67 +262,73 @@ impl CPU6502 {
     ////////////////////////////////////////////////////////////////////////////////
     // Subroutines - instructions
     ////////////////////////////////////////////////////////////////////////////////
-    fn adc(&mut self) -> () {
+    fn adc(&mut self) {
         let v = self.read(self.addr);
+        self.add_with_carry(v);
+    }
+
+    fn add_with_carry(&mut self, v: u8) {
         let c = if self.C { 1 } else { 0 };
-        let r = self.A.wrapping_add(v).wrapping_add(c);
+        let r = self.A as u16 + v as u16 + c;
         if self.D {
-            let mut al = (self.A & 0x0F).wrapping_add(v & 0x0F).wrapping_add(c);
+            let mut al = (self.A as u16 & 0x0F) + (v as u16 & 0x0F) + c;
             if al > 9 {
-                al = al.wrapping_add(6);
+                al += 6;
             };
-            let mut ah =
-                (self.A >> 4)
-                    .wrapping_add(v >> 4)
-                    .wrapping_add(if al > 15 { 1 } else { 0 });
+            let mut ah = (self.A as u16 >> 4) + (v as u16 >> 4) + (if al > 15 { 1 } else { 0 });
+            // NMOS derives Z from the binary sum and N/V from the unadjusted high nibble
             self.Z = (r & 0xFF) == 0;
             self.N = (ah & 8) != 0;
-            self.V = (!(self.A ^ v) & (self.A ^ (ah << 4)) & 0x80) != 0;
+            self.V = (!(self.A as u16 ^ v as u16) & (self.A as u16 ^ (ah << 4)) & 0x80) != 0;
             if ah > 9 {
-                ah = ah.wrapping_add(6);
+                ah += 6;
             };
             self.C = ah > 15;
-            self.A = ((ah << 4) | (al & 15)) & 0xFF;
+            self.A = ((ah << 4) | (al & 15)) as u8;
+            if self.is_cmos() {
+                self.fnz(self.A as u16);
+                self.cycles += 1;
+            }
         } else {
             self.Z = (r & 0xFF) == 0;
             self.N = (r & 0x80) != 0;
-            self.V = (!(self.A ^ v) & (self.A ^ r) & 0x80) != 0;
-            self.C = (r as u16 & 0x100) != 0;
-            self.A = r & 0xFF;
+            self.V = (!(self.A as u16 ^ v as u16) & (self.A as u16 ^ r) & 0x80) != 0;
+            self.C = (r & 0x100) != 0;
+            self.A = r as u8;
         }
     }
 
-    fn ahx(&mut self) -> () {
+    fn ahx(&mut self) {
         self.tmp = ((self.addr >> 8).wrapping_add(1)) & self.A as u16 & self.X as u16;
         self.write(self.addr, self.tmp as u8);
     }
 
-    fn alr(&mut self) -> () {
+    fn alr(&mut self) {
         self.tmp = self.read16(self.addr) & self.A as u16;
         self.tmp = ((self.tmp & 1) << 8) | (self.tmp >> 1);
         self.fnzc(self.tmp);
         self.A = self.tmp as u8;
     }
 
-    fn anc(&mut self) -> () {
+    fn anc(&mut self) {
         self.tmp = self.read16(self.addr);
         self.tmp |= ((self.tmp & 0x80) & (self.A as u16 & 0x80)) << 1;
         self.fnzc(self.tmp);
         self.A = self.tmp as u8;
     }
 
-    fn _and(&mut self) -> () {
+    fn _and(&mut self) {
         self.A &= self.read(self.addr);
         self.fnz(self.A as u16);
     }
 
-    fn ane(&mut self) -> () {
+    fn ane(&mut self) {
         self.tmp = self.read16(self.addr) & self.A as u16 & (self.A as u16 | 0xEE);
         self.fnz(self.tmp);
         self.A = self.tmp as u8;
     }
 
-    fn arr(&mut self) -> () {
+    fn arr(&mut self) {
         self.tmp = self.read16(self.addr) & self.A as u16;
         self.C = (self.tmp & 0x80) != 0;
         self.V = (((self.tmp >> 7) & 1) ^ ((self.tmp >> 6) & 1)) != 0;
@@ -283,45 +350,57 @@ impl CPU6502 {
         self.A = self.tmp as u8;
     }
 
-    fn asl(&mut self) -> () {
+    fn asl(&mut self) {
         self.tmp = self.read16(self.addr) << 1;
         self.fnzc(self.tmp);
         self.tmp &= 0xFF;
     }
-    fn asla(&mut self) -> () {
+    fn asla(&mut self) {
         self.tmp = (self.A as u16) << 1;
         self.fnzc(self.tmp);
         self.A = self.tmp as u8;
     }
 
-    fn bit(&mut self) -> () {
+    fn bit(&mut self) {
         self.tmp = self.read16(self.addr);
         self.N = (self.tmp & 0x80) != 0;
         self.V = (self.tmp & 0x40) != 0;
         self.Z = (self.tmp & self.A as u16) == 0;
     }
 
-    fn brk(&mut self) -> () {
+    fn brk(&mut self) {
         self.PC += 1;
         self.write(self.S as u16 + 0x100, (self.PC >> 8) as u8);
         self.S = self.S.wrapping_sub(1);
         self.write(self.S as u16 + 0x100, self.PC as u8);
         self.S = self.S.wrapping_sub(1);
-        let mut v = if self.N { 1 << 7 } else { 0 };
-        v |= if self.V { 1 << 6 } else { 0 };
-        v |= 3 << 4;
-        v |= if self.D { 1 << 3 } else { 0 };
-        v |= if self.I { 1 << 2 } else { 0 };
-        v |= if self.Z { 1 << 1 } else { 0 };
-        v |= if self.C { 1 } else { 0 };
-        self.write(self.S as u16 + 0x100, v);
+        // B is only ever set on the pushed copy
+        self.write(self.S as u16 + 0x100, self.status() | 0x10);
         self.S = self.S.wrapping_sub(1);
         self.I = true;
-        self.D = false;
+        if self.is_cmos() {
+            self.D = false;
+        }
         self.PC = (self.read16(0xFFFF) << 8) | self.read16(0xFFFE);
         self.cycles += 5;
     }
 
+    // IRQ/NMI entry: like BRK but with B clear on the pushed status
+    fn interrupt(&mut self, vector: u16) {
+        self.write(self.S as u16 + 0x100, (self.PC >> 8) as u8);
+        self.S = self.S.wrapping_sub(1);
+        self.write(self.S as u16 + 0x100, self.PC as u8);
+        self.S = self.S.wrapping_sub(1);
+        self.write(self.S as u16 + 0x100, self.status() & !0x10);
+        self.S = self.S.wrapping_sub(1);
+        self.I = true;
+        if self.is_cmos() {
+            self.D = false;
+        }
+        self.PC = (self.read16(vector + 1) << 8) | self.read16(vector);
+        self.cycles += 7;
+    }
+
     fn bcc(&mut self) {
         self.branch(!self.C);
     }
@@ -416,32 +495,9 @@ impl CPU6502 {
         self.fnz(self.Y as u16);
     }
 
-    // TODO: CHECK THIS
     fn isc(&mut self) {
-        let v: u16 = self.read16(self.addr) + 1;
-        let c: u16 = 1 - (if self.C { 1 } else { 0 });
-        let r: u16 = self.A as u16 - v - c;
-        if self.D {
-            let mut al = (self.A as u16 & 0x0F) - (v & 0x0F) - c;
-            if al > 0x80 {
-                al -= 6
-            };
-            let mut ah = (self.A as u16 >> 4) - (v >> 4) - (if al > 0x80 { 1 } else { 0 });
-            self.Z = (r & 0xFF) == 0;
-            self.N = (r & 0x80) != 0;
-            self.V = ((self.A as u16 ^ v) & (self.A as u16 ^ r) & 0x80) != 0;
-            self.C = if r & 0x100 != 0 { false } else { true };
-            if ah > 0x80 {
-                ah -= 6
-            };
-            self.A = ((ah << 4) | (al & 15)) as u8;
-        } else {
-            self.Z = (r & 0xFF) == 0;
-            self.N = (r & 0x80) != 0;
-            self.V = ((self.A as u16 ^ v) & (self.A as u16 ^ r) & 0x80) != 0;
-            self.C = if (r & 0x100) != 0 { false } else { true };
-            self.A = r as u8;
-        }
+        self.tmp = (self.read16(self.addr).wrapping_add(1)) & 0xFF;
+        self.subtract_with_borrow(self.tmp as u8);
     }
 
     fn jmp(&mut self) {
@@ -540,14 +596,8 @@ impl CPU6502 {
     }
 
     fn php(&mut self) {
-        let mut v = if self.N { 1 << 7 } else { 0 };
-        v |= if self.V { 1 << 6 } else { 0 };
-        v |= 3 << 4;
-        v |= if self.D { 1 << 3 } else { 0 };
-        v |= if self.I { 1 << 2 } else { 0 };
-        v |= if self.Z { 1 << 1 } else { 0 };
-        v |= if self.C { 1 } else { 0 };
-        self.write(self.S as u16 + 0x100, v);
+        // B is only ever set on the pushed copy
+        self.write(self.S as u16 + 0x100, self.status() | 0x10);
         self.S = self.S.wrapping_sub(1);
         self.cycles += 1;
     }
@@ -562,24 +612,14 @@ impl CPU6502 {
     fn plp(&mut self) {
         self.S = self.S.wrapping_add(1);
         self.tmp = self.read16(self.S as u16 + 0x100);
-        self.N = (self.tmp & 0x80) != 0;
-        self.V = (self.tmp & 0x40) != 0;
-        self.D = (self.tmp & 0x08) != 0;
-        self.I = (self.tmp & 0x04) != 0;
-        self.Z = (self.tmp & 0x02) != 0;
-        self.C = (self.tmp & 0x01) != 0;
+        self.set_status(self.tmp as u8);
         self.cycles += 2;
     }
 
     fn rti(&mut self) {
         self.S = self.S.wrapping_add(1);
         self.tmp = self.read16(self.S as u16 + 0x100);
-        self.N = (self.tmp & 0x80) != 0;
-        self.V = (self.tmp & 0x40) != 0;
-        self.D = (self.tmp & 0x08) != 0;
-        self.I = (self.tmp & 0x04) != 0;
-        self.Z = (self.tmp & 0x02) != 0;
-        self.C = (self.tmp & 0x01) != 0;
+        self.set_status(self.tmp as u8);
         self.S = self.S.wrapping_add(1);
         self.PC = self.read16(self.S as u16 + 0x100);
         self.S = self.S.wrapping_add(1);
@@ -601,28 +641,48 @@ impl CPU6502 {
     }
 
     fn sbc(&mut self) {
-        let v: u16 = self.read16(self.addr);
-        let c: u16 = 1 - (if self.C { 1 } else { 0 });
+        let v = self.read(self.addr);
+        self.subtract_with_borrow(v);
+    }
+
+    fn subtract_with_borrow(&mut self, v: u8) {
+        let v = v as u16;
+        let c: u16 = if self.C { 0 } else { 1 };
         let r: u16 = (self.A as u16).wrapping_sub(v).wrapping_sub(c);
+        // Flags always follow the binary difference, except N/Z on the 65C02
+        self.Z = (r & 0xFF) == 0;
+        self.N = (r & 0x80) != 0;
+        self.V = ((self.A as u16 ^ v) & (self.A as u16 ^ r) & 0x80) != 0;
+        self.C = (r & 0x100) == 0;
         if self.D {
-            let mut al = (self.A as u16 & 0x0F) - (v & 0x0F) - c;
-            if al > 0x80 {
-                al -= 6
-            };
-            let mut ah = (self.A as u16 >> 4) - (v >> 4) - (if al > 0x80 { 1 } else { 0 });
-            self.Z = (r & 0xFF) == 0;
-            self.N = (r & 0x80) != 0;
-            self.V = ((self.A as u16 ^ v) & (self.A as u16 ^ r) & 0x80) != 0;
-            self.C = if (r & 0x100) != 0 { false } else { true };
-            if ah > 0x80 {
-                ah -= 6
-            };
-            self.A = ((ah << 4) | (al & 15)) as u8;
+            let al = (self.A as u16 & 0x0F)
+                .wrapping_sub(v & 0x0F)
+                .wrapping_sub(c);
+            if self.is_cmos() {
+                let mut a = r;
+                if r & 0x100 != 0 {
+                    a = a.wrapping_sub(0x60);
+                }
+                if al & 0x8000 != 0 {
+                    a = a.wrapping_sub(0x06);
+                }
+                self.A = a as u8;
+                self.fnz(self.A as u16);
+                self.cycles += 1;
+            } else {
+                let mut al = al;
+                if al & 0x8000 != 0 {
+                    al = al.wrapping_sub(6);
+                };
+                let mut ah = (self.A as u16 >> 4)
+                    .wrapping_sub(v >> 4)
+                    .wrapping_sub(if al & 0x8000 != 0 { 1 } else { 0 });
+                if ah & 0x8000 != 0 {
+                    ah = ah.wrapping_sub(6);
+                };
+                self.A = ((ah << 4) | (al & 15)) as u8;
+            }
         } else {
-            self.Z = (r & 0xFF) == 0;
-            self.N = (r & 0x80) != 0;
-            self.V = ((self.A as u16 ^ v) & (self.A as u16 ^ r) & 0x80) != 0;
-            self.C = if (r & 0x100) != 0 { false } else { true };
             self.A = r as u8;
         }
     }
@@ -717,9 +777,157 @@ impl CPU6502 {
         self.fnz(self.A as u16);
     }
 
+    ////////////////////////////////////////////////////////////////////////////////
+    // Subroutines - 65C02 instructions
+    ////////////////////////////////////////////////////////////////////////////////
+
+    fn bra(&mut self) {
+        self.branch(true);
+    }
+
+    // BIT #imm only touches Z
+    fn biti(&mut self) {
+        self.Z = (self.read(self.addr) & self.A) == 0;
+    }
+
+    fn ina(&mut self) {
+        self.A = self.A.wrapping_add(1);
+        self.fnz(self.A as u16);
+    }
+
+    fn dea(&mut self) {
+        self.A = self.A.wrapping_sub(1);
+        self.fnz(self.A as u16);
+    }
+
+    fn phx(&mut self) {
+        self.write(self.S as u16 + 0x100, self.X);
+        self.S = self.S.wrapping_sub(1);
+        self.cycles += 1;
+    }
+
+    fn phy(&mut self) {
+        self.write(self.S as u16 + 0x100, self.Y);
+        self.S = self.S.wrapping_sub(1);
+        self.cycles += 1;
+    }
+
+    fn plx(&mut self) {
+        self.S = self.S.wrapping_add(1);
+        self.X = self.read(self.S as u16 + 0x100);
+        self.fnz(self.X as u16);
+        self.cycles += 2;
+    }
+
+    fn ply(&mut self) {
+        self.S = self.S.wrapping_add(1);
+        self.Y = self.read(self.S as u16 + 0x100);
+        self.fnz(self.Y as u16);
+        self.cycles += 2;
+    }
+
+    fn stz(&mut self) {
+        self.write(self.addr, 0);
+    }
+
+    fn trb(&mut self) {
+        self.tmp = self.read16(self.addr);
+        self.Z = (self.tmp & self.A as u16) == 0;
+        self.tmp &= !(self.A as u16) & 0xFF;
+    }
+
+    fn tsb(&mut self) {
+        self.tmp = self.read16(self.addr);
+        self.Z = (self.tmp & self.A as u16) == 0;
+        self.tmp |= self.A as u16;
+    }
+
+    fn rmb(&mut self, bit: u8) {
+        self.tmp = self.read16(self.addr) & !(1 << bit);
+    }
+
+    fn smb(&mut self, bit: u8) {
+        self.tmp = self.read16(self.addr) | (1 << bit);
+    }
+
+    // BBR/BBS: zero page operand followed by a relative branch
+    fn bbx(&mut self, bit: u8, set: bool) {
+        self.zp();
+        let v = self.read(self.addr);
+        self.rel();
+        self.branch(((v >> bit) & 1 != 0) == set);
+    }
+
+    fn wai(&mut self) {
+        self.waiting = true;
+        self.cycles += 1;
+    }
+
+    fn stp(&mut self) {
+        self.stopped = true;
+        self.cycles += 1;
+    }
+
     // CPU FUNCTIONS
 
-    pub fn reset(&mut self) -> () {
+    fn status(&self) -> u8 {
+        let mut v = if self.N { 1 << 7 } else { 0 };
+        v |= if self.V { 1 << 6 } else { 0 };
+        v |= 1 << 5;
+        v |= if self.D { 1 << 3 } else { 0 };
+        v |= if self.I { 1 << 2 } else { 0 };
+        v |= if self.Z { 1 << 1 } else { 0 };
+        v |= if self.C { 1 } else { 0 };
+        v
+    }
+
+    fn set_status(&mut self, v: u8) {
+        self.N = (v & 0x80) != 0;
+        self.V = (v & 0x40) != 0;
+        self.D = (v & 0x08) != 0;
+        self.I = (v & 0x04) != 0;
+        self.Z = (v & 0x02) != 0;
+        self.C = (v & 0x01) != 0;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.PC,
+            a: self.A,
+            x: self.X,
+            y: self.Y,
+            s: self.S,
+            p: self.status(),
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.PC = registers.pc;
+        self.A = registers.a;
+        self.X = registers.x;
+        self.Y = registers.y;
+        self.S = registers.s;
+        self.set_status(registers.p);
+    }
+
+    pub fn address_spaces(&mut self) -> &mut AddressSpaces {
+        &mut self.address_spaces
+    }
+
+    pub fn peek(&self, address: u16) -> u8 {
+        self.address_spaces.peek(address)
+    }
+
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.address_spaces.poke(address, value);
+    }
+
+    // Shared handle, events run against this CPU's cycle counter
+    pub fn scheduler(&self) -> Scheduler {
+        self.scheduler.clone()
+    }
+
+    pub fn reset(&mut self) {
         self.A = 0;
         self.X = 0;
         self.Y = 0;
@@ -731,23 +939,29 @@ impl CPU6502 {
         self.I = false;
         self.D = false;
         self.opcode = 0x4C;
+        self.waiting = false;
+        self.stopped = false;
 
         self.PC = u16::from_be_bytes([self.read(0xfffd), self.read(0xfffc)]);
     }
 
     fn read(&mut self, address: u16) -> u8 {
-        return self.address_spaces.read(address);
+        self.address_spaces.read(address)
     }
 
     fn read16(&mut self, address: u16) -> u16 {
         self.read(address) as u16
     }
 
-    fn write(&mut self, address: u16, value: u8) -> () {
+    fn write(&mut self, address: u16, value: u8) {
         self.address_spaces.write(address, value);
     }
 
     pub fn exec_op(&mut self, opcode: u8) {
+        if self.is_cmos() && self.exec_cmos_op(opcode) {
+            return;
+        }
+
         match opcode {
             /*  BRK     */
             0x00 => {
@@ -2112,26 +2326,246 @@ impl CPU6502 {
             }
         }
     }
+
+    // Opcodes whose behaviour differs on the 65C02. Returns false when the
+    // NMOS table already does the right thing.
+    fn exec_cmos_op(&mut self, opcode: u8) -> bool {
+        match opcode {
+            /*  TSB zp  */
+            0x04 => {
+                self.zp();
+                self.tsb();
+                self.rmw();
+            }
+            /*  TSB abs */
+            0x0C => {
+                self.abs();
+                self.tsb();
+                self.rmw();
+            }
+            /*  TRB zp  */
+            0x14 => {
+                self.zp();
+                self.trb();
+                self.rmw();
+            }
+            /*  TRB abs */
+            0x1C => {
+                self.abs();
+                self.trb();
+                self.rmw();
+            }
+            /*  ORA izp */
+            0x12 => {
+                self.izp();
+                self.ora();
+            }
+            /*  AND izp */
+            0x32 => {
+                self.izp();
+                self._and();
+            }
+            /*  EOR izp */
+            0x52 => {
+                self.izp();
+                self.eor();
+            }
+            /*  ADC izp */
+            0x72 => {
+                self.izp();
+                self.adc();
+            }
+            /*  STA izp */
+            0x92 => {
+                self.izp();
+                self.sta();
+            }
+            /*  LDA izp */
+            0xB2 => {
+                self.izp();
+                self.lda();
+            }
+            /*  CMP izp */
+            0xD2 => {
+                self.izp();
+                self.cmp();
+            }
+            /*  SBC izp */
+            0xF2 => {
+                self.izp();
+                self.sbc();
+            }
+            /*  NOP imm */
+            0x02 | 0x22 | 0x42 | 0x62 => {
+                self.imm();
+                self.nop();
+            }
+            /*  INC     */
+            0x1A => {
+                self.imp();
+                self.ina();
+            }
+            /*  DEC     */
+            0x3A => {
+                self.imp();
+                self.dea();
+            }
+            /*  BIT zpx */
+            0x34 => {
+                self.zpx();
+                self.bit();
+            }
+            /*  BIT abx */
+            0x3C => {
+                self.abx();
+                self.bit();
+            }
+            /*  BIT imm */
+            0x89 => {
+                self.imm();
+                self.biti();
+            }
+            /*  PHY     */
+            0x5A => {
+                self.imp();
+                self.phy();
+            }
+            /*  PLY     */
+            0x7A => {
+                self.imp();
+                self.ply();
+            }
+            /*  PHX     */
+            0xDA => {
+                self.imp();
+                self.phx();
+            }
+            /*  PLX     */
+            0xFA => {
+                self.imp();
+                self.plx();
+            }
+            /*  NOP abs (8 cycles) */
+            0x5C => {
+                self.abs();
+                self.nop();
+                self.cycles += 4;
+            }
+            /*  NOP abs */
+            0xDC | 0xFC => {
+                self.abs();
+                self.nop();
+            }
+            /*  STZ zp  */
+            0x64 => {
+                self.zp();
+                self.stz();
+            }
+            /*  STZ zpx */
+            0x74 => {
+                self.zpx();
+                self.stz();
+            }
+            /*  STZ abs */
+            0x9C => {
+                self.abs();
+                self.stz();
+            }
+            /*  STZ abx */
+            0x9E => {
+                self.abx();
+                self.stz();
+            }
+            /*  JMP iax */
+            0x7C => {
+                self.iax();
+                self.jmp();
+            }
+            /*  BRA rel */
+            0x80 => {
+                self.rel();
+                self.bra();
+            }
+            /*  WAI     */
+            0xCB => {
+                self.imp();
+                self.wai();
+            }
+            /*  STP     */
+            0xDB => {
+                self.imp();
+                self.stp();
+            }
+            /*  RMB0-7 zp / SMB0-7 zp */
+            op if op & 0x0F == 0x07 => {
+                self.zp();
+                if op < 0x80 {
+                    self.rmb(op >> 4);
+                } else {
+                    self.smb((op >> 4) - 8);
+                }
+                self.rmw();
+            }
+            /*  BBR0-7 zp,rel / BBS0-7 zp,rel */
+            op if op & 0x0F == 0x0F => {
+                if op < 0x80 {
+                    self.bbx(op >> 4, false);
+                } else {
+                    self.bbx((op >> 4) - 8, true);
+                }
+            }
+            /*  NOP (1 byte, 1 cycle) */
+            op if op & 0x07 == 0x03 => {
+                self.cycles += 1;
+            }
+            _ => return false,
+        }
+
+        true
+    }
 }
 
 impl Clockable for CPU6502 {
     fn get_cycles(&self) -> usize {
-        return self.cycles;
+        self.cycles
     }
 
     fn step(&mut self) -> usize {
         let start_cycles = self.cycles;
-        self.opcode = self.read(self.PC);
+        self.scheduler.run_until(self.cycles);
+        if self.stopped {
+            self.cycles += 1;
+            return 1;
+        }
+
+        self.address_spaces.sync(self.cycles);
+        self.irq = self.address_spaces.irq();
+        if self.waiting && !self.irq && !self.nmi {
+            self.cycles += 1;
+            return 1;
+        }
+        self.waiting = false;
+
+        if self.irq && !self.I {
+            self.interrupt(0xFFFE);
+            return self.cycles - start_cycles;
+        }
+        self.opcode = self.address_spaces.fetch(self.PC);
         self.PC += 1;
         self.exec_op(self.opcode);
-        return self.cycles - start_cycles;
+        self.cycles - start_cycles
     }
 }
 
+#[cfg(test)]
+mod decimal_tests;
+
 #[cfg(test)]
 mod tests {
     use super::super::*;
     use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     fn build_base_map() -> std::vec::Vec<address_spaces::AddressMap> {
         let rom = Box::new(rom::Rom::init_with_size(0xffff - 0xff00));
@@ -2153,10 +2587,121 @@ mod tests {
         the_mapping
     }
 
+    // 4K of RAM at $0000 and a page of ROM at $FF00 holding `program`,
+    // with the reset vector pointing at its first byte.
+    fn build_program_cpu(variant: CpuVariant, program: &[u8]) -> CPU6502 {
+        let mut rom_data = vec![0x00; 2 + 0x100];
+        rom_data[2..2 + program.len()].copy_from_slice(program);
+        rom_data[2 + 0xfd] = 0xFF;
+        rom_data[2 + 0xfc] = 0x00;
+
+        let mut rom = Box::new(rom::Rom::init_with_size(0x100));
+        rom.flash(&rom_data);
+
+        let the_mapping = vec![
+            address_spaces::AddressMap {
+                addr: [0x0000, 0x0fff],
+                component: Box::new(ram::Ram::init_with_size(0x1000)),
+                name: String::from("RAM"),
+            },
+            address_spaces::AddressMap {
+                addr: [0xff00, 0xffff],
+                component: rom,
+                name: String::from("ROM"),
+            },
+        ];
+
+        let mut cpu = CPU6502::init(address_spaces::AddressSpaces::init(the_mapping), variant);
+        cpu.reset();
+        cpu
+    }
+
+    #[derive(Debug)]
+    struct IrqLine {
+        level: Rc<Cell<bool>>,
+    }
+
+    impl IoAddressable for IrqLine {
+        fn read(&mut self, _address: usize) -> u8 {
+            0
+        }
+        fn write(&mut self, _address: usize, _value: u8) {}
+        fn flash(&mut self, _data: &[u8]) {}
+        fn irq(&self) -> bool {
+            self.level.get()
+        }
+    }
+
+    #[test]
+    fn services_irq_from_bus() {
+        let mut program = vec![0x00; 0x100];
+        // CLI; JMP $FF01
+        program[..4].copy_from_slice(&[0x58, 0x4C, 0x01, 0xFF]);
+        // INX; RTI
+        program[0x10..0x12].copy_from_slice(&[0xE8, 0x40]);
+        program[0xFE] = 0x10;
+        program[0xFF] = 0xFF;
+        let mut rom_data = vec![0x00, 0x00];
+        rom_data.extend_from_slice(&program);
+        rom_data[2 + 0xfc] = 0x00;
+        rom_data[2 + 0xfd] = 0xFF;
+        let mut rom = rom::Rom::init_with_size(0x100);
+        rom.flash(&rom_data);
+
+        let level = Rc::new(Cell::new(false));
+        let the_mapping = vec![
+            address_spaces::AddressMap {
+                addr: [0x0000, 0x0fff],
+                component: Box::new(ram::Ram::init_with_size(0x1000)),
+                name: String::from("RAM"),
+            },
+            address_spaces::AddressMap {
+                addr: [0xc000, 0xc000],
+                component: Box::new(IrqLine {
+                    level: level.clone(),
+                }),
+                name: String::from("IRQ"),
+            },
+            address_spaces::AddressMap {
+                addr: [0xff00, 0xffff],
+                component: Box::new(rom),
+                name: String::from("ROM"),
+            },
+        ];
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
+        cpu.reset();
+
+        // Masked until CLI
+        level.set(true);
+        cpu.set_registers(Registers {
+            p: 0x24,
+            ..cpu.registers()
+        });
+        cpu.step();
+        assert_eq!(0xFF01, cpu.PC);
+        assert_eq!(7, cpu.step());
+        assert_eq!(0xFF10, cpu.PC);
+        assert!(cpu.I);
+        assert_eq!(0x20, cpu.read(0x01FE) & 0x30, "B clear on the stack");
+
+        level.set(false);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0xFF01, cpu.PC);
+        assert_eq!(1, cpu.X);
+        assert!(!cpu.I);
+    }
+
     #[test]
     fn initial_state() {
         let the_mapping = build_base_map();
-        let mut cpu = CPU6502::init(address_spaces::AddressSpaces::init(the_mapping));
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
         assert_eq!(0x0, cpu.get_cycles());
         let cycles = cpu.step(); // BRK
         assert_eq!(7, cycles);
@@ -2175,7 +2720,10 @@ mod tests {
         //    .component
         //    .flash(&vec![0x00, 0xFF, 0xEA, 0xEA, 0xEA, 0x4C, 02, 0xFF]);
 
-        let mut cpu = CPU6502::init(address_spaces::AddressSpaces::init(the_mapping));
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
 
         cpu.reset();
 
@@ -2192,9 +2740,12 @@ mod tests {
 
         the_mapping[1]
             .component
-            .flash(&vec![0x00, 0xFF, 0xEA, 0xEA, 0xEA, 0x4C, 02, 0xFF]);
+            .flash(&[0x00, 0xFF, 0xEA, 0xEA, 0xEA, 0x4C, 0x02, 0xFF]);
 
-        let mut cpu = CPU6502::init(address_spaces::AddressSpaces::init(the_mapping));
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
 
         // 8  ff00				   loop
         // 9  ff00		       ea		      nop
@@ -2240,12 +2791,15 @@ mod tests {
         rom_data[2 + 0xfc] = 0x00;
         the_mapping[1].component.flash(&rom_data);
 
-        the_mapping[1].component.flash(&vec![
+        the_mapping[1].component.flash(&[
             0x00, 0x00, 0xad, 0x13, 0xff, 0x85, 0x0a, 0xad, 0x14, 0xff, 0x85, 0x0b, 0xa9, 0xcc,
             0xa2, 0x01, 0x95, 0x0b, 0x4c, 0x00, 0xff, 0xaa, 0xbb,
         ]);
 
-        let mut cpu = CPU6502::init(address_spaces::AddressSpaces::init(the_mapping));
+        let mut cpu = CPU6502::init(
+            address_spaces::AddressSpaces::init(the_mapping),
+            CpuVariant::Nmos6502,
+        );
 
         /*
             * = $ff00 "Main"
@@ -2303,16 +2857,172 @@ mod tests {
         assert_eq!(2, step_res);
 
         // ldx #1
-        let step_res = cpu.step();
+        let _step_res = cpu.step();
         assert_eq!(0xff0e, cpu.PC);
         assert_eq!(0x01, cpu.X);
 
         // sta 11, x
-        let step_res = cpu.step();
+        let _step_res = cpu.step();
         assert_eq!(0xCC, cpu.read(12));
 
         // jmp start
-        let step_res = cpu.step();
+        let _step_res = cpu.step();
+        assert_eq!(0xff00, cpu.PC);
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // jmp ($00ff)
+        let program = [0x6c, 0xff, 0x00];
+
+        let mut cpu = build_program_cpu(CpuVariant::Nmos6502, &program);
+        cpu.write(0x00ff, 0x34);
+        cpu.write(0x0100, 0x12);
+        cpu.write(0x0000, 0x56);
+        assert_eq!(5, cpu.step());
+        assert_eq!(0x5634, cpu.PC);
+
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+        cpu.write(0x00ff, 0x34);
+        cpu.write(0x0100, 0x12);
+        cpu.write(0x0000, 0x56);
+        assert_eq!(6, cpu.step());
+        assert_eq!(0x1234, cpu.PC);
+    }
+
+    #[test]
+    fn cmos_stack_and_store_zero() {
+        /*
+            ldx #$ff
+            txs
+            ldx #$42
+            phx
+            ldy #$24
+            phy
+            plx
+            ply
+            stz $10
+            bra *-2
+        */
+        let program = [
+            0xa2, 0xff, 0x9a, 0xa2, 0x42, 0xda, 0xa0, 0x24, 0x5a, 0xfa, 0x7a, 0x64, 0x10, 0x80,
+            0xfc,
+        ];
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+        cpu.write(0x0010, 0x99);
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(0x42, cpu.read(0x01ff));
+        assert_eq!(0xfe, cpu.S);
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(0x24, cpu.X);
+        assert_eq!(0x42, cpu.Y);
+        assert_eq!(0xff, cpu.S);
+
+        assert_eq!(3, cpu.step());
+        assert_eq!(0x00, cpu.read(0x0010));
+
+        assert_eq!(3, cpu.step());
+        assert_eq!(0xff0b, cpu.PC);
+    }
+
+    #[test]
+    fn cmos_bit_instructions() {
+        /*
+            smb3 $20
+            rmb0 $20
+            bbs3 $20, +2
+            nop
+            nop
+            bbr0 $20, +0
+            lda #$0f
+            tsb $21
+            trb $20
+        */
+        let program = [
+            0xb7, 0x20, 0x07, 0x20, 0xbf, 0x20, 0x02, 0xea, 0xea, 0x0f, 0x20, 0x00, 0xa9, 0x0f,
+            0x04, 0x21, 0x14, 0x20,
+        ];
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+        cpu.write(0x0020, 0x01);
+        cpu.write(0x0021, 0xf0);
+
+        assert_eq!(5, cpu.step());
+        assert_eq!(0x09, cpu.read(0x0020));
+        assert_eq!(5, cpu.step());
+        assert_eq!(0x08, cpu.read(0x0020));
+
+        assert_eq!(6, cpu.step());
+        assert_eq!(0xff09, cpu.PC);
+        assert_eq!(6, cpu.step());
+        assert_eq!(0xff0c, cpu.PC);
+
+        cpu.step();
+        assert_eq!(5, cpu.step());
+        assert_eq!(0xff, cpu.read(0x0021));
+        assert!(cpu.Z);
+        assert_eq!(5, cpu.step());
+        assert_eq!(0x00, cpu.read(0x0020));
+        assert!(!cpu.Z);
+    }
+
+    #[test]
+    fn decimal_flags_per_variant() {
+        /*
+            sed
+            clc
+            lda #$99
+            adc #$01
+        */
+        let program = [0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01];
+
+        let mut cpu = build_program_cpu(CpuVariant::Nmos6502, &program);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(2, cpu.step());
+        assert_eq!(0x00, cpu.A);
+        assert!(cpu.C);
+        assert!(!cpu.Z);
+
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(3, cpu.step());
+        assert_eq!(0x00, cpu.A);
+        assert!(cpu.C);
+        assert!(cpu.Z);
+    }
+
+    #[test]
+    fn cmos_undocumented_opcodes_are_nops() {
+        // *kil / *slo izx on NMOS, nop imm / 1 cycle nop on 65C02
+        let program = [0x02, 0x00, 0x03];
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+
+        assert_eq!(2, cpu.step());
+        assert_eq!(0xff02, cpu.PC);
+        assert_eq!(1, cpu.step());
+        assert_eq!(0xff03, cpu.PC);
+    }
+
+    #[test]
+    fn cmos_stp_halts_until_reset() {
+        let program = [0xdb, 0xea];
+        let mut cpu = build_program_cpu(CpuVariant::Cmos65C02, &program);
+
+        assert_eq!(3, cpu.step());
+        assert_eq!(1, cpu.step());
+        assert_eq!(0xff01, cpu.PC);
+
+        cpu.reset();
         assert_eq!(0xff00, cpu.PC);
+        assert_eq!(3, cpu.step());
     }
 }
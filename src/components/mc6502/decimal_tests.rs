@@ -0,0 +1,234 @@
+// Exhaustive ADC/SBC verification against the reference sequences from
+// Bruce Clark's "Decimal Mode" tutorial (6502.org), for every accumulator,
+// operand and carry combination on both CPU variants.
+
+use super::super::{address_spaces, ram};
+use super::*;
+
+const OPERAND_ADDR: u16 = 0x10;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Outcome {
+    a: u8,
+    n: bool,
+    v: bool,
+    z: bool,
+    c: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Adc,
+    Sbc,
+}
+
+fn reference_adc(variant: CpuVariant, decimal: bool, a: u8, b: u8, c: bool) -> Outcome {
+    let (a, b, c) = (a as i32, b as i32, c as i32);
+    let binary = a + b + c;
+    if !decimal {
+        return Outcome {
+            a: binary as u8,
+            n: binary & 0x80 != 0,
+            v: (!(a ^ b) & (a ^ binary) & 0x80) != 0,
+            z: binary & 0xFF == 0,
+            c: binary > 0xFF,
+        };
+    }
+
+    // Seq. 1: accumulator and carry
+    let mut al = (a & 0x0F) + (b & 0x0F) + c;
+    if al >= 0x0A {
+        al = ((al + 0x06) & 0x0F) + 0x10;
+    }
+    let mut sum = (a & 0xF0) + (b & 0xF0) + al;
+    // Seq. 2: N and V come from the sum before the high nibble adjust,
+    // using signed arithmetic for V
+    let unadjusted = sum;
+    let signed = (a & 0xF0) as u8 as i8 as i32 + (b & 0xF0) as u8 as i8 as i32 + al;
+    if sum >= 0xA0 {
+        sum += 0x60;
+    }
+    let result = sum as u8;
+
+    match variant {
+        CpuVariant::Nmos6502 => Outcome {
+            a: result,
+            n: unadjusted & 0x80 != 0,
+            v: !(-128..=127).contains(&signed),
+            z: binary & 0xFF == 0,
+            c: sum >= 0x100,
+        },
+        CpuVariant::Cmos65C02 => Outcome {
+            a: result,
+            n: result & 0x80 != 0,
+            v: !(-128..=127).contains(&signed),
+            z: result == 0,
+            c: sum >= 0x100,
+        },
+    }
+}
+
+fn reference_sbc(variant: CpuVariant, decimal: bool, a: u8, b: u8, c: bool) -> Outcome {
+    let (a, b, c) = (a as i32, b as i32, c as i32);
+    let binary = a - b + c - 1;
+    let flags = Outcome {
+        a: binary as u8,
+        n: binary & 0x80 != 0,
+        v: ((a ^ b) & (a ^ binary) & 0x80) != 0,
+        z: binary & 0xFF == 0,
+        c: binary >= 0,
+    };
+    if !decimal {
+        return flags;
+    }
+
+    match variant {
+        CpuVariant::Nmos6502 => {
+            // Seq. 3, flags are the binary ones
+            let mut al = (a & 0x0F) - (b & 0x0F) + c - 1;
+            if al < 0 {
+                al = ((al - 0x06) & 0x0F) - 0x10;
+            }
+            let mut diff = (a & 0xF0) - (b & 0xF0) + al;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            Outcome {
+                a: diff as u8,
+                ..flags
+            }
+        }
+        CpuVariant::Cmos65C02 => {
+            // Seq. 4, N and Z follow the adjusted accumulator
+            let al = (a & 0x0F) - (b & 0x0F) + c - 1;
+            let mut diff = binary;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            if al < 0 {
+                diff -= 0x06;
+            }
+            let result = diff as u8;
+            Outcome {
+                a: result,
+                n: result & 0x80 != 0,
+                z: result == 0,
+                ..flags
+            }
+        }
+    }
+}
+
+fn build_cpu(variant: CpuVariant) -> CPU6502 {
+    let the_mapping = vec![address_spaces::AddressMap {
+        addr: [0x0000, 0x00ff],
+        component: Box::new(ram::Ram::init_with_size(0x100)),
+        name: String::from("RAM"),
+    }];
+
+    CPU6502::init(address_spaces::AddressSpaces::init(the_mapping), variant)
+}
+
+fn run_op(cpu: &mut CPU6502, op: Op, decimal: bool, a: u8, b: u8, c: bool) -> Outcome {
+    cpu.write(OPERAND_ADDR, b);
+    cpu.addr = OPERAND_ADDR;
+    cpu.A = a;
+    cpu.C = c;
+    cpu.D = decimal;
+    cpu.N = false;
+    cpu.V = false;
+    cpu.Z = false;
+    match op {
+        Op::Adc => cpu.adc(),
+        Op::Sbc => cpu.sbc(),
+    }
+
+    Outcome {
+        a: cpu.A,
+        n: cpu.N,
+        v: cpu.V,
+        z: cpu.Z,
+        c: cpu.C,
+    }
+}
+
+fn describe(outcome: &Outcome) -> String {
+    format!(
+        "A=${:02X} N={} V={} Z={} C={}",
+        outcome.a, outcome.n as u8, outcome.v as u8, outcome.z as u8, outcome.c as u8
+    )
+}
+
+fn verify(variant: CpuVariant, op: Op, decimal: bool) {
+    let mut cpu = build_cpu(variant);
+    let mut mismatches = Vec::new();
+
+    for a in 0..=255u8 {
+        for b in 0..=255u8 {
+            for &c in &[false, true] {
+                let expected = match op {
+                    Op::Adc => reference_adc(variant, decimal, a, b, c),
+                    Op::Sbc => reference_sbc(variant, decimal, a, b, c),
+                };
+                let actual = run_op(&mut cpu, op, decimal, a, b, c);
+                if actual != expected {
+                    mismatches.push(format!(
+                        "{:?} A=${:02X} M=${:02X} C={}: expected {}, got {}",
+                        op,
+                        a,
+                        b,
+                        c as u8,
+                        describe(&expected),
+                        describe(&actual)
+                    ));
+                }
+            }
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "{:?} D={}: {} mismatches, first ones:\n{}",
+        variant,
+        decimal as u8,
+        mismatches.len(),
+        mismatches
+            .iter()
+            .take(16)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+#[test]
+fn nmos_adc_decimal() {
+    verify(CpuVariant::Nmos6502, Op::Adc, true);
+}
+
+#[test]
+fn nmos_sbc_decimal() {
+    verify(CpuVariant::Nmos6502, Op::Sbc, true);
+}
+
+#[test]
+fn cmos_adc_decimal() {
+    verify(CpuVariant::Cmos65C02, Op::Adc, true);
+}
+
+#[test]
+fn cmos_sbc_decimal() {
+    verify(CpuVariant::Cmos65C02, Op::Sbc, true);
+}
+
+#[test]
+fn nmos_adc_sbc_binary() {
+    verify(CpuVariant::Nmos6502, Op::Adc, false);
+    verify(CpuVariant::Nmos6502, Op::Sbc, false);
+}
+
+#[test]
+fn cmos_adc_sbc_binary() {
+    verify(CpuVariant::Cmos65C02, Op::Adc, false);
+    verify(CpuVariant::Cmos65C02, Op::Sbc, false);
+}
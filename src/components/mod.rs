@@ -1,16 +1,70 @@
+pub mod aci;
 pub mod address_spaces;
+pub mod assembler;
+pub mod callback;
 pub mod clock;
+pub mod config;
+pub mod coverage;
+pub mod disasm;
+pub mod display;
+pub mod feeder;
+pub mod frame;
+pub mod gdb;
+pub mod gif;
+pub mod golden;
+pub mod handle;
+pub mod machine;
 pub mod mc6502;
 pub mod pia6820;
+pub mod profiler;
+pub mod profiles;
 pub mod ram;
+pub mod recorder;
+pub mod render;
 pub mod rom;
+pub mod runner;
+pub mod scheduler;
+pub mod stats;
+pub mod symbols;
+pub mod terminal;
+#[cfg(test)]
+pub mod test_support;
+pub mod time;
+pub mod tracer;
+pub mod wav;
+use self::scheduler::Scheduler;
 use std::fmt::Debug;
-use std::thread;
 
 pub trait IoAddressable: Debug {
     fn read(&mut self, address: usize) -> u8;
-    fn write(&mut self, address: usize, value: u8) -> ();
-    fn flash(&mut self, data: &Vec<u8>) -> ();
+    fn write(&mut self, address: usize, value: u8);
+    fn flash(&mut self, data: &[u8]);
+
+    // Called by the CPU before every instruction with its cycle counter, for
+    // devices whose behaviour depends on emulated time.
+    fn sync(&mut self, _cycles: usize) {}
+
+    // Given the CPU's event scheduler when the CPU is built, for devices
+    // that time their own events in emulated cycles
+    fn attach_scheduler(&mut self, _scheduler: &Scheduler) {}
+
+    // Debugger access: no side effects and no access counters. Devices that
+    // can't be inspected read as 0 and ignore pokes.
+    fn peek(&self, _address: usize) -> u8 {
+        0
+    }
+
+    fn poke(&mut self, _address: usize, _value: u8) {}
+
+    // Names of the device's registers by offset, for symbol tables
+    fn register_names(&self) -> Vec<(usize, String)> {
+        Vec::new()
+    }
+
+    // Level of the device's IRQ output, sampled right after `sync`
+    fn irq(&self) -> bool {
+        false
+    }
 }
 
 pub trait Clockable: Debug {
@@ -18,13 +72,33 @@ pub trait Clockable: Debug {
     fn step(&mut self) -> usize;
 }
 
-pub trait IoComponent: Debug {
-    fn read(&mut self, address: usize) -> thread::Result<u8>;
-    fn write(&mut self, value: u8) -> thread::Result<()>;
-    fn wire(&mut self, options: IoComponentWireOptions) -> ();
+// Control inputs a device drives into its PIA port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ControlLines {
+    pub c1: bool,
+    pub c2: bool,
 }
 
-struct IoComponentWireOptions {
-    logic_write: fn(value: u8) -> thread::Result<()>,
-    logic_read: fn(address: usize) -> thread::Result<u8>,
+// A device on one side of a PIA: eight data lines plus the C1/C2 control
+// lines. Interrupts are requested through C1/C2 transitions, the PIA raises
+// IRQ when the matching control register bits enable it.
+pub trait IoComponent: Debug {
+    // Levels on the port's input lines, sampled when the CPU reads the data
+    // register
+    fn input(&mut self) -> u8 {
+        0
+    }
+
+    // The output lines after a write to the data register, lines set as
+    // inputs in the DDR read as 0
+    fn output(&mut self, _lines: u8) {}
+
+    // C2 when the PIA drives it as an output
+    fn c2_output(&mut self, _level: bool) {}
+
+    // Called before every instruction with the CPU cycle counter, returns
+    // the current C1/C2 input levels
+    fn sync(&mut self, _cycles: usize) -> ControlLines {
+        ControlLines::default()
+    }
 }
@@ -1,3 +1,4 @@
+use super::ControlLines;
 use super::IoAddressable;
 use super::IoComponent;
 
@@ -8,27 +9,382 @@ const CRT_A_ADDR: u8 = 0x1;
 const DATA_B_ADDR: u8 = 0x2;
 const CRT_B_ADDR: u8 = 0x3;
 
-#[derive(Debug)]
-pub struct pia6820 {
-    pub data: Vec<u8>,
-    io_a: Option<Box<dyn IoComponent>>,
-    io_b: Option<Box<dyn IoComponent>>,
+// Control register bits
+const CR_IRQ1: u8 = 0x80;
+const CR_IRQ2: u8 = 0x40;
+const CR_C2_OUTPUT: u8 = 0x20;
+const CR_C2_MODE: u8 = 0x10;
+const CR_C2_BIT3: u8 = 0x08;
+const CR_DATA_SELECT: u8 = 0x04;
+const CR_C1_RISING: u8 = 0x02;
+const CR_C1_IRQ: u8 = 0x01;
+const CR_WRITABLE: u8 = 0x3F;
+
+#[derive(Debug, Default)]
+struct Port {
+    or: u8,
+    ddr: u8,
+    cr: u8,
+    // Last control input levels, for edge detection
+    lines: ControlLines,
+    // Input lines as last read, peeks can't ask the device again
+    input: u8,
+    io: Option<Box<dyn IoComponent>>,
+}
+
+impl Port {
+    fn pins(&self, input: u8) -> u8 {
+        (self.or & self.ddr) | (input & !self.ddr)
+    }
+
+    fn c2_is_output(&self) -> bool {
+        self.cr & CR_C2_OUTPUT != 0
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        if let Some(io) = &mut self.io {
+            io.c2_output(level);
+        }
+    }
+
+    // Handshake and pulse modes drop C2 on a data access: reads on port A,
+    // writes on port B
+    fn strobe_c2(&mut self) {
+        if !self.c2_is_output() || self.cr & CR_C2_MODE != 0 {
+            return;
+        }
+        self.set_c2(false);
+        if self.cr & CR_C2_BIT3 != 0 {
+            self.set_c2(true);
+        }
+    }
+
+    fn read(&mut self, strobe: bool) -> u8 {
+        if self.cr & CR_DATA_SELECT == 0 {
+            return self.ddr;
+        }
+
+        // Reading the data register acknowledges the C1/C2 interrupt flags
+        self.cr &= !(CR_IRQ1 | CR_IRQ2);
+        self.input = match &mut self.io {
+            Some(io) => io.input(),
+            None => 0,
+        };
+        if strobe {
+            self.strobe_c2();
+        }
+        self.pins(self.input)
+    }
+
+    fn peek(&self) -> u8 {
+        if self.cr & CR_DATA_SELECT == 0 {
+            self.ddr
+        } else {
+            self.pins(self.input)
+        }
+    }
+
+    fn poke(&mut self, value: u8) {
+        if self.cr & CR_DATA_SELECT == 0 {
+            self.ddr = value;
+        } else {
+            self.or = value;
+        }
+    }
+
+    fn write(&mut self, value: u8, strobe: bool) {
+        if self.cr & CR_DATA_SELECT == 0 {
+            self.ddr = value;
+            return;
+        }
+
+        self.or = value;
+        if let Some(io) = &mut self.io {
+            io.output(value & self.ddr);
+        }
+        if strobe {
+            self.strobe_c2();
+        }
+    }
+
+    fn write_cr(&mut self, value: u8) {
+        self.cr = (self.cr & !CR_WRITABLE) | (value & CR_WRITABLE);
+        if !self.c2_is_output() {
+            return;
+        }
+        // The flag bit only exists while C2 is an input
+        self.cr &= !CR_IRQ2;
+        // Manual mode sets C2 straight from bit 3, handshake mode idles high
+        let manual = self.cr & CR_C2_MODE != 0;
+        self.set_c2(!manual || self.cr & CR_C2_BIT3 != 0);
+    }
+
+    fn sync(&mut self, cycles: usize) {
+        let lines = match &mut self.io {
+            Some(io) => io.sync(cycles),
+            None => return,
+        };
+        let previous = std::mem::replace(&mut self.lines, lines);
+
+        let c1_active = self.cr & CR_C1_RISING != 0;
+        if lines.c1 != previous.c1 && lines.c1 == c1_active {
+            self.cr |= CR_IRQ1;
+            // Handshake mode: the C1 edge ends the C2 strobe
+            if self.cr & (CR_C2_OUTPUT | CR_C2_MODE | CR_C2_BIT3) == CR_C2_OUTPUT {
+                self.set_c2(true);
+            }
+        }
+
+        let c2_active = self.cr & CR_C2_MODE != 0;
+        if !self.c2_is_output() && lines.c2 != previous.c2 && lines.c2 == c2_active {
+            self.cr |= CR_IRQ2;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        let c1 = self.cr & CR_IRQ1 != 0 && self.cr & CR_C1_IRQ != 0;
+        let c2 = self.cr & CR_IRQ2 != 0 && self.cr & CR_C2_BIT3 != 0 && !self.c2_is_output();
+        c1 || c2
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Pia6820 {
+    port_a: Port,
+    port_b: Port,
+    // IRQA/IRQB aren't connected on the Apple-1 board
+    irq_connected: bool,
+}
+
+impl Pia6820 {
+    pub fn new() -> Pia6820 {
+        Pia6820::default()
+    }
+
+    pub fn wire_ioa(&mut self, io_a: Option<Box<dyn IoComponent>>) {
+        self.port_a.io = io_a;
+    }
+
+    pub fn wire_iob(&mut self, io_b: Option<Box<dyn IoComponent>>) {
+        self.port_b.io = io_b;
+    }
+
+    // Routes IRQA/IRQB to the CPU, for PIAs on expansion cards
+    pub fn connect_irq(&mut self, connected: bool) {
+        self.irq_connected = connected;
+    }
+
+    pub fn irq_a(&self) -> bool {
+        self.port_a.irq()
+    }
+
+    pub fn irq_b(&self) -> bool {
+        self.port_b.irq()
+    }
 }
 
-impl pia6820 {
-    pub fn new() -> pia6820 {
-        pia6820 {
-            data: vec![0x00; 4],
-            io_a: None,
-            io_b: None,
+impl IoAddressable for Pia6820 {
+    fn read(&mut self, address: usize) -> u8 {
+        match (address & 0x3) as u8 {
+            DATA_A_ADDR => self.port_a.read(true),
+            CRT_A_ADDR => self.port_a.cr,
+            DATA_B_ADDR => self.port_b.read(false),
+            _ => self.port_b.cr,
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match (address & 0x3) as u8 {
+            DATA_A_ADDR => self.port_a.write(value, false),
+            CRT_A_ADDR => self.port_a.write_cr(value),
+            DATA_B_ADDR => self.port_b.write(value, true),
+            CRT_B_ADDR => self.port_b.write_cr(value),
+            _ => {}
+        }
+    }
+
+    fn flash(&mut self, _data: &[u8]) {}
+
+    fn sync(&mut self, cycles: usize) {
+        self.port_a.sync(cycles);
+        self.port_b.sync(cycles);
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        match (address & 0x3) as u8 {
+            DATA_A_ADDR => self.port_a.peek(),
+            CRT_A_ADDR => self.port_a.cr,
+            DATA_B_ADDR => self.port_b.peek(),
+            _ => self.port_b.cr,
+        }
+    }
+
+    // Sets registers without touching the devices or the C2 lines
+    fn poke(&mut self, address: usize, value: u8) {
+        match (address & 0x3) as u8 {
+            DATA_A_ADDR => self.port_a.poke(value),
+            CRT_A_ADDR => self.port_a.cr = value,
+            DATA_B_ADDR => self.port_b.poke(value),
+            _ => self.port_b.cr = value,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_connected && (self.irq_a() || self.irq_b())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::handle::DeviceHandle;
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FakeDevice {
+        input: u8,
+        written: Vec<u8>,
+        lines: ControlLines,
+        c2: Vec<bool>,
+    }
+
+    impl IoComponent for FakeDevice {
+        fn input(&mut self) -> u8 {
+            self.input
+        }
+
+        fn output(&mut self, lines: u8) {
+            self.written.push(lines);
+        }
+
+        fn c2_output(&mut self, level: bool) {
+            self.c2.push(level);
         }
+
+        fn sync(&mut self, _cycles: usize) -> ControlLines {
+            self.lines
+        }
+    }
+
+    fn wired_pia() -> (Pia6820, DeviceHandle<FakeDevice>, DeviceHandle<FakeDevice>) {
+        let a = DeviceHandle::new(FakeDevice::default());
+        let b = DeviceHandle::new(FakeDevice::default());
+        let mut pia = Pia6820::new();
+        pia.wire_ioa(Some(Box::new(a.clone())));
+        pia.wire_iob(Some(Box::new(b.clone())));
+        (pia, a, b)
+    }
+
+    #[test]
+    fn initial_state() {
+        let mut pia = Pia6820::new();
+        for reg in 0..4 {
+            assert_eq!(0x00, pia.read(reg));
+        }
+        assert!(!pia.irq());
     }
 
-    pub fn wire_ioa(&mut self, io_a: Option<Box<dyn IoComponent>>) -> () {
-        self.io_a = io_a;
+    #[test]
+    fn selects_ddr_or_data() {
+        let (mut pia, _, b) = wired_pia();
+        b.borrow_mut().input = 0x80;
+
+        // Woz Monitor setup: PB0-6 output, PB7 input
+        pia.write(DATA_B_ADDR as usize, 0x7F);
+        pia.write(CRT_B_ADDR as usize, 0xA7);
+        assert_eq!(0x27, pia.read(CRT_B_ADDR as usize));
+
+        pia.write(DATA_B_ADDR as usize, 0xC1);
+        assert_eq!(vec![0x41], b.borrow().written);
+        assert_eq!(0xC1, pia.read(DATA_B_ADDR as usize));
+
+        pia.write(CRT_B_ADDR as usize, 0x00);
+        assert_eq!(0x7F, pia.read(DATA_B_ADDR as usize));
+    }
+
+    #[test]
+    fn strobe_sets_flag_until_data_read() {
+        let (mut pia, a, _) = wired_pia();
+        a.borrow_mut().input = 0xC1;
+        pia.write(CRT_A_ADDR as usize, 0xA7);
+
+        a.borrow_mut().lines.c1 = true;
+        pia.sync(10);
+        assert_eq!(0xA7, pia.read(CRT_A_ADDR as usize));
+        assert_eq!(0xC1, pia.read(DATA_A_ADDR as usize));
+        assert_eq!(0x27, pia.read(CRT_A_ADDR as usize));
+
+        // Still high, no new transition
+        pia.sync(20);
+        assert_eq!(0x27, pia.read(CRT_A_ADDR as usize));
     }
 
-    pub fn wire_iob(&mut self, io_b: Option<Box<dyn IoComponent>>) -> () {
-        self.io_b = io_b;
+    #[test]
+    fn peek_leaves_flags_alone() {
+        let (mut pia, a, _) = wired_pia();
+        a.borrow_mut().input = 0xC1;
+        pia.write(CRT_A_ADDR as usize, 0xA7);
+        a.borrow_mut().lines.c1 = true;
+        pia.sync(0);
+
+        assert_eq!(0xA7, pia.peek(CRT_A_ADDR as usize));
+        assert_eq!(0x00, pia.peek(DATA_A_ADDR as usize));
+        assert_eq!(0xA7, pia.read(CRT_A_ADDR as usize));
+        assert_eq!(0xC1, pia.read(DATA_A_ADDR as usize));
+        assert_eq!(0xC1, pia.peek(DATA_A_ADDR as usize));
+
+        pia.poke(CRT_A_ADDR as usize, 0x80);
+        assert_eq!(0x80, pia.read(CRT_A_ADDR as usize));
+        assert_eq!(0x00, pia.peek(DATA_A_ADDR as usize), "DDR selected");
+    }
+
+    #[test]
+    fn c1_edge_polarity() {
+        let (mut pia, a, _) = wired_pia();
+        // Falling edge active
+        pia.write(CRT_A_ADDR as usize, 0x04);
+
+        a.borrow_mut().lines.c1 = true;
+        pia.sync(0);
+        assert_eq!(0x04, pia.read(CRT_A_ADDR as usize));
+        a.borrow_mut().lines.c1 = false;
+        pia.sync(1);
+        assert_eq!(0x84, pia.read(CRT_A_ADDR as usize));
+    }
+
+    #[test]
+    fn c2_input_and_irq() {
+        let (mut pia, _, b) = wired_pia();
+        pia.connect_irq(true);
+        // C2 input, rising edge, IRQ enabled
+        pia.write(CRT_B_ADDR as usize, 0x1C);
+
+        b.borrow_mut().lines.c2 = true;
+        pia.sync(0);
+        assert_eq!(0x5C, pia.read(CRT_B_ADDR as usize));
+        assert!(pia.irq_b());
+        assert!(pia.irq());
+
+        pia.read(DATA_B_ADDR as usize);
+        assert!(!pia.irq());
+    }
+
+    #[test]
+    fn c2_output_modes() {
+        let (mut pia, a, b) = wired_pia();
+
+        // Port A handshake: low on data read, high again on the C1 edge
+        pia.write(CRT_A_ADDR as usize, 0x26);
+        pia.read(DATA_A_ADDR as usize);
+        a.borrow_mut().lines.c1 = true;
+        pia.sync(0);
+        assert_eq!(vec![true, false, true], a.borrow().c2);
+
+        // Port B pulse: low then high on data write
+        pia.write(CRT_B_ADDR as usize, 0x2C);
+        pia.write(DATA_B_ADDR as usize, 0x00);
+        // Manual: follows bit 3
+        pia.write(CRT_B_ADDR as usize, 0x34);
+        pia.write(CRT_B_ADDR as usize, 0x3C);
+        assert_eq!(vec![true, false, true, false, true], b.borrow().c2);
     }
 }
@@ -0,0 +1,496 @@
+use super::mc6502::CPU6502;
+use super::symbols::Symbols;
+use super::Clockable;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Execution profiler. Each step is charged to the routine on top of a shadow
+// call stack built from JSR, BRK/IRQ and RTS/RTI. Returns pop every frame
+// entered at or below the new stack pointer, so RTS used as a computed jump
+// (Integer BASIC does this a lot) doesn't unwind anything, and code that
+// drops its return address with PLA/PLA is resynced on the next return.
+//
+// Call paths are interned as nodes of a tree and every frame keeps its node,
+// so charging a step is constant time whatever the call depth. A routine's
+// total is settled when its outermost frame returns, frames still on the
+// stack are added in when asked.
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u16,
+    // S before the call pushed anything
+    s_before: u8,
+    // Node of the call path ending in this frame
+    path: usize,
+    // Profiler cycles when the frame was entered
+    started: u64,
+    // First frame of its routine on the stack, the one that counts towards
+    // the routine's total
+    outermost: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PathNode {
+    entry: u16,
+    parent: Option<usize>,
+    self_cycles: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub entry: u16,
+    pub calls: u64,
+    // Cycles spent in the routine itself
+    pub self_cycles: u64,
+    // Self plus everything it called
+    pub total_cycles: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    stack: Vec<Frame>,
+    routines: HashMap<u16, RoutineProfile>,
+    // Frames of each routine on the stack
+    depths: HashMap<u16, usize>,
+    nodes: Vec<PathNode>,
+    children: HashMap<(Option<usize>, u16), usize>,
+    root_cycles: u64,
+    total_cycles: u64,
+    names: HashMap<u16, String>,
+}
+
+fn cmp_desc(a: u64, b: u64) -> std::cmp::Ordering {
+    b.cmp(&a)
+}
+
+impl Profiler {
+    pub fn init() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn reset(&mut self) {
+        let names = std::mem::take(&mut self.names);
+        *self = Profiler {
+            names,
+            ..Profiler::default()
+        };
+    }
+
+    // Label used for a routine in reports instead of its address
+    pub fn name(&mut self, entry: u16, name: &str) {
+        self.names.insert(entry, name.to_string());
+    }
+
+    // Names every routine that has a symbol
+    pub fn use_symbols(&mut self, symbols: &Symbols) {
+        for (addr, name) in symbols.iter() {
+            self.names.entry(addr).or_insert_with(|| name.to_string());
+        }
+    }
+
+    fn label(&self, entry: u16) -> String {
+        match self.names.get(&entry) {
+            Some(name) => name.clone(),
+            None => format!("${:04X}", entry),
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    // Cycles spent outside any call
+    pub fn root_cycles(&self) -> u64 {
+        self.root_cycles
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn routine(&self, entry: u16) -> Option<RoutineProfile> {
+        let mut routine = *self.routines.get(&entry)?;
+        for frame in &self.stack {
+            if frame.outermost && frame.entry == entry {
+                routine.total_cycles += self.total_cycles - frame.started;
+            }
+        }
+        Some(routine)
+    }
+
+    // Runs one CPU step and charges its cycles
+    pub fn step(&mut self, cpu: &mut CPU6502) -> usize {
+        let before = cpu.registers();
+        let opcode = cpu.peek(before.pc);
+        let cycles = cpu.step();
+        let after = cpu.registers();
+
+        self.charge(cycles as u64);
+
+        match opcode {
+            // An IRQ taken instead of running the opcode
+            _ if Profiler::took_irq(cpu, before.s, after.s, after.pc) => {
+                self.call(after.pc, before.s)
+            }
+            BRK | JSR => self.call(after.pc, before.s),
+            RTS | RTI => self.ret(after.s),
+            _ => {}
+        }
+        cycles
+    }
+
+    // Three bytes pushed, the PC at the IRQ handler and B clear in the
+    // pushed status, so TXS and friends aren't taken for calls
+    fn took_irq(cpu: &CPU6502, s_before: u8, s_after: u8, pc: u16) -> bool {
+        let handler = u16::from_le_bytes([cpu.peek(IRQ_VECTOR), cpu.peek(IRQ_VECTOR + 1)]);
+        let status = cpu.peek(0x0100 + s_after.wrapping_add(1) as u16);
+        s_before.wrapping_sub(s_after) == 3 && pc == handler && status & 0x10 == 0
+    }
+
+    pub fn run_cycles(&mut self, cpu: &mut CPU6502, cycles: usize) {
+        let until = cpu.get_cycles() + cycles;
+        while cpu.get_cycles() < until {
+            self.step(cpu);
+        }
+    }
+
+    fn charge(&mut self, cycles: u64) {
+        self.total_cycles += cycles;
+        match self.stack.last() {
+            Some(frame) => {
+                self.nodes[frame.path].self_cycles += cycles;
+                if let Some(routine) = self.routines.get_mut(&frame.entry) {
+                    routine.self_cycles += cycles;
+                }
+            }
+            None => self.root_cycles += cycles,
+        }
+    }
+
+    fn call(&mut self, entry: u16, s_before: u8) {
+        let routine = self.routines.entry(entry).or_default();
+        routine.entry = entry;
+        routine.calls += 1;
+
+        let parent = self.stack.last().map(|frame| frame.path);
+        let next = self.nodes.len();
+        let path = *self.children.entry((parent, entry)).or_insert(next);
+        if path == next {
+            self.nodes.push(PathNode {
+                entry,
+                parent,
+                self_cycles: 0,
+            });
+        }
+        // Recursive routines only count once towards their own total
+        let depth = self.depths.entry(entry).or_default();
+        *depth += 1;
+        self.stack.push(Frame {
+            entry,
+            s_before,
+            path,
+            started: self.total_cycles,
+            outermost: *depth == 1,
+        });
+    }
+
+    fn ret(&mut self, s_after: u8) {
+        while let Some(frame) = self.stack.last().copied() {
+            if frame.s_before > s_after {
+                break;
+            }
+            self.stack.pop();
+            if let Some(depth) = self.depths.get_mut(&frame.entry) {
+                *depth -= 1;
+            }
+            if frame.outermost {
+                if let Some(routine) = self.routines.get_mut(&frame.entry) {
+                    routine.total_cycles += self.total_cycles - frame.started;
+                }
+            }
+        }
+    }
+
+    // Routines by self cycles, busiest first
+    pub fn flat(&self) -> Vec<RoutineProfile> {
+        let mut flat: Vec<RoutineProfile> = self
+            .routines
+            .keys()
+            .filter_map(|entry| self.routine(*entry))
+            .collect();
+        flat.sort_by(|a, b| cmp_desc(a.self_cycles, b.self_cycles).then(a.entry.cmp(&b.entry)));
+        flat
+    }
+
+    pub fn flat_report(&self) -> String {
+        let total = self.total_cycles.max(1) as f64;
+        let mut out = format!(
+            "{:>7} {:>12} {:>12} {:>8}  routine\n",
+            "self%", "self", "total", "calls"
+        );
+        for routine in self.flat() {
+            out.push_str(&format!(
+                "{:>6.2}% {:>12} {:>12} {:>8}  {}\n",
+                100.0 * routine.self_cycles as f64 / total,
+                routine.self_cycles,
+                routine.total_cycles,
+                routine.calls,
+                self.label(routine.entry)
+            ));
+        }
+        out.push_str(&format!(
+            "{:>6.2}% {:>12} {:>12} {:>8}  [root]\n",
+            100.0 * self.root_cycles as f64 / total,
+            self.root_cycles,
+            self.total_cycles,
+            ""
+        ));
+        out
+    }
+
+    // Self cycles per call path, outermost routine first
+    fn paths(&self) -> HashMap<Vec<u16>, u64> {
+        let mut paths = HashMap::new();
+        if self.root_cycles > 0 {
+            paths.insert(Vec::new(), self.root_cycles);
+        }
+        for node in self.nodes.iter().filter(|node| node.self_cycles > 0) {
+            let mut path = vec![node.entry];
+            let mut parent = node.parent;
+            while let Some(at) = parent {
+                path.push(self.nodes[at].entry);
+                parent = self.nodes[at].parent;
+            }
+            path.reverse();
+            paths.insert(path, node.self_cycles);
+        }
+        paths
+    }
+
+    // Inclusive cycles per call path
+    fn path_totals(paths: &HashMap<Vec<u16>, u64>) -> HashMap<Vec<u16>, u64> {
+        let mut totals: HashMap<Vec<u16>, u64> = HashMap::new();
+        for (path, cycles) in paths {
+            for depth in 0..=path.len() {
+                *totals.entry(path[..depth].to_vec()).or_default() += cycles;
+            }
+        }
+        totals
+    }
+
+    // Indented call tree, children by inclusive cycles
+    pub fn call_tree_report(&self) -> String {
+        let paths = self.paths();
+        let totals = Profiler::path_totals(&paths);
+        let mut out = String::new();
+        self.tree_node(&paths, &totals, &[], 0, &mut out);
+        out
+    }
+
+    fn tree_node(
+        &self,
+        paths: &HashMap<Vec<u16>, u64>,
+        totals: &HashMap<Vec<u16>, u64>,
+        path: &[u16],
+        depth: usize,
+        out: &mut String,
+    ) {
+        let total = totals.get(path).copied().unwrap_or(0);
+        let own = paths.get(path).copied().unwrap_or(0);
+        let label = match path.last() {
+            Some(entry) => self.label(*entry),
+            None => String::from("[root]"),
+        };
+        out.push_str(&format!(
+            "{:indent$}{} total={} self={}\n",
+            "",
+            label,
+            total,
+            own,
+            indent = depth * 2
+        ));
+
+        let mut children: Vec<(&Vec<u16>, u64)> = totals
+            .iter()
+            .filter(|(child, _)| child.len() == path.len() + 1 && child.starts_with(path))
+            .map(|(child, cycles)| (child, *cycles))
+            .collect();
+        children.sort_by(|a, b| cmp_desc(a.1, b.1).then(a.0.cmp(b.0)));
+        for (child, _) in children {
+            self.tree_node(paths, totals, child, depth + 1, out);
+        }
+    }
+
+    // One `root;caller;callee cycles` line per call path, the input format
+    // of flamegraph.pl and inferno
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .paths()
+            .iter()
+            .filter(|(_, cycles)| **cycles > 0)
+            .map(|(path, cycles)| {
+                let mut frames = vec![String::from("root")];
+                frames.extend(path.iter().map(|entry| self.label(*entry)));
+                format!("{} {}", frames.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    pub fn write_folded<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.folded())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::mc6502::CpuVariant;
+    use super::super::{ram, rom, IoAddressable};
+    use super::*;
+
+    fn build_cpu(program: &[(u16, &[u8])]) -> CPU6502 {
+        let mut rom_data = vec![0x00; 2 + 0x100];
+        for (addr, code) in program {
+            let at = 2 + (*addr as usize - 0xFF00);
+            rom_data[at..at + code.len()].copy_from_slice(code);
+        }
+        rom_data[2 + 0xfd] = 0xFF;
+        let mut rom = rom::Rom::init_with_size(0x100);
+        rom.flash(&rom_data);
+
+        let the_mapping = vec![
+            AddressMap {
+                addr: [0x0000, 0x0fff],
+                component: Box::new(ram::Ram::init_with_size(0x1000)),
+                name: String::from("RAM"),
+            },
+            AddressMap {
+                addr: [0xff00, 0xffff],
+                component: Box::new(rom),
+                name: String::from("ROM"),
+            },
+        ];
+        let mut cpu = CPU6502::init(AddressSpaces::init(the_mapping), CpuVariant::Nmos6502);
+        cpu.reset();
+        cpu.set_registers(super::super::mc6502::Registers {
+            s: 0xFF,
+            ..cpu.registers()
+        });
+        cpu
+    }
+
+    #[test]
+    fn initial_state() {
+        let profiler = Profiler::init();
+        assert_eq!(0, profiler.total_cycles());
+        assert_eq!(0, profiler.depth());
+        assert!(profiler.flat().is_empty());
+    }
+
+    #[test]
+    fn attributes_cycles_to_routines() {
+        let mut cpu = build_cpu(&[
+            // JSR OUTER; JSR INNER; JMP *
+            (
+                0xFF00,
+                &[0x20, 0x10, 0xFF, 0x20, 0x20, 0xFF, 0x4C, 0x06, 0xFF],
+            ),
+            // OUTER: JSR INNER; RTS
+            (0xFF10, &[0x20, 0x20, 0xFF, 0x60]),
+            // INNER: NOP; RTS
+            (0xFF20, &[0xEA, 0x60]),
+        ]);
+        let mut profiler = Profiler::init();
+        profiler.name(0xFF10, "OUTER");
+        profiler.use_symbols(&Symbols::parse("OUTER_ALIAS = $FF10\n").unwrap());
+        for _ in 0..9 {
+            profiler.step(&mut cpu);
+        }
+        assert_eq!(0xFF06, cpu.registers().pc);
+        assert_eq!(0, profiler.depth());
+
+        let inner = profiler.routine(0xFF20).unwrap();
+        assert_eq!(
+            (2, 16, 16),
+            (inner.calls, inner.self_cycles, inner.total_cycles)
+        );
+        let outer = profiler.routine(0xFF10).unwrap();
+        assert_eq!(
+            (1, 12, 20),
+            (outer.calls, outer.self_cycles, outer.total_cycles)
+        );
+        assert_eq!(15, profiler.root_cycles());
+        assert_eq!(0xFF20, profiler.flat()[0].entry);
+
+        assert_eq!(
+            "root 15\nroot;$FF20 8\nroot;OUTER 12\nroot;OUTER;$FF20 8\n",
+            profiler.folded()
+        );
+        let tree = profiler.call_tree_report();
+        assert!(tree.starts_with("[root] total=43 self=15\n  OUTER total=20 self=12\n    $FF20 total=8 self=8\n  $FF20 total=8 self=8\n"), "{}", tree);
+        assert!(profiler.flat_report().contains("OUTER"));
+    }
+
+    #[test]
+    fn rts_dispatch_stays_in_routine() {
+        let mut cpu = build_cpu(&[
+            // JSR DISPATCH; JMP *
+            (0xFF00, &[0x20, 0x10, 0xFF, 0x4C, 0x03, 0xFF]),
+            // DISPATCH: push $FF17 and "return" to $FF18, then RTS for real
+            (
+                0xFF10,
+                &[0xA9, 0xFF, 0x48, 0xA9, 0x17, 0x48, 0x60, 0x00, 0x60],
+            ),
+        ]);
+        let mut profiler = Profiler::init();
+        for _ in 0..6 {
+            profiler.step(&mut cpu);
+        }
+        assert_eq!(0xFF18, cpu.registers().pc);
+        assert_eq!(1, profiler.depth());
+        profiler.step(&mut cpu);
+        assert_eq!(0, profiler.depth());
+    }
+
+    #[test]
+    fn stack_adjustments_are_not_calls() {
+        // LDX #$FC; TXS; JMP *
+        let mut cpu = build_cpu(&[(0xFF00, &[0xA2, 0xFC, 0x9A, 0x4C, 0x03, 0xFF])]);
+        let mut profiler = Profiler::init();
+        for _ in 0..3 {
+            profiler.step(&mut cpu);
+        }
+        assert_eq!(0xFC, cpu.registers().s);
+        assert_eq!(0, profiler.depth());
+        assert!(profiler.flat().is_empty());
+    }
+
+    #[test]
+    fn brk_is_a_call() {
+        let mut cpu = build_cpu(&[
+            // BRK; NOP; JMP *
+            (0xFF00, &[0x00, 0xEA, 0x4C, 0x02, 0xFF]),
+            // Handler: RTI
+            (0xFF20, &[0x40]),
+            (0xFFFE, &[0x20, 0xFF]),
+        ]);
+        let mut profiler = Profiler::init();
+        profiler.step(&mut cpu);
+        assert_eq!((0xFF20, 1), (cpu.registers().pc, profiler.depth()));
+        profiler.step(&mut cpu);
+        assert_eq!((0xFF02, 0), (cpu.registers().pc, profiler.depth()));
+        assert_eq!(1, profiler.routine(0xFF20).unwrap().calls);
+    }
+}
@@ -0,0 +1,397 @@
+use super::address_spaces::AddressMap;
+use super::ram::Ram;
+use super::rom::Rom;
+use super::IoAddressable;
+use std::error::Error;
+use std::fmt;
+
+// Built-in machine profiles: the RAM and firmware layout each well-known
+// Apple-1 setup expects. Firmware images are supplied by the user and
+// checked against the entry points the profile documents.
+
+#[derive(Debug)]
+pub struct EntryPoint {
+    pub name: &'static str,
+    pub addr: u16,
+}
+
+#[derive(Debug)]
+pub struct ImageSlot {
+    pub name: &'static str,
+    pub addr: [u16; 2],
+    // Loaded into RAM (tape based software) rather than ROM
+    pub writable: bool,
+    pub entry_points: &'static [EntryPoint],
+    // (vector location, expected target) pairs the image must contain
+    pub vectors: &'static [(u16, u16)],
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub ram: &'static [[u16; 2]],
+    pub slots: &'static [ImageSlot],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProfileError {
+    UnknownProfile(String),
+    UnknownImage(String),
+    MissingImage(&'static str),
+    ImageTooLarge {
+        slot: &'static str,
+        size: usize,
+        max: usize,
+    },
+    BadEntryPoint {
+        slot: &'static str,
+        entry: &'static str,
+        addr: u16,
+    },
+    BadVector {
+        slot: &'static str,
+        vector: u16,
+        expected: u16,
+        found: u16,
+    },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::UnknownProfile(name) => write!(
+                f,
+                "unknown profile '{}', expected one of: {}",
+                name,
+                PROFILES
+                    .iter()
+                    .map(|p| p.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ProfileError::UnknownImage(name) => write!(f, "profile has no image slot '{}'", name),
+            ProfileError::MissingImage(slot) => write!(f, "missing image for slot '{}'", slot),
+            ProfileError::ImageTooLarge { slot, size, max } => write!(
+                f,
+                "image for '{}' is {} bytes, slot holds {}",
+                slot, size, max
+            ),
+            ProfileError::BadEntryPoint { slot, entry, addr } => write!(
+                f,
+                "image for '{}' has no code at {} (${:04X})",
+                slot, entry, addr
+            ),
+            ProfileError::BadVector {
+                slot,
+                vector,
+                expected,
+                found,
+            } => write!(
+                f,
+                "image for '{}' has ${:04X} at vector ${:04X}, expected ${:04X}",
+                slot, found, vector, expected
+            ),
+        }
+    }
+}
+
+impl Error for ProfileError {}
+
+const WOZ_MONITOR: ImageSlot = ImageSlot {
+    name: "monitor",
+    addr: [0xFF00, 0xFFFF],
+    writable: false,
+    entry_points: &[
+        EntryPoint {
+            name: "RESET",
+            addr: 0xFF00,
+        },
+        EntryPoint {
+            name: "PRBYTE",
+            addr: 0xFFDC,
+        },
+        EntryPoint {
+            name: "ECHO",
+            addr: 0xFFEF,
+        },
+    ],
+    vectors: &[(0xFFFC, 0xFF00)],
+};
+
+pub static PROFILES: &[Profile] = &[
+    Profile {
+        name: "woz",
+        description: "Stock 4K Apple-1 with the Woz Monitor",
+        ram: &[[0x0000, 0x0FFF]],
+        slots: &[WOZ_MONITOR],
+    },
+    Profile {
+        name: "integer-basic",
+        description: "8K Apple-1 with Integer BASIC loaded at $E000",
+        ram: &[[0x0000, 0x0FFF]],
+        slots: &[
+            ImageSlot {
+                name: "basic",
+                addr: [0xE000, 0xEFFF],
+                writable: true,
+                entry_points: &[
+                    EntryPoint {
+                        name: "COLD",
+                        addr: 0xE000,
+                    },
+                    EntryPoint {
+                        name: "WARM",
+                        addr: 0xE2B3,
+                    },
+                ],
+                vectors: &[],
+            },
+            WOZ_MONITOR,
+        ],
+    },
+    Profile {
+        name: "krusader",
+        description: "Replica-1 style 32K machine with the BASIC/Krusader/monitor ROM at $E000",
+        ram: &[[0x0000, 0x7FFF]],
+        slots: &[ImageSlot {
+            name: "rom",
+            addr: [0xE000, 0xFFFF],
+            writable: false,
+            entry_points: &[
+                EntryPoint {
+                    name: "BASIC",
+                    addr: 0xE000,
+                },
+                EntryPoint {
+                    name: "KRUSADER",
+                    addr: 0xF000,
+                },
+                EntryPoint {
+                    name: "RESET",
+                    addr: 0xFF00,
+                },
+            ],
+            vectors: &[(0xFFFC, 0xFF00)],
+        }],
+    },
+    Profile {
+        name: "a1-assembler",
+        description: "8K Apple-1 with the A1 Assembler loaded at $E000",
+        ram: &[[0x0000, 0x0FFF]],
+        slots: &[
+            ImageSlot {
+                name: "assembler",
+                addr: [0xE000, 0xEFFF],
+                writable: true,
+                entry_points: &[EntryPoint {
+                    name: "START",
+                    addr: 0xE000,
+                }],
+                vectors: &[],
+            },
+            WOZ_MONITOR,
+        ],
+    },
+];
+
+impl ImageSlot {
+    fn size(&self) -> usize {
+        (self.addr[1] - self.addr[0]) as usize + 1
+    }
+
+    fn validate(&self, image: &[u8]) -> Result<(), ProfileError> {
+        if image.len() > self.size() {
+            return Err(ProfileError::ImageTooLarge {
+                slot: self.name,
+                size: image.len(),
+                max: self.size(),
+            });
+        }
+
+        let byte_at = |addr: u16| image.get((addr - self.addr[0]) as usize).copied();
+        for entry in self.entry_points {
+            // Erased or missing bytes can't be the start of a routine
+            match byte_at(entry.addr) {
+                Some(0x00) | Some(0xFF) | None => {
+                    return Err(ProfileError::BadEntryPoint {
+                        slot: self.name,
+                        entry: entry.name,
+                        addr: entry.addr,
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        for &(vector, expected) in self.vectors {
+            let found = u16::from_le_bytes([
+                byte_at(vector).unwrap_or(0),
+                byte_at(vector + 1).unwrap_or(0),
+            ]);
+            if found != expected {
+                return Err(ProfileError::BadVector {
+                    slot: self.name,
+                    vector,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Profile {
+    pub fn by_name(name: &str) -> Result<&'static Profile, ProfileError> {
+        PROFILES
+            .iter()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| ProfileError::UnknownProfile(name.to_string()))
+    }
+
+    pub fn entry_point(&self, name: &str) -> Option<u16> {
+        self.slots
+            .iter()
+            .flat_map(|slot| slot.entry_points.iter())
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.addr)
+    }
+
+    // Builds the memory map, `images` pairs a slot name with its raw bytes
+    pub fn build(&self, images: &[(&str, &[u8])]) -> Result<Vec<AddressMap>, ProfileError> {
+        if let Some((name, _)) = images
+            .iter()
+            .find(|(name, _)| !self.slots.iter().any(|slot| slot.name == *name))
+        {
+            return Err(ProfileError::UnknownImage(name.to_string()));
+        }
+
+        let mut the_mapping = Vec::new();
+        for range in self.ram {
+            the_mapping.push(AddressMap {
+                addr: *range,
+                component: Box::new(Ram::init_with_size((range[1] - range[0]) as usize + 1)),
+                name: String::from("RAM"),
+            });
+        }
+
+        for slot in self.slots {
+            let image = images
+                .iter()
+                .find(|(name, _)| *name == slot.name)
+                .map(|(_, image)| *image)
+                .ok_or(ProfileError::MissingImage(slot.name))?;
+            slot.validate(image)?;
+
+            let mut data = vec![0x00, 0x00];
+            data.extend_from_slice(image);
+            let mut component: Box<dyn IoAddressable> = if slot.writable {
+                Box::new(Ram::init_with_size(slot.size()))
+            } else {
+                Box::new(Rom::init_with_size(slot.size()))
+            };
+            component.flash(&data);
+
+            the_mapping.push(AddressMap {
+                addr: slot.addr,
+                component,
+                name: slot.name.to_uppercase(),
+            });
+        }
+
+        Ok(the_mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::AddressSpaces;
+    use super::*;
+
+    fn fake_monitor() -> Vec<u8> {
+        let mut monitor = vec![0xEA; 0x100];
+        monitor[0xFC] = 0x00;
+        monitor[0xFD] = 0xFF;
+        monitor
+    }
+
+    #[test]
+    fn by_name() {
+        assert_eq!("krusader", Profile::by_name("krusader").unwrap().name);
+        assert_eq!(
+            ProfileError::UnknownProfile(String::from("apple2")),
+            Profile::by_name("apple2").unwrap_err()
+        );
+        assert_eq!(
+            Some(0xE2B3),
+            Profile::by_name("integer-basic")
+                .unwrap()
+                .entry_point("WARM")
+        );
+    }
+
+    #[test]
+    fn builds_integer_basic() {
+        let monitor = fake_monitor();
+        let basic = vec![0x4C; 0x1000];
+        let the_mapping = Profile::by_name("integer-basic")
+            .unwrap()
+            .build(&[("basic", &basic), ("monitor", &monitor)])
+            .unwrap();
+
+        let mut spaces = AddressSpaces::init(the_mapping);
+        assert_eq!(0x4C, spaces.read(0xE2B3));
+        assert_eq!(0xFF, spaces.read(0xFFFD));
+        // BASIC lives in RAM
+        spaces.write(0xE000, 0x60);
+        assert_eq!(0x60, spaces.read(0xE000));
+        // the monitor doesn't
+        spaces.write(0xFF00, 0x60);
+        assert_eq!(0xEA, spaces.read(0xFF00));
+    }
+
+    #[test]
+    fn validates_images() {
+        let profile = Profile::by_name("integer-basic").unwrap();
+        let monitor = fake_monitor();
+
+        assert_eq!(
+            ProfileError::MissingImage("basic"),
+            profile.build(&[("monitor", &monitor)]).unwrap_err()
+        );
+
+        let short_basic = vec![0x4C; 0x100];
+        assert_eq!(
+            ProfileError::BadEntryPoint {
+                slot: "basic",
+                entry: "WARM",
+                addr: 0xE2B3
+            },
+            profile
+                .build(&[("basic", &short_basic), ("monitor", &monitor)])
+                .unwrap_err()
+        );
+
+        let basic = vec![0x4C; 0x1000];
+        let mut bad_monitor = fake_monitor();
+        bad_monitor[0xFD] = 0xE0;
+        assert_eq!(
+            ProfileError::BadVector {
+                slot: "monitor",
+                vector: 0xFFFC,
+                expected: 0xFF00,
+                found: 0xE000
+            },
+            profile
+                .build(&[("basic", &basic), ("monitor", &bad_monitor)])
+                .unwrap_err()
+        );
+
+        let huge = vec![0x4C; 0x2000];
+        assert!(profile
+            .build(&[("basic", &huge), ("monitor", &monitor)])
+            .is_err());
+    }
+}
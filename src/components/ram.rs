@@ -20,24 +20,33 @@ impl Ram {
 impl IoAddressable for Ram {
     fn read(&mut self, address: usize) -> u8 {
         self.read_ops += 1;
-        return if self.data.len() > address {
+        if self.data.len() > address {
             self.data[address]
         } else {
             0
-        };
+        }
     }
 
-    fn write(&mut self, address: usize, value: u8) -> () {
+    fn write(&mut self, address: usize, value: u8) {
         self.write_ops += 1;
         self.data[address] = value;
     }
-    fn flash(&mut self, data: &Vec<u8>) -> () {
-        let prg_addr = u16::from_be_bytes([data[0], data[1]]);
+    fn peek(&self, address: usize) -> u8 {
+        self.data.get(address).copied().unwrap_or(0)
+    }
 
-        for i in 2..data.len() {
-            self.data[(prg_addr as usize) + i - 2] = data[i];
+    fn poke(&mut self, address: usize, value: u8) {
+        if let Some(data) = self.data.get_mut(address) {
+            *data = value;
         }
     }
+
+    fn flash(&mut self, data: &[u8]) {
+        let prg_addr = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let payload = &data[2..];
+
+        self.data[prg_addr..prg_addr + payload.len()].copy_from_slice(payload);
+    }
 }
 
 #[cfg(test)]
@@ -56,7 +65,7 @@ mod tests {
     #[test]
     fn initial_flash() {
         let mut rom = Ram::init_with_size(4);
-        rom.flash(&vec![0, 2, 3, 4]);
+        rom.flash(&[0, 2, 3, 4]);
         assert_eq!(0x0, rom.read(0));
         assert_eq!(0x0, rom.read(1));
         assert_eq!(3, rom.read(2));
@@ -91,4 +100,14 @@ mod tests {
         ram.write(0, 10);
         assert_eq!(3, ram.write_ops);
     }
+
+    #[test]
+    fn peek_does_not_count() {
+        let mut mem = Ram::init_with_size(4);
+        mem.poke(1, 0x42);
+        assert_eq!(0x42, mem.peek(1));
+        assert_eq!(0x00, mem.peek(9));
+        assert_eq!(0, mem.read_ops);
+        assert_eq!(0, mem.write_ops);
+    }
 }
@@ -0,0 +1,275 @@
+use super::aci::APPLE1_CLOCK_HZ;
+use super::display::{Display, CYCLES_PER_CHAR};
+use super::frame::Frame;
+use super::gif::GifWriter;
+use super::machine::Machine;
+use super::render::{cursor_visible, Renderer};
+use super::scheduler::{EventId, Scheduler};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Records the rendered screen every `interval` emulated cycles, from a
+// periodic scheduler event. Frames are stamped with the cycle the event was
+// due at and rendered from the machine state at that point, so the same
+// program and input give the same recording however fast the host is.
+//
+// A GIF only gets a new image when the screen changes, the delays adding up
+// to the emulated time between changes. A frame sequence writes every
+// sample as frame-000000.ppm, frame-000001.ppm, ... for a constant frame
+// rate, e.g. `ffmpeg -framerate 10 -i frame-%06d.ppm`.
+
+// Every sixth video frame, about 10 per second
+pub const DEFAULT_INTERVAL: usize = 6 * CYCLES_PER_CHAR;
+
+fn centiseconds(cycles: usize) -> usize {
+    (cycles as f64 * 100.0 / APPLE1_CLOCK_HZ) as usize
+}
+
+enum Sink {
+    Gif {
+        writer: GifWriter,
+        // Last distinct frame and the cycle it first appeared at
+        pending: Option<(Frame, usize)>,
+    },
+    Frames {
+        dir: PathBuf,
+    },
+}
+
+struct RecorderState {
+    renderer: Renderer,
+    display: Option<Display>,
+    sink: Sink,
+    frames: usize,
+    error: Option<io::Error>,
+}
+
+impl RecorderState {
+    fn capture(&mut self, at: usize) -> io::Result<()> {
+        let display = match &self.display {
+            Some(display) => display,
+            None => return Ok(()),
+        };
+        let frame = self.renderer.render(display, cursor_visible(at));
+        match &mut self.sink {
+            Sink::Gif { writer, pending } => match pending {
+                Some((last, _)) if *last == frame => {}
+                Some((last, start)) => {
+                    writer.frame(last, delay(*start, at))?;
+                    *pending = Some((frame, at));
+                }
+                None => *pending = Some((frame, at)),
+            },
+            Sink::Frames { dir } => {
+                frame.save(dir.join(format!("frame-{:06}.ppm", self.frames)))?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, end: usize) -> io::Result<()> {
+        if let Sink::Gif { writer, pending } = &mut self.sink {
+            if let Some((last, start)) = pending.take() {
+                writer.frame(&last, delay(start, end))?;
+            }
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn delay(start: usize, end: usize) -> u16 {
+    (centiseconds(end) - centiseconds(start)).clamp(1, u16::MAX as usize) as u16
+}
+
+pub struct Recorder {
+    state: Rc<RefCell<RecorderState>>,
+    interval: usize,
+    event: Option<(Scheduler, EventId)>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("interval", &self.interval)
+            .field("frames", &self.frame_count())
+            .field("recording", &self.event.is_some())
+            .finish()
+    }
+}
+
+impl Recorder {
+    fn init(renderer: Renderer, sink: Sink) -> Recorder {
+        Recorder {
+            state: Rc::new(RefCell::new(RecorderState {
+                renderer,
+                display: None,
+                sink,
+                frames: 0,
+                error: None,
+            })),
+            interval: DEFAULT_INTERVAL,
+            event: None,
+        }
+    }
+
+    pub fn gif(out: Box<dyn io::Write>, renderer: Renderer) -> io::Result<Recorder> {
+        let (width, height) = renderer.size();
+        let writer = GifWriter::init(out, width, height, &renderer.palette())?;
+        Ok(Recorder::init(
+            renderer,
+            Sink::Gif {
+                writer,
+                pending: None,
+            },
+        ))
+    }
+
+    // Numbered PPM files in `dir`, created if needed
+    pub fn frames<P: AsRef<Path>>(dir: P, renderer: Renderer) -> io::Result<Recorder> {
+        fs::create_dir_all(&dir)?;
+        Ok(Recorder::init(
+            renderer,
+            Sink::Frames {
+                dir: dir.as_ref().to_path_buf(),
+            },
+        ))
+    }
+
+    // A GIF file for a .gif path, otherwise a directory of frames
+    pub fn create<P: AsRef<Path>>(path: P, renderer: Renderer) -> io::Result<Recorder> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("gif") => {
+                let out = BufWriter::new(File::create(path)?);
+                Recorder::gif(Box::new(out), renderer)
+            }
+            _ => Recorder::frames(path, renderer),
+        }
+    }
+
+    pub fn interval(mut self, cycles: usize) -> Recorder {
+        self.interval = cycles.max(1);
+        self
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.state.borrow().frames
+    }
+
+    // Takes the first frame now and schedules the rest
+    pub fn start(&mut self, machine: &Machine) {
+        self.stop();
+        let now = machine.cycles();
+        let interval = self.interval;
+        {
+            let mut state = self.state.borrow_mut();
+            state.display = Some(machine.display.clone());
+            if let Err(err) = state.capture(now) {
+                state.error = Some(err);
+                return;
+            }
+        }
+
+        let scheduler = machine.cpu.scheduler();
+        let state = self.state.clone();
+        let id = scheduler.schedule_at(now + interval, move |at| {
+            let mut state = state.borrow_mut();
+            match state.capture(at) {
+                Ok(()) => Some(at + interval),
+                Err(err) => {
+                    state.error = Some(err);
+                    None
+                }
+            }
+        });
+        self.event = Some((scheduler, id));
+    }
+
+    // Pauses; `start` picks up again with the same output
+    pub fn stop(&mut self) {
+        if let Some((scheduler, id)) = self.event.take() {
+            scheduler.cancel(id);
+        }
+    }
+
+    // Stops and completes the output, returning the number of samples taken
+    // or the first error hit while recording
+    pub fn finish(mut self, machine: &Machine) -> io::Result<usize> {
+        self.stop();
+        let mut state = self.state.borrow_mut();
+        if let Some(err) = state.error.take() {
+            return Err(err);
+        }
+        state.finish(machine.cycles())?;
+        Ok(state.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{echo_machine, Shared};
+    use super::*;
+
+    fn record_gif() -> (Vec<u8>, usize) {
+        let mut machine = echo_machine();
+        let out = Shared::default();
+        let mut recorder = Recorder::gif(Box::new(out.clone()), Renderer::init().scale(1))
+            .unwrap()
+            .interval(DEFAULT_INTERVAL / 2);
+        recorder.start(&machine);
+        machine.run_cycles(DEFAULT_INTERVAL);
+        machine.feeder.type_text("HELLO\r");
+        machine.run_cycles(4 * DEFAULT_INTERVAL);
+        let frames = recorder.finish(&machine).unwrap();
+        let data = out.bytes();
+        (data, frames)
+    }
+
+    #[test]
+    fn records_deterministic_gif() {
+        let (data, frames) = record_gif();
+        // First frame at cycle 0, then every half interval. The one due as
+        // the run ends would fire before the next instruction.
+        assert_eq!(10, frames);
+        assert_eq!(b"GIF89a", &data[..6]);
+        assert_eq!(Some(&0x3B), data.last());
+
+        // Delays cover the recording in emulated time, however it was split
+        let mut total = 0;
+        let mut images = 0;
+        for (at, window) in data.windows(4).enumerate() {
+            if window == [0x21, 0xF9, 0x04, 0x00] {
+                total += u16::from_le_bytes([data[at + 4], data[at + 5]]) as usize;
+                images += 1;
+            }
+        }
+        assert!(images > 1 && images < frames, "{} images", images);
+        assert_eq!(centiseconds(5 * DEFAULT_INTERVAL), total);
+
+        assert_eq!(data, record_gif().0);
+    }
+
+    #[test]
+    fn records_frame_sequence() {
+        let dir = std::env::temp_dir().join(format!("recorder-{}", std::process::id()));
+        let mut machine = echo_machine();
+        let mut recorder = Recorder::create(&dir, Renderer::init()).unwrap();
+        recorder.start(&machine);
+        machine.run_cycles(2 * DEFAULT_INTERVAL);
+        recorder.stop();
+        machine.run_cycles(2 * DEFAULT_INTERVAL);
+        assert_eq!(2, recorder.frame_count());
+        assert_eq!(2, recorder.finish(&machine).unwrap());
+
+        let ppm = fs::read(dir.join("frame-000001.ppm")).unwrap();
+        assert!(ppm.starts_with(b"P6\n480 384\n255\n"));
+        assert!(!dir.join("frame-000002.ppm").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use super::aci::APPLE1_CLOCK_HZ;
+use super::display::{Display, COLUMNS, ROWS};
+use super::frame::Frame;
+
+// Software renderer for the 40x24 terminal. Characters come from the
+// Signetics 2513 character generator the Apple-1 uses: 64 glyphs of 5x7
+// dots, addressed by the low six bits of the character. The 2513 has no
+// lowercase, so lowercase codes show the punctuation glyphs just as on the
+// real machine. The cursor is the blinking @ the terminal draws at the
+// cursor position.
+//
+// Each character cell is 6x8 dots, giving a 240x192 picture that is then
+// scaled up. Scanlines dim the bottom line of every scaled dot row; the
+// green phosphor tints the picture and lets lit dots glow into their
+// neighbours.
+
+pub const CELL_WIDTH: usize = 6;
+pub const CELL_HEIGHT: usize = 8;
+pub const WIDTH: usize = COLUMNS * CELL_WIDTH;
+pub const HEIGHT: usize = ROWS * CELL_HEIGHT;
+
+// The 555 timer blinks the cursor at roughly 1Hz; half a period in cycles
+pub const CURSOR_BLINK_CYCLES: usize = (APPLE1_CLOCK_HZ / 2.0) as usize;
+
+const CURSOR: u8 = b'@';
+
+// Dot brightness: lit, phosphor glow, and both dimmed by a scanline
+const LIT: u8 = 0xFF;
+const GLOW: u8 = 0x40;
+const LEVELS: [u8; 5] = [0, LIT, GLOW, LIT / 2, GLOW / 2];
+
+// Rows top to bottom, bit 4 is the leftmost dot. Indexed by code & 0x3F:
+// @ A-Z [ \ ] ^ _ first, then space through ?.
+#[rustfmt::skip]
+pub const FONT: [[u8; 7]; 64] = [
+    [0x0E, 0x11, 0x15, 0x17, 0x16, 0x10, 0x0F], // @
+    [0x04, 0x0A, 0x11, 0x11, 0x1F, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0F, 0x10, 0x10, 0x13, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x01, 0x01, 0x01, 0x01, 0x01, 0x11, 0x0E], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0E, 0x11, 0x10, 0x0E, 0x01, 0x11, 0x0E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x1B, 0x11], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x1F, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1F], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+    [0x1F, 0x03, 0x03, 0x03, 0x03, 0x03, 0x1F], // ]
+    [0x00, 0x00, 0x04, 0x0A, 0x11, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A], // #
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x08, 0x14, 0x14, 0x08, 0x15, 0x12, 0x0D], // &
+    [0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x04, 0x15, 0x0E, 0x04, 0x0E, 0x15, 0x04], // *
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x04, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x06, 0x08, 0x10, 0x1F], // 2
+    [0x1F, 0x01, 0x02, 0x06, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x07, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x1C], // 9
+    [0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x00], // :
+    [0x00, 0x00, 0x04, 0x00, 0x04, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0E, 0x11, 0x02, 0x04, 0x04, 0x00, 0x04], // ?
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phosphor {
+    White,
+    Green,
+}
+
+impl Phosphor {
+    fn colour(self) -> [u8; 3] {
+        match self {
+            Phosphor::White => [0xFF, 0xFF, 0xFF],
+            Phosphor::Green => [0x33, 0xFF, 0x33],
+        }
+    }
+}
+
+pub fn glyph(code: u8) -> &'static [u8; 7] {
+    &FONT[(code & 0x3F) as usize]
+}
+
+fn shade(colour: [u8; 3], level: u8) -> [u8; 3] {
+    colour.map(|c| (c as u32 * level as u32 / 255) as u8)
+}
+
+// Whether the blinking cursor is lit at this cycle count
+pub fn cursor_visible(cycles: usize) -> bool {
+    (cycles / CURSOR_BLINK_CYCLES).is_multiple_of(2)
+}
+
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    scale: usize,
+    scanlines: bool,
+    phosphor: Phosphor,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer::init()
+    }
+}
+
+impl Renderer {
+    pub fn init() -> Renderer {
+        Renderer {
+            scale: 2,
+            scanlines: false,
+            phosphor: Phosphor::White,
+        }
+    }
+
+    pub fn scale(mut self, scale: usize) -> Renderer {
+        self.scale = scale.max(1);
+        self
+    }
+
+    // No visible effect at scale 1
+    pub fn scanlines(mut self, scanlines: bool) -> Renderer {
+        self.scanlines = scanlines;
+        self
+    }
+
+    pub fn phosphor(mut self, phosphor: Phosphor) -> Renderer {
+        self.phosphor = phosphor;
+        self
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (WIDTH * self.scale, HEIGHT * self.scale)
+    }
+
+    // Every colour a rendered frame can contain, black first
+    pub fn palette(&self) -> Vec<[u8; 3]> {
+        let colour = self.phosphor.colour();
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        for level in LEVELS {
+            let rgb = shade(colour, level);
+            if !palette.contains(&rgb) {
+                palette.push(rgb);
+            }
+        }
+        palette
+    }
+
+    // Dot brightness at native resolution, 0 to 255
+    fn dots(&self, screen: &[[u8; COLUMNS]; ROWS], cursor: Option<(usize, usize)>) -> Vec<u8> {
+        let mut dots = vec![0u8; WIDTH * HEIGHT];
+        for (row, codes) in screen.iter().enumerate() {
+            for (column, code) in codes.iter().enumerate() {
+                let code = match cursor {
+                    Some(at) if at == (column, row) => CURSOR,
+                    _ => *code,
+                };
+                for (line, bits) in glyph(code).iter().enumerate() {
+                    let y = row * CELL_HEIGHT + line;
+                    for dot in 0..5 {
+                        if bits & (0x10 >> dot) != 0 {
+                            dots[y * WIDTH + column * CELL_WIDTH + dot] = LIT;
+                        }
+                    }
+                }
+            }
+        }
+        if self.phosphor == Phosphor::Green {
+            let lit = dots.clone();
+            for (i, dot) in dots.iter_mut().enumerate() {
+                let x = i % WIDTH;
+                let left = x > 0 && lit[i - 1] != 0;
+                let right = x + 1 < WIDTH && lit[i + 1] != 0;
+                if *dot == 0 && (left || right) {
+                    *dot = GLOW;
+                }
+            }
+        }
+        dots
+    }
+
+    pub fn render(&self, display: &Display, cursor_on: bool) -> Frame {
+        let (width, height) = self.size();
+        let mut frame = Frame::init(width, height);
+        self.render_into(display, cursor_on, &mut frame);
+        frame
+    }
+
+    // Reuses the frame, e.g. a window's back buffer, resizing it if needed
+    pub fn render_into(&self, display: &Display, cursor_on: bool, frame: &mut Frame) {
+        let (width, height) = self.size();
+        if frame.width() != width || frame.height() != height {
+            *frame = Frame::init(width, height);
+        }
+        let cursor = if cursor_on {
+            Some(display.cursor())
+        } else {
+            None
+        };
+        let dots = self.dots(&display.screen(), cursor);
+        let colour = self.phosphor.colour();
+        for y in 0..height {
+            let dim = self.scanlines && self.scale > 1 && y % self.scale == self.scale - 1;
+            for x in 0..width {
+                let mut level = dots[(y / self.scale) * WIDTH + x / self.scale];
+                if dim {
+                    level /= 2;
+                }
+                frame.set_pixel(x, y, shade(colour, level));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::IoComponent;
+    use super::*;
+
+    fn print(display: &mut Display, text: &str) {
+        for byte in text.bytes() {
+            display.output(byte | 0x80);
+        }
+    }
+
+    #[test]
+    fn draws_glyphs_and_cursor() {
+        let mut display = Display::init(false);
+        print(&mut display, "A");
+        let renderer = Renderer::init().scale(1);
+        let frame = renderer.render(&display, false);
+        assert_eq!((WIDTH, HEIGHT), (frame.width(), frame.height()));
+
+        // Top row of A is ..#..
+        let lit: Vec<bool> = (0..6).map(|x| frame.pixel(x, 0)[0] != 0).collect();
+        assert_eq!(vec![false, false, true, false, false, false], lit);
+        // Cursor cell is blank until the @ blinks on
+        let cursor_dot = (CELL_WIDTH + 1, 0);
+        assert_eq!([0, 0, 0], frame.pixel(cursor_dot.0, cursor_dot.1));
+        let frame = renderer.render(&display, true);
+        assert_eq!([0xFF; 3], frame.pixel(cursor_dot.0, cursor_dot.1));
+
+        assert!(cursor_visible(0));
+        assert!(!cursor_visible(CURSOR_BLINK_CYCLES));
+        assert_eq!(glyph(b'!'), glyph(b'a'));
+    }
+
+    #[test]
+    fn scales_with_effects() {
+        let mut display = Display::init(false);
+        print(&mut display, "I");
+        let renderer = Renderer::init()
+            .scale(3)
+            .scanlines(true)
+            .phosphor(Phosphor::Green);
+        let frame = renderer.render(&display, false);
+        assert_eq!((WIDTH * 3, HEIGHT * 3), (frame.width(), frame.height()));
+
+        // Middle dot of the I's top bar, then its scanline, then the glow
+        assert_eq!([0x33, 0xFF, 0x33], frame.pixel(2 * 3, 0));
+        assert_eq!([0x19, 0x7F, 0x19], frame.pixel(2 * 3, 2));
+        assert_eq!([0x0C, 0x40, 0x0C], frame.pixel(3 * 3, 3));
+
+        let palette = renderer.palette();
+        assert_eq!(5, palette.len());
+        assert!(frame
+            .pixels()
+            .chunks_exact(3)
+            .all(|p| palette.contains(&[p[0], p[1], p[2]])));
+
+        let mut reused = Frame::init(1, 1);
+        renderer.render_into(&display, false, &mut reused);
+        assert_eq!(frame, reused);
+    }
+}
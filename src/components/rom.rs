@@ -18,20 +18,30 @@ impl Rom {
 impl IoAddressable for Rom {
     fn read(&mut self, address: usize) -> u8 {
         self.read_ops += 1;
-        return if self.data.len() > address {
+        if self.data.len() > address {
             self.data[address]
         } else {
             0
-        };
+        }
     }
 
-    fn write(&mut self, _address: usize, _value: u8) -> () {}
+    fn write(&mut self, _address: usize, _value: u8) {}
+
+    fn peek(&self, address: usize) -> u8 {
+        self.data.get(address).copied().unwrap_or(0)
+    }
 
-    fn flash(&mut self, data: &Vec<u8>) -> () {
-        for i in 2..data.len() {
-            self.data[i - 2] = data[i];
+    fn poke(&mut self, address: usize, value: u8) {
+        if let Some(data) = self.data.get_mut(address) {
+            *data = value;
         }
     }
+
+    fn flash(&mut self, data: &[u8]) {
+        let payload = &data[2..];
+
+        self.data[..payload.len()].copy_from_slice(payload);
+    }
 }
 
 #[cfg(test)]
@@ -50,7 +60,7 @@ mod tests {
     #[test]
     fn initial_flash() {
         let mut rom = Rom::init_with_size(4);
-        rom.flash(&vec![1, 2, 3, 4, 5, 6]);
+        rom.flash(&[1, 2, 3, 4, 5, 6]);
         assert_eq!(3, rom.read(0));
         assert_eq!(4, rom.read(1));
         assert_eq!(5, rom.read(2));
@@ -72,4 +82,13 @@ mod tests {
         rom.read(0);
         assert_eq!(3, rom.read_ops);
     }
+
+    #[test]
+    fn peek_does_not_count() {
+        let mut mem = Rom::init_with_size(4);
+        mem.poke(1, 0x42);
+        assert_eq!(0x42, mem.peek(1));
+        assert_eq!(0x00, mem.peek(9));
+        assert_eq!(0, mem.read_ops);
+    }
 }
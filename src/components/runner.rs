@@ -0,0 +1,466 @@
+use super::golden;
+use super::machine::Machine;
+use super::recorder::{Recorder, DEFAULT_INTERVAL};
+use super::render::{cursor_visible, Renderer};
+use super::symbols::Symbols;
+use regex::Regex;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+
+// Headless script runner. One step per line, `#` starts a comment:
+//
+//   type "E000R\r"          queue keys (\r, \n, \\ and \" escapes)
+//   wait /regex/ [cycles]   run until the display output matches
+//   run 100000              run for a number of cycles
+//   assert mem $0300 = $A9
+//   assert reg PC = $FF1F   A, X, Y, S, P or PC
+//   load prog.bin $0300     copy a binary image into memory
+//   reset
+//   screen                  dump the 40x24 screen to the output
+//   golden screen.txt       compare the screen with a golden file
+//   screenshot shot.png     render the screen to a PNG (or .ppm) file
+//   record run.gif [cycles] record the screen to a GIF (or a directory of
+//                           numbered .ppm frames) every `cycles`
+//   record stop             finish the recording, also done at the end
+//   symbols prog.sym        load symbols on top of the machine's
+//   break ECHO [cycles]     run until PC reaches a symbol or address
+
+const DEFAULT_WAIT_CYCLES: usize = 5_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    PC,
+}
+
+#[derive(Debug, Clone)]
+pub enum ScriptStep {
+    Type(String),
+    Wait { pattern: Regex, timeout: usize },
+    Run(usize),
+    AssertMemory { addr: u16, value: u8 },
+    AssertRegister { register: Register, value: u16 },
+    Load { path: String, addr: u16 },
+    Reset,
+    Screen,
+    Golden(String),
+    Screenshot(String),
+    Record { path: String, interval: usize },
+    StopRecording,
+    Symbols(String),
+    Break { target: String, timeout: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    steps: Vec<(usize, ScriptStep)>,
+}
+
+pub fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix('$') {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn parse_quoted(text: &str) -> Result<String, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted string, got {}", text))?;
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            other => return Err(format!("unknown escape \\{}", other.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_register(text: &str) -> Result<Register, String> {
+    match text.to_uppercase().as_str() {
+        "A" => Ok(Register::A),
+        "X" => Ok(Register::X),
+        "Y" => Ok(Register::Y),
+        "S" | "SP" => Ok(Register::S),
+        "P" => Ok(Register::P),
+        "PC" => Ok(Register::PC),
+        _ => Err(format!("unknown register {}", text)),
+    }
+}
+
+fn number<T: TryFrom<u32>>(text: &str) -> Result<T, String> {
+    parse_number(text)
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("bad number {}", text))
+}
+
+// `assert <mem ADDR|reg NAME> = VALUE`
+fn parse_assert(rest: &str) -> Result<ScriptStep, String> {
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    match words.as_slice() {
+        ["mem", addr, "=", value] => Ok(ScriptStep::AssertMemory {
+            addr: number(addr)?,
+            value: number(value)?,
+        }),
+        ["reg", register, "=", value] => Ok(ScriptStep::AssertRegister {
+            register: parse_register(register)?,
+            value: number(value)?,
+        }),
+        _ => Err(String::from(
+            "expected 'assert mem ADDR = VALUE' or 'assert reg NAME = VALUE'",
+        )),
+    }
+}
+
+fn parse_wait(rest: &str) -> Result<ScriptStep, String> {
+    let end = rest
+        .rfind('/')
+        .filter(|end| rest.starts_with('/') && *end > 0)
+        .ok_or_else(|| String::from("expected /regex/"))?;
+    let pattern = Regex::new(&rest[1..end]).map_err(|err| err.to_string())?;
+    let timeout = match rest[end + 1..].trim() {
+        "" => DEFAULT_WAIT_CYCLES,
+        cycles => number(cycles)?,
+    };
+    Ok(ScriptStep::Wait { pattern, timeout })
+}
+
+fn parse_step(line: &str) -> Result<ScriptStep, String> {
+    let (command, rest) = match line.find(char::is_whitespace) {
+        Some(at) => (&line[..at], line[at..].trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        "type" => Ok(ScriptStep::Type(parse_quoted(rest)?)),
+        "wait" => parse_wait(rest),
+        "run" => Ok(ScriptStep::Run(number(rest)?)),
+        "assert" => parse_assert(rest),
+        "load" => match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [path, addr] => Ok(ScriptStep::Load {
+                path: path.to_string(),
+                addr: number(addr)?,
+            }),
+            _ => Err(String::from("expected 'load PATH ADDR'")),
+        },
+        "reset" => Ok(ScriptStep::Reset),
+        "screen" => Ok(ScriptStep::Screen),
+        "golden" if !rest.is_empty() => Ok(ScriptStep::Golden(rest.to_string())),
+        "screenshot" if !rest.is_empty() => Ok(ScriptStep::Screenshot(rest.to_string())),
+        "record" => match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["stop"] => Ok(ScriptStep::StopRecording),
+            [path] => Ok(ScriptStep::Record {
+                path: path.to_string(),
+                interval: DEFAULT_INTERVAL,
+            }),
+            [path, cycles] => Ok(ScriptStep::Record {
+                path: path.to_string(),
+                interval: number(cycles)?,
+            }),
+            _ => Err(String::from(
+                "expected 'record PATH [cycles]' or 'record stop'",
+            )),
+        },
+        "symbols" if !rest.is_empty() => Ok(ScriptStep::Symbols(rest.to_string())),
+        "break" => match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [target] => Ok(ScriptStep::Break {
+                target: target.to_string(),
+                timeout: DEFAULT_WAIT_CYCLES,
+            }),
+            [target, cycles] => Ok(ScriptStep::Break {
+                target: target.to_string(),
+                timeout: number(cycles)?,
+            }),
+            _ => Err(String::from("expected 'break SYMBOL [cycles]'")),
+        },
+        _ => Err(format!("unknown command {}", command)),
+    }
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, ScriptError> {
+        let mut steps = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_step(line).map_err(|message| ScriptError {
+                line: i + 1,
+                message,
+            })?;
+            steps.push((i + 1, step));
+        }
+        Ok(Script { steps })
+    }
+
+    pub fn run(&self, machine: &mut Machine, out: &mut dyn Write) -> Result<(), ScriptError> {
+        // Display output not yet matched by a `wait`
+        let output = machine.display.subscribe();
+        let mut unmatched = String::new();
+        let mut symbols = machine.symbols.clone();
+        let mut recording: Option<(usize, Recorder)> = None;
+
+        for (line, step) in &self.steps {
+            let fail = |message: String| ScriptError {
+                line: *line,
+                message,
+            };
+
+            match step {
+                ScriptStep::Type(text) => machine.feeder.type_text(text),
+                ScriptStep::Wait { pattern, timeout } => {
+                    let until = machine.cycles() + timeout;
+                    unmatched.push_str(&output.take());
+                    // Only matched again when the display printed something
+                    let mut grown = true;
+                    loop {
+                        if grown {
+                            if let Some(found) = pattern.find(&unmatched) {
+                                let end = found.end();
+                                unmatched.drain(..end);
+                                break;
+                            }
+                        }
+                        if machine.cycles() >= until {
+                            return Err(fail(format!(
+                                "timed out after {} cycles waiting for /{}/, display shows {:?}",
+                                timeout,
+                                pattern.as_str(),
+                                unmatched
+                            )));
+                        }
+                        machine.step();
+                        let printed = output.take();
+                        grown = !printed.is_empty();
+                        unmatched.push_str(&printed);
+                    }
+                }
+                ScriptStep::Run(cycles) => machine.run_cycles(*cycles),
+                ScriptStep::AssertMemory { addr, value } => {
+                    let found = machine.peek(*addr);
+                    if found != *value {
+                        return Err(fail(format!(
+                            "expected ${:02X} at ${:04X}, found ${:02X}",
+                            value, addr, found
+                        )));
+                    }
+                }
+                ScriptStep::AssertRegister { register, value } => {
+                    let registers = machine.cpu.registers();
+                    let found = match register {
+                        Register::A => registers.a as u16,
+                        Register::X => registers.x as u16,
+                        Register::Y => registers.y as u16,
+                        Register::S => registers.s as u16,
+                        Register::P => registers.p as u16,
+                        Register::PC => registers.pc,
+                    };
+                    if found != *value {
+                        return Err(fail(format!(
+                            "expected {:?} = ${:02X}, found ${:02X}",
+                            register, value, found
+                        )));
+                    }
+                }
+                ScriptStep::Load { path, addr } => {
+                    let data = fs::read(path).map_err(|err| fail(format!("{}: {}", path, err)))?;
+                    machine.load(*addr, &data);
+                }
+                ScriptStep::Reset => machine.reset(),
+                ScriptStep::Screen => {
+                    let _ = write!(out, "{}", machine.display.screen_text());
+                }
+                ScriptStep::Golden(path) => {
+                    golden::compare(&machine.display.screen_text(), path)
+                        .map_err(|err| fail(err.to_string()))?;
+                }
+                ScriptStep::Screenshot(path) => {
+                    Renderer::init()
+                        .render(&machine.display, cursor_visible(machine.cycles()))
+                        .save(path)
+                        .map_err(|err| fail(format!("{}: {}", path, err)))?;
+                }
+                ScriptStep::Record { path, interval } => {
+                    if let Some((started, recorder)) = recording.take() {
+                        finish_recording(started, recorder, machine)?;
+                    }
+                    let mut recorder = Recorder::create(path, Renderer::init())
+                        .map_err(|err| fail(format!("{}: {}", path, err)))?
+                        .interval(*interval);
+                    recorder.start(machine);
+                    recording = Some((*line, recorder));
+                }
+                ScriptStep::StopRecording => match recording.take() {
+                    Some((started, recorder)) => finish_recording(started, recorder, machine)?,
+                    None => return Err(fail(String::from("not recording"))),
+                },
+                ScriptStep::Symbols(path) => {
+                    let loaded =
+                        Symbols::load(path).map_err(|err| fail(format!("{}: {}", path, err)))?;
+                    symbols.extend(&loaded);
+                }
+                ScriptStep::Break { target, timeout } => {
+                    let addr = symbols
+                        .resolve(target)
+                        .ok_or_else(|| fail(format!("unknown symbol {}", target)))?;
+                    let until = machine.cycles() + timeout;
+                    machine.step();
+                    while machine.cpu.registers().pc != addr {
+                        if machine.cycles() >= until {
+                            return Err(fail(format!(
+                                "timed out after {} cycles waiting for {} (${:04X})",
+                                timeout, target, addr
+                            )));
+                        }
+                        machine.step();
+                    }
+                }
+            }
+        }
+
+        if let Some((started, recorder)) = recording {
+            finish_recording(started, recorder, machine)?;
+        }
+        Ok(())
+    }
+}
+
+// Errors are reported against the `record` line
+fn finish_recording(line: usize, recorder: Recorder, machine: &Machine) -> Result<(), ScriptError> {
+    recorder
+        .finish(machine)
+        .map(|_| ())
+        .map_err(|err| ScriptError {
+            line,
+            message: format!("recording: {}", err),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::echo_machine;
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let script = Script::parse(
+            "# comment\n\ntype \"A\\r\"\nwait /\\\\/ 100\nrun $10\nassert mem $10 = 1\nassert reg pc = $FF12\nscreen\n",
+        )
+        .unwrap();
+        assert_eq!(6, script.steps.len());
+        assert_eq!(3, script.steps[0].0);
+        match &script.steps[1].1 {
+            ScriptStep::Wait { pattern, timeout } => {
+                assert_eq!("\\\\", pattern.as_str());
+                assert_eq!(100, *timeout);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let err = Script::parse("run 1\nfly away\n").unwrap_err();
+        assert_eq!(2, err.line);
+    }
+
+    #[test]
+    fn runs_script() {
+        let shot = std::env::temp_dir().join(format!("runner-{}.png", std::process::id()));
+        let gif = shot.with_extension("gif");
+        let script = Script::parse(&format!(
+            "record {} 50000\nwait /\\\\/\ntype \"HELLO\\r\"\nwait /HELLO\\n/ 100000\nbreak $FF12 1000\nrun 100\nassert reg A = $27\nassert mem $D011 = $27\nscreen\nscreenshot {}\n",
+            gif.display(),
+            shot.display()
+        ))
+        .unwrap();
+        let mut machine = echo_machine();
+        let mut out = Vec::new();
+
+        script.run(&mut machine, &mut out).unwrap();
+        let screen = String::from_utf8(out).unwrap();
+        assert!(screen.starts_with("\\HELLO\n\n"), "{}", screen);
+        assert_eq!(24, screen.lines().count());
+
+        let png = fs::read(&shot).unwrap();
+        fs::remove_file(&shot).unwrap();
+        assert_eq!(b"\x89PNG", &png[..4]);
+        let recorded = fs::read(&gif).unwrap();
+        fs::remove_file(&gif).unwrap();
+        assert_eq!(b"GIF89a", &recorded[..6]);
+        assert_eq!(Some(&0x3B), recorded.last());
+    }
+
+    #[test]
+    fn waits_on_output_not_the_transcript() {
+        let mut machine = echo_machine();
+        machine.display.keep_transcript(false);
+        let script =
+            Script::parse("wait /\\\\/\ntype \"AB\"\nwait /A/ 100000\nwait /^B$/ 100000\n")
+                .unwrap();
+        script.run(&mut machine, &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn reports_failures() {
+        let mut machine = echo_machine();
+        let mut out = Vec::new();
+
+        let err = Script::parse("run 100\nassert reg X = 1")
+            .unwrap()
+            .run(&mut machine, &mut out)
+            .unwrap_err();
+        assert_eq!(2, err.line);
+
+        let err = Script::parse("record stop")
+            .unwrap()
+            .run(&mut machine, &mut out)
+            .unwrap_err();
+        assert!(err.message.contains("not recording"), "{}", err);
+
+        let err = Script::parse("break NOWHERE")
+            .unwrap()
+            .run(&mut machine, &mut out)
+            .unwrap_err();
+        assert!(err.message.contains("unknown symbol"), "{}", err);
+
+        let err = Script::parse("wait /never/ 1000")
+            .unwrap()
+            .run(&mut machine, &mut out)
+            .unwrap_err();
+        assert!(err.message.contains("timed out"), "{}", err);
+    }
+}
@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+// Cycle based event scheduler. The CPU calls `run_until` with its cycle
+// counter before every instruction, so events fire in lockstep with emulated
+// time. An event callback gets the cycle it was due at and can return the
+// cycle to fire again at, for periodic events.
+
+pub type EventId = u64;
+
+type Callback = Box<dyn FnMut(usize) -> Option<usize>>;
+
+#[derive(Default)]
+struct SchedulerState {
+    now: usize,
+    next_id: EventId,
+    // Cancelled events stay queued and are skipped when due
+    queue: BinaryHeap<Reverse<(usize, EventId)>>,
+    callbacks: HashMap<EventId, Callback>,
+}
+
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    state: Rc<RefCell<SchedulerState>>,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Scheduler")
+            .field("now", &state.now)
+            .field("pending", &state.callbacks.len())
+            .finish()
+    }
+}
+
+impl Scheduler {
+    pub fn init() -> Scheduler {
+        Scheduler::default()
+    }
+
+    pub fn now(&self) -> usize {
+        self.state.borrow().now
+    }
+
+    pub fn pending(&self) -> usize {
+        self.state.borrow().callbacks.len()
+    }
+
+    pub fn schedule_at<F>(&self, at: usize, callback: F) -> EventId
+    where
+        F: FnMut(usize) -> Option<usize> + 'static,
+    {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queue.push(Reverse((at, id)));
+        state.callbacks.insert(id, Box::new(callback));
+        id
+    }
+
+    pub fn schedule_in<F>(&self, delay: usize, callback: F) -> EventId
+    where
+        F: FnMut(usize) -> Option<usize> + 'static,
+    {
+        let at = self.now() + delay;
+        self.schedule_at(at, callback)
+    }
+
+    // Returns false if the event already fired or was cancelled
+    pub fn cancel(&self, id: EventId) -> bool {
+        self.state.borrow_mut().callbacks.remove(&id).is_some()
+    }
+
+    // Cycle of the next pending event
+    pub fn next_event(&self) -> Option<usize> {
+        let mut state = self.state.borrow_mut();
+        while let Some(Reverse((at, id))) = state.queue.peek().copied() {
+            if state.callbacks.contains_key(&id) {
+                return Some(at);
+            }
+            state.queue.pop();
+        }
+        None
+    }
+
+    // Fires every event due at or before `cycles`, oldest first
+    pub fn run_until(&self, cycles: usize) {
+        loop {
+            let (at, id, mut callback) = {
+                let mut state = self.state.borrow_mut();
+                match state.queue.peek().copied() {
+                    Some(Reverse((at, id))) if at <= cycles => {
+                        state.queue.pop();
+                        state.now = state.now.max(at);
+                        match state.callbacks.remove(&id) {
+                            Some(callback) => (at, id, callback),
+                            None => continue,
+                        }
+                    }
+                    _ => break,
+                }
+            };
+
+            // The state isn't borrowed here, callbacks may schedule or cancel
+            if let Some(next) = callback(at) {
+                let mut state = self.state.borrow_mut();
+                state.queue.push(Reverse((next.max(at + 1), id)));
+                state.callbacks.insert(id, callback);
+            }
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.now = state.now.max(cycles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let scheduler = Scheduler::init();
+        assert_eq!(0, scheduler.now());
+        assert_eq!(None, scheduler.next_event());
+        scheduler.run_until(100);
+        assert_eq!(100, scheduler.now());
+    }
+
+    #[test]
+    fn fires_in_cycle_order() {
+        let scheduler = Scheduler::init();
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        for at in [30, 10, 20] {
+            let fired = fired.clone();
+            scheduler.schedule_at(at, move |cycles| {
+                fired.borrow_mut().push(cycles);
+                None
+            });
+        }
+        let cancelled = scheduler.schedule_at(15, |_| panic!("cancelled"));
+        assert!(scheduler.cancel(cancelled));
+        assert!(!scheduler.cancel(cancelled));
+
+        assert_eq!(Some(10), scheduler.next_event());
+        scheduler.run_until(20);
+        assert_eq!(vec![10, 20], *fired.borrow());
+        scheduler.run_until(100);
+        assert_eq!(vec![10, 20, 30], *fired.borrow());
+        assert_eq!(0, scheduler.pending());
+    }
+
+    #[test]
+    fn periodic_and_nested_events() {
+        let scheduler = Scheduler::init();
+        let ticks = Rc::new(RefCell::new(Vec::new()));
+
+        let log = ticks.clone();
+        scheduler.schedule_at(0, move |cycles| {
+            log.borrow_mut().push(cycles);
+            Some(cycles + 100)
+        });
+
+        let handle = scheduler.clone();
+        let log = ticks.clone();
+        scheduler.schedule_at(150, move |_| {
+            let log = log.clone();
+            handle.schedule_in(1, move |cycles| {
+                log.borrow_mut().push(cycles);
+                None
+            });
+            None
+        });
+
+        scheduler.run_until(250);
+        assert_eq!(vec![0, 100, 151, 200], *ticks.borrow());
+        assert_eq!(Some(300), scheduler.next_event());
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Per-address access counters for the whole 64K space. Opcode fetches count
+// as executes only, operand bytes count as reads.
+
+const SPACE: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+    // Sum of the three
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub executes: u64,
+}
+
+impl AccessCounts {
+    pub fn get(&self, kind: AccessKind) -> u64 {
+        match kind {
+            AccessKind::Read => self.reads,
+            AccessKind::Write => self.writes,
+            AccessKind::Execute => self.executes,
+            AccessKind::Any => self.reads + self.writes + self.executes,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessStats {
+    counts: Vec<AccessCounts>,
+}
+
+impl Default for AccessStats {
+    fn default() -> Self {
+        AccessStats::init()
+    }
+}
+
+impl AccessStats {
+    pub fn init() -> AccessStats {
+        AccessStats {
+            counts: vec![AccessCounts::default(); SPACE],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.counts
+            .iter_mut()
+            .for_each(|c| *c = AccessCounts::default());
+    }
+
+    pub fn record(&mut self, address: u16, kind: AccessKind) {
+        let counts = &mut self.counts[address as usize];
+        match kind {
+            AccessKind::Read => counts.reads += 1,
+            AccessKind::Write => counts.writes += 1,
+            AccessKind::Execute => counts.executes += 1,
+            AccessKind::Any => {}
+        }
+    }
+
+    pub fn get(&self, address: u16) -> AccessCounts {
+        self.counts[address as usize]
+    }
+
+    // Addresses accessed at least once, in address order
+    pub fn touched(&self) -> impl Iterator<Item = (u16, AccessCounts)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.get(AccessKind::Any) > 0)
+            .map(|(addr, c)| (addr as u16, *c))
+    }
+
+    pub fn total(&self, kind: AccessKind) -> u64 {
+        self.counts.iter().map(|c| c.get(kind)).sum()
+    }
+
+    // Lowest and highest address touched in [from, to], handy for sizing RAM
+    pub fn touched_range(&self, from: u16, to: u16) -> Option<(u16, u16)> {
+        let mut touched = self
+            .touched()
+            .filter(|(addr, _)| *addr >= from && *addr <= to);
+        let first = touched.next()?.0;
+        let last = touched.last().map_or(first, |(addr, _)| addr);
+        Some((first, last))
+    }
+
+    // One row per touched address
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,reads,writes,executes\n");
+        for (addr, c) in self.touched() {
+            csv.push_str(&format!(
+                "${:04X},{},{},{}\n",
+                addr, c.reads, c.writes, c.executes
+            ));
+        }
+        csv
+    }
+
+    // Binary PGM, one pixel per address: the row is the high byte, the
+    // column the low byte. Brightness is log scaled against the busiest
+    // address, untouched memory is black.
+    pub fn to_pgm(&self, kind: AccessKind) -> Vec<u8> {
+        let max = self.counts.iter().map(|c| c.get(kind)).max().unwrap_or(0);
+        let scale = ((max + 1) as f64).ln();
+
+        let mut pgm = b"P5\n256 256\n255\n".to_vec();
+        pgm.extend(self.counts.iter().map(|c| match c.get(kind) {
+            0 => 0,
+            n => (1.0 + 254.0 * ((n + 1) as f64).ln() / scale).round() as u8,
+        }));
+        pgm
+    }
+
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub fn write_pgm<P: AsRef<Path>>(&self, path: P, kind: AccessKind) -> io::Result<()> {
+        fs::write(path, self.to_pgm(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let stats = AccessStats::init();
+        assert_eq!(0, stats.total(AccessKind::Any));
+        assert_eq!(None, stats.touched_range(0, 0xFFFF));
+        assert_eq!("address,reads,writes,executes\n", stats.to_csv());
+    }
+
+    #[test]
+    fn counts_and_exports() {
+        let mut stats = AccessStats::init();
+        stats.record(0x0300, AccessKind::Execute);
+        stats.record(0x0301, AccessKind::Read);
+        stats.record(0x0301, AccessKind::Read);
+        stats.record(0x1FFF, AccessKind::Write);
+
+        assert_eq!(2, stats.get(0x0301).reads);
+        assert_eq!(4, stats.total(AccessKind::Any));
+        assert_eq!(Some((0x0300, 0x1FFF)), stats.touched_range(0, 0xCFFF));
+        assert_eq!(
+            "address,reads,writes,executes\n$0300,0,0,1\n$0301,2,0,0\n$1FFF,0,1,0\n",
+            stats.to_csv()
+        );
+
+        let pgm = stats.to_pgm(AccessKind::Read);
+        let pixels = &pgm[15..];
+        assert_eq!(b"P5\n256 256\n255\n", &pgm[..15]);
+        assert_eq!(0x10000, pixels.len());
+        assert_eq!(0, pixels[0x0300]);
+        assert_eq!(255, pixels[0x0301]);
+
+        stats.reset();
+        assert_eq!(0, stats.total(AccessKind::Any));
+    }
+}
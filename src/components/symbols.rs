@@ -0,0 +1,306 @@
+use super::runner::parse_number;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Symbol tables for the debugging tools. `Symbols::parse` takes any mix of:
+//
+//   al C:FFEF .ECHO                        VICE labels, also ld65 -Ln output
+//   sym id=0,name="ECHO",...,val=0xFFEF    ca65/ld65 --dbgfile records
+//   ECHO = $FFEF                           plain assignments
+//
+// Blank lines and `;` or `#` comments are skipped, as are the other record
+// types of a ca65 debug file.
+
+const DBG_RECORDS: [&str; 12] = [
+    "version", "info", "file", "lib", "mod", "seg", "span", "line", "scope", "type", "csym", "sym",
+];
+
+// Woz Monitor entry points and variables, and the keyboard/display PIA
+const WOZ: [(&str, u16); 26] = [
+    ("XAML", 0x24),
+    ("XAMH", 0x25),
+    ("STL", 0x26),
+    ("STH", 0x27),
+    ("L", 0x28),
+    ("H", 0x29),
+    ("YSAV", 0x2A),
+    ("MODE", 0x2B),
+    ("IN", 0x0200),
+    ("KBD", 0xD010),
+    ("KBDCR", 0xD011),
+    ("DSP", 0xD012),
+    ("DSPCR", 0xD013),
+    ("RESET", 0xFF00),
+    ("NOTCR", 0xFF0F),
+    ("ESCAPE", 0xFF1A),
+    ("GETLINE", 0xFF1F),
+    ("BACKSPACE", 0xFF26),
+    ("NEXTCHAR", 0xFF29),
+    ("SETSTOR", 0xFF40),
+    ("SETMODE", 0xFF41),
+    ("BLSKIP", 0xFF43),
+    ("NEXTITEM", 0xFF44),
+    ("PRBYTE", 0xFFDC),
+    ("PRHEX", 0xFFE5),
+    ("ECHO", 0xFFEF),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SymbolError {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    by_name: HashMap<String, u16>,
+    // All names per address, the first one defined is used for display
+    by_addr: BTreeMap<u16, Vec<String>>,
+}
+
+fn address(text: &str) -> Result<u16, String> {
+    parse_number(text)
+        .filter(|addr| *addr <= 0xFFFF)
+        .map(|addr| addr as u16)
+        .ok_or_else(|| format!("bad address {}", text))
+}
+
+// `al [C:]ADDR .NAME`, the address is bare hex
+fn parse_vice(rest: &str) -> Result<(String, u16), String> {
+    match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [addr, name] => {
+            let addr = addr.rsplit(':').next().unwrap_or(addr);
+            let addr = address(&format!("${}", addr))?;
+            Ok((name.trim_start_matches('.').to_string(), addr))
+        }
+        _ => Err(String::from("expected 'al ADDR .NAME'")),
+    }
+}
+
+// `sym id=0,name="ECHO",...,val=0xFFEF,...`, imports have no value
+fn parse_dbg_sym(rest: &str) -> Result<Option<(String, u16)>, String> {
+    let mut name = None;
+    let mut value = None;
+    for field in rest.split(',') {
+        match field.trim().split_once('=') {
+            Some(("name", quoted)) => name = Some(quoted.trim_matches('"').to_string()),
+            Some(("val", val)) => value = Some(address(val)?),
+            _ => {}
+        }
+    }
+    match (name, value) {
+        (Some(name), Some(value)) => Ok(Some((name, value))),
+        (None, _) => Err(String::from("sym record without a name")),
+        _ => Ok(None),
+    }
+}
+
+fn parse_line(line: &str) -> Result<Option<(String, u16)>, String> {
+    let (record, rest) = match line.find(char::is_whitespace) {
+        Some(at) => (&line[..at], line[at..].trim()),
+        None => (line, ""),
+    };
+    if record == "al" {
+        return parse_vice(rest).map(Some);
+    }
+    if record == "sym" && rest.contains("name=") {
+        return parse_dbg_sym(rest);
+    }
+    if DBG_RECORDS.contains(&record) && !rest.starts_with('=') && !rest.starts_with(":=") {
+        return Ok(None);
+    }
+    match line.split_once('=') {
+        Some((name, addr)) => {
+            let name = name.trim().trim_end_matches(':').trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(format!("bad symbol name '{}'", name));
+            }
+            Ok(Some((name.to_string(), address(addr.trim())?)))
+        }
+        None => Err(format!("can't parse '{}'", line)),
+    }
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn woz() -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, addr) in WOZ.iter() {
+            symbols.insert(name, *addr);
+        }
+        symbols
+    }
+
+    pub fn parse(source: &str) -> Result<Symbols, SymbolError> {
+        let mut symbols = Symbols::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            let parsed = parse_line(line).map_err(|message| SymbolError {
+                line: i + 1,
+                message,
+            })?;
+            if let Some((name, addr)) = parsed {
+                symbols.insert(&name, addr);
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Symbols, SymbolError> {
+        let source = fs::read_to_string(&path).map_err(|err| SymbolError {
+            line: 0,
+            message: format!("{}: {}", path.as_ref().display(), err),
+        })?;
+        Symbols::parse(&source)
+    }
+
+    // Redefining a name moves it to the new address
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr) {
+            if let Some(names) = self.by_addr.get_mut(&old) {
+                names.retain(|n| n != name);
+                if names.is_empty() {
+                    self.by_addr.remove(&old);
+                }
+            }
+        }
+        self.by_addr.entry(addr).or_default().push(name.to_string());
+    }
+
+    pub fn extend(&mut self, other: &Symbols) {
+        for (addr, name) in other.iter() {
+            self.insert(name, addr);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.by_addr
+            .get(&addr)
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+
+    // Every name, in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.by_addr
+            .iter()
+            .flat_map(|(addr, names)| names.iter().map(move |name| (*addr, name.as_str())))
+    }
+
+    // Closest symbol at or below addr, with the offset from it
+    pub fn nearest(&self, addr: u16) -> Option<(&str, u16)> {
+        self.by_addr
+            .range(..=addr)
+            .next_back()
+            .map(|(at, names)| (names[0].as_str(), addr - at))
+    }
+
+    // `ECHO`, `ECHO+2`, `ECHO-1` or a plain number
+    pub fn resolve(&self, spec: &str) -> Option<u16> {
+        let spec = spec.trim();
+        if let Some(addr) = parse_number(spec).filter(|addr| *addr <= 0xFFFF) {
+            return Some(addr as u16);
+        }
+        let (name, offset) = match spec.find(['+', '-']) {
+            Some(at) if at > 0 => {
+                let offset = parse_number(spec[at + 1..].trim())? as u16;
+                match &spec[at..at + 1] {
+                    "+" => (&spec[..at], offset),
+                    _ => (&spec[..at], offset.wrapping_neg()),
+                }
+            }
+            _ => (spec, 0),
+        };
+        self.get(name.trim()).map(|addr| addr.wrapping_add(offset))
+    }
+
+    // Name or name+offset when there is a symbol within `reach` bytes,
+    // otherwise the address in hex
+    pub fn describe(&self, addr: u16, reach: u16) -> String {
+        match self.nearest(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) if offset <= reach => format!("{}+{}", name, offset),
+            _ => format!("${:04X}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_woz_symbols() {
+        let symbols = Symbols::woz();
+        assert_eq!(Some(0xFFEF), symbols.get("ECHO"));
+        assert_eq!(Some("KBDCR"), symbols.name(0xD011));
+        assert_eq!(Some(0xFF21), symbols.resolve("GETLINE+2"));
+        assert_eq!(Some(0xFFEE), symbols.resolve("ECHO-1"));
+        assert_eq!(Some(0x0300), symbols.resolve("$0300"));
+        assert_eq!(None, symbols.resolve("NOWHERE"));
+        assert_eq!("NEXTCHAR+3", symbols.describe(0xFF2C, 16));
+        assert_eq!("$0300", symbols.describe(0x0300, 16));
+    }
+
+    #[test]
+    fn parses_all_formats() {
+        let symbols = Symbols::parse(
+            "; mixed file\n\
+             al C:0300 .START\n\
+             al 00FFEF .ECHO\n\
+             version\tmajor=2,minor=0\n\
+             sym\tid=0,name=\"LOOP\",addrsize=absolute,size=1,scope=0,def=1,val=0x305,seg=0,type=lab\n\
+             sym\tid=1,name=\"EXTERN\",addrsize=absolute,scope=0,def=2,type=imp\n\
+             BUFFER = $0400\n\
+             COUNT := 16\n",
+        )
+        .unwrap();
+        assert_eq!(5, symbols.len());
+        assert_eq!(Some(0x0300), symbols.get("START"));
+        assert_eq!(Some(0xFFEF), symbols.get("ECHO"));
+        assert_eq!(Some(0x0305), symbols.get("LOOP"));
+        assert_eq!(Some(0x0400), symbols.get("BUFFER"));
+        assert_eq!(Some(16), symbols.get("COUNT"));
+
+        let err = Symbols::parse("GOOD = $10\nBAD = $10000\n").unwrap_err();
+        assert_eq!(2, err.line);
+    }
+
+    #[test]
+    fn redefinition_moves_name() {
+        let mut symbols = Symbols::woz();
+        symbols.insert("ECHO", 0x0300);
+        assert_eq!(None, symbols.name(0xFFEF));
+        assert_eq!(Some("ECHO"), symbols.name(0x0300));
+        assert_eq!(Symbols::woz().len(), symbols.len());
+    }
+}
@@ -0,0 +1,327 @@
+use super::display::DisplayOutput;
+use super::machine::Machine;
+use super::time::{SystemTime, TimeSource};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Serves the Apple-1 keyboard and display over TCP. Every client sees the
+// display output and can type; keys from several clients simply interleave
+// in the keyboard queue. A client attaching to a running machine first gets
+// the current screen, then the output as it happens, with the Apple-1's CR
+// sent as CR LF.
+//
+// Plain sockets get raw bytes. In telnet mode the server asks the client for
+// character at a time input without local echo (the Apple-1 echoes itself)
+// and strips IAC sequences from what comes back.
+//
+// Sockets are non-blocking; output a client isn't ready for waits in its
+// own queue, so one slow reader neither stalls the machine nor loses bytes.
+
+const IAC: u8 = 0xFF;
+const SB: u8 = 0xFA;
+const SE: u8 = 0xF0;
+const WILL: u8 = 0xFB;
+const DONT: u8 = 0xFE;
+const ECHO: u8 = 0x01;
+const SUPPRESS_GO_AHEAD: u8 = 0x03;
+
+// Emulated time between polls of the sockets
+const SLICE: Duration = Duration::from_millis(10);
+
+// Unsent output after which a client that stopped reading is dropped, hours
+// of Apple-1 output
+const MAX_BACKLOG: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Telnet {
+    Data,
+    Command,
+    Option,
+    Sub,
+    SubCommand,
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: TcpStream,
+    peer: SocketAddr,
+    telnet: Telnet,
+    last_cr: bool,
+    outgoing: VecDeque<u8>,
+}
+
+impl Client {
+    fn init(stream: TcpStream, peer: SocketAddr) -> Client {
+        Client {
+            stream,
+            peer,
+            telnet: Telnet::Data,
+            last_cr: false,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    // Sends as much of the queue as the socket takes. False when the client
+    // hung up, failed or fell too far behind.
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(self.outgoing.make_contiguous()) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        self.outgoing.len() <= MAX_BACKLOG
+    }
+
+    // Keys in the data, minus telnet commands and the LF or NUL after a CR
+    fn keys(&mut self, data: &[u8], telnet: bool) -> String {
+        let mut keys = String::new();
+        for &byte in data {
+            let state = self.telnet;
+            self.telnet = match (state, byte) {
+                (Telnet::Data, IAC) if telnet => Telnet::Command,
+                (Telnet::Command, SB) => Telnet::Sub,
+                (Telnet::Command, WILL..=DONT) => Telnet::Option,
+                (Telnet::Sub, IAC) => Telnet::SubCommand,
+                (Telnet::SubCommand, SE) => Telnet::Data,
+                (Telnet::Sub, _) | (Telnet::SubCommand, _) => Telnet::Sub,
+                _ => Telnet::Data,
+            };
+            // IAC IAC is a literal 0xFF, which isn't a key anyway
+            if state != Telnet::Data || self.telnet != Telnet::Data {
+                continue;
+            }
+            let after_cr = self.last_cr;
+            self.last_cr = byte == b'\r';
+            match byte {
+                b'\n' | 0 if after_cr => {}
+                _ if byte.is_ascii() => keys.push(byte as char),
+                _ => {}
+            }
+        }
+        keys
+    }
+}
+
+#[derive(Debug)]
+pub struct TerminalServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    output: Option<DisplayOutput>,
+    telnet: bool,
+}
+
+fn to_network(text: &str) -> Vec<u8> {
+    text.replace('\n', "\r\n").into_bytes()
+}
+
+impl TerminalServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TerminalServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TerminalServer {
+            listener,
+            clients: Vec::new(),
+            output: None,
+            telnet: false,
+        })
+    }
+
+    pub fn localhost(port: u16) -> io::Result<TerminalServer> {
+        TerminalServer::bind((Ipv4Addr::LOCALHOST, port))
+    }
+
+    pub fn telnet(mut self, telnet: bool) -> TerminalServer {
+        self.telnet = telnet;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    // Accepts new clients, queues their keys and sends them the output the
+    // display produced since the last poll. Clients that hang up or fail
+    // are dropped.
+    pub fn poll(&mut self, machine: &mut Machine) -> io::Result<()> {
+        let display = &machine.display;
+        let output = self.output.get_or_insert_with(|| display.subscribe());
+        let output = to_network(&output.take());
+
+        let mut live = Vec::with_capacity(self.clients.len());
+        for mut client in self.clients.drain(..) {
+            client.outgoing.extend(&output);
+            if !client.flush() {
+                continue;
+            }
+            let mut buf = [0u8; 256];
+            match client.stream.read(&mut buf) {
+                Ok(0) => continue,
+                Ok(n) => {
+                    let keys = client.keys(&buf[..n], self.telnet);
+                    if !keys.is_empty() {
+                        machine.feeder.type_text(&keys);
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => continue,
+            }
+            live.push(client);
+        }
+        self.clients = live;
+
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => self.attach(machine, stream, peer)?,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn attach(&mut self, machine: &Machine, stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let mut client = Client::init(stream, peer);
+        if self.telnet {
+            client
+                .outgoing
+                .extend([IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD]);
+        }
+        // Screen up to the cursor, so new output continues where it should
+        let (column, row) = machine.display.cursor();
+        let screen = machine.display.screen_text();
+        let mut lines: Vec<&str> = screen.lines().take(row + 1).collect();
+        let last = lines.pop().unwrap_or("");
+        let mut text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        text.push_str(&format!("{:<width$}", last, width = column));
+        client.outgoing.extend(to_network(&text));
+        if client.flush() {
+            self.clients.push(client);
+        }
+        Ok(())
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.clients.iter().map(|client| client.peer)
+    }
+
+    // Runs the machine at `mhz` forever, polling between slices
+    pub fn run(&mut self, machine: &mut Machine, mhz: f64) -> io::Result<()> {
+        let mut time = SystemTime::init();
+        let cycles = (mhz * SLICE.as_micros() as f64) as usize;
+        loop {
+            let start = time.now();
+            machine.run_cycles(cycles);
+            self.poll(machine)?;
+            let spent = time.now() - start;
+            if spent < SLICE {
+                time.sleep(SLICE - spent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::echo_machine;
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    // Polls until `done` or a few seconds have passed
+    fn pump<F: FnMut(&TerminalServer) -> bool>(
+        server: &mut TerminalServer,
+        machine: &mut Machine,
+        mut done: F,
+    ) {
+        let start = Instant::now();
+        while !done(server) && start.elapsed() < Duration::from_secs(5) {
+            machine.run_cycles(1000);
+            server.poll(machine).unwrap();
+        }
+    }
+
+    #[test]
+    fn strips_telnet_commands() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let peer = stream.local_addr().unwrap();
+        let mut client = Client::init(stream, peer);
+        let data = [
+            IAC, 0xFD, ECHO, b'E', IAC, SB, 0x18, 0x01, IAC, SE, b'R', b'\r', 0, b'X', b'\r', b'\n',
+        ];
+        assert_eq!("ER\rX\r", client.keys(&data, true));
+    }
+
+    #[test]
+    fn queues_output_for_slow_readers() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let data: Vec<u8> = (0..MAX_BACKLOG).map(|i| (i % 251) as u8).collect();
+        let len = data.len();
+        let reader = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            let mut received = vec![0u8; len];
+            stream.read_exact(&mut received).unwrap();
+            received
+        });
+        let (stream, peer) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut client = Client::init(stream, peer);
+
+        // More than the socket takes at once, so some writes stop short
+        client.outgoing.extend(&data);
+        let start = Instant::now();
+        while !client.outgoing.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            assert!(client.flush());
+        }
+        assert_eq!(data, reader.join().unwrap());
+
+        client.outgoing.extend(vec![0u8; MAX_BACKLOG + 1]);
+        client.stream.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(!client.flush());
+    }
+
+    #[test]
+    fn serves_clients() {
+        let mut machine = echo_machine();
+        machine.run_cycles(1000);
+        let mut server = TerminalServer::localhost(0).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let first = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut screen = [0u8; 1];
+            stream.read_exact(&mut screen).unwrap();
+            stream.write_all(b"hi\r\n").unwrap();
+            let mut echo = [0u8; 4];
+            stream.read_exact(&mut echo).unwrap();
+            (screen, echo)
+        });
+        pump(&mut server, &mut machine, |server| server.clients() == 1);
+        let second = TcpStream::connect(addr).unwrap();
+        pump(&mut server, &mut machine, |server| server.clients() == 2);
+        pump(&mut server, &mut machine, |_| first.is_finished());
+
+        let (screen, echo) = first.join().unwrap();
+        assert_eq!(b"\\", &screen);
+        assert_eq!(b"HI\r\n", &echo);
+        drop(second);
+        pump(&mut server, &mut machine, |server| server.clients() == 0);
+        assert_eq!(0, server.clients());
+    }
+}
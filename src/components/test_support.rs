@@ -0,0 +1,77 @@
+use super::address_spaces::AddressMap;
+use super::assembler::Assembler;
+use super::machine::Machine;
+use super::mc6502::CpuVariant;
+use super::symbols::Symbols;
+use super::{ram, rom};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Helpers shared by the unit tests
+
+// In-memory writer, clones share the same bytes
+#[derive(Clone, Default)]
+pub struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Shared {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Prints "\" then echoes keys, like a very small Woz Monitor
+const ECHO_MONITOR: &str = "
+        .org $FF00
+RESET:  LDA #$7F
+        STA DSP
+        LDA #$A7
+        STA KBDCR
+        STA DSPCR
+        LDA #'\\'+$80
+        STA DSP
+NEXT:   LDA KBDCR
+        BPL NEXT
+        LDA KBD
+WAIT:   BIT DSP
+        BMI WAIT
+        STA DSP
+        JMP NEXT
+        .org $FFFC
+        .word RESET
+";
+
+// 4K of RAM plus the echo monitor in ROM at $FF00, reset
+pub fn echo_machine() -> Machine {
+    let the_mapping = vec![
+        AddressMap {
+            addr: [0x0000, 0x0fff],
+            component: Box::new(ram::Ram::init_with_size(0x1000)),
+            name: String::from("RAM"),
+        },
+        AddressMap {
+            addr: [0xff00, 0xffff],
+            component: Box::new(rom::Rom::init_with_size(0x100)),
+            name: String::from("ROM"),
+        },
+    ];
+    let mut machine = Machine::init(the_mapping, CpuVariant::Nmos6502, false);
+    let monitor = Assembler::init(CpuVariant::Nmos6502)
+        .symbols(&Symbols::woz())
+        .assemble(ECHO_MONITOR)
+        .unwrap();
+    monitor.load(machine.cpu.address_spaces());
+    machine.reset();
+    machine
+}
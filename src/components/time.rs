@@ -0,0 +1,186 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Where `Clock` gets wall clock time from. `now` is measured from an
+// arbitrary origin, only differences matter.
+pub trait TimeSource: Debug {
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+// A clock speed or time scale that isn't a positive, finite number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedError {
+    pub value: f64,
+}
+
+impl fmt::Display for SpeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "speed must be positive and finite, got {}", self.value)
+    }
+}
+
+impl Error for SpeedError {}
+
+pub fn check_speed(value: f64) -> Result<f64, SpeedError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(SpeedError { value })
+    }
+}
+
+// Host time
+#[derive(Debug, Clone, Copy)]
+pub struct SystemTime {
+    origin: Instant,
+}
+
+impl Default for SystemTime {
+    fn default() -> Self {
+        SystemTime::init()
+    }
+}
+
+impl SystemTime {
+    pub fn init() -> SystemTime {
+        SystemTime {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl TimeSource for SystemTime {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// Virtual time that only moves when told to. Sleeping advances it instantly,
+// so a throttled clock runs deterministically at full host speed. Clones
+// share the same time, keep one to drive a clock owning another.
+#[derive(Debug, Clone, Default)]
+pub struct ManualTime {
+    now: Rc<Cell<Duration>>,
+}
+
+impl ManualTime {
+    pub fn init() -> ManualTime {
+        ManualTime::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+}
+
+impl TimeSource for ManualTime {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+// Runs another source faster (scale > 1) or slower than it really is
+#[derive(Debug, Clone)]
+pub struct ScaledTime<T: TimeSource> {
+    inner: T,
+    scale: f64,
+}
+
+impl<T: TimeSource> ScaledTime<T> {
+    pub fn init(inner: T, scale: f64) -> Result<ScaledTime<T>, SpeedError> {
+        Ok(ScaledTime {
+            inner,
+            scale: check_speed(scale)?,
+        })
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+// Saturates instead of panicking like Duration::mul_f64
+fn scale_duration(duration: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(duration.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+}
+
+impl<T: TimeSource> TimeSource for ScaledTime<T> {
+    fn now(&self) -> Duration {
+        scale_duration(self.inner.now(), self.scale)
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.inner.sleep(scale_duration(duration, 1.0 / self.scale));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_time_is_shared() {
+        let time = ManualTime::init();
+        let mut other = time.clone();
+        assert_eq!(Duration::from_millis(0), time.now());
+
+        other.sleep(Duration::from_millis(3));
+        time.advance(Duration::from_millis(2));
+        assert_eq!(Duration::from_millis(5), other.now());
+
+        time.set(Duration::from_secs(1));
+        assert_eq!(Duration::from_secs(1), other.now());
+    }
+
+    #[test]
+    fn scaled_time() {
+        let manual = ManualTime::init();
+        let mut scaled = ScaledTime::init(manual.clone(), 2.0).unwrap();
+
+        manual.advance(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(20), scaled.now());
+
+        scaled.sleep(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(15), manual.now());
+
+        for scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(ScaledTime::init(manual.clone(), scale).is_err());
+        }
+
+        // Saturates rather than overflowing
+        let huge = ScaledTime::init(manual.clone(), 1e300).unwrap();
+        assert_eq!(Duration::MAX, huge.now());
+        let mut tiny = ScaledTime::init(ManualTime::init(), 1e-300).unwrap();
+        tiny.sleep(Duration::from_nanos(1));
+        assert_eq!(Duration::MAX, tiny.inner().now());
+    }
+
+    #[test]
+    fn system_time_moves_forward() {
+        let mut time = SystemTime::init();
+        let before = time.now();
+        time.sleep(Duration::from_millis(1));
+        assert!(time.now() >= before + Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,198 @@
+use super::disasm;
+use super::mc6502::CPU6502;
+use super::symbols::Symbols;
+use super::Clockable;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Write;
+
+// Instruction tracer with breakpoints. Breakpoints are given as symbols or
+// addresses ("ECHO", "GETLINE+2", "$0300") and stop the CPU before the
+// instruction there runs. Trace lines look like
+//
+//   FF29  NEXTCHAR      AD 11 D0  LDA KBDCR        A=8D X=00 Y=01 P=30 S=FD
+//
+// and are written to the trace output, when one is set, before each step.
+
+// How far past a symbol an address is still shown as NAME+offset
+const SYMBOL_REACH: u16 = 0x40;
+
+pub struct Tracer {
+    symbols: Symbols,
+    breakpoints: BTreeSet<u16>,
+    out: Option<Box<dyn Write>>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("symbols", &self.symbols.len())
+            .field("breakpoints", &self.breakpoints)
+            .field("tracing", &self.out.is_some())
+            .finish()
+    }
+}
+
+impl Tracer {
+    pub fn init(symbols: Symbols) -> Tracer {
+        Tracer {
+            symbols,
+            breakpoints: BTreeSet::new(),
+            out: None,
+        }
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut Symbols {
+        &mut self.symbols
+    }
+
+    pub fn trace_to(&mut self, out: Box<dyn Write>) {
+        self.out = Some(out);
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.out = None;
+    }
+
+    pub fn set_breakpoint(&mut self, spec: &str) -> Result<u16, String> {
+        let addr = self
+            .symbols
+            .resolve(spec)
+            .ok_or_else(|| format!("unknown symbol or address {}", spec))?;
+        self.breakpoints.insert(addr);
+        Ok(addr)
+    }
+
+    // False when there was no breakpoint there
+    pub fn clear_breakpoint(&mut self, spec: &str) -> bool {
+        match self.symbols.resolve(spec) {
+            Some(addr) => self.breakpoints.remove(&addr),
+            None => false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Trace line for the instruction about to run
+    pub fn line(&self, cpu: &CPU6502) -> String {
+        let registers = cpu.registers();
+        let ins = disasm::disassemble(|addr| cpu.peek(addr), cpu.variant(), registers.pc);
+        format!(
+            "{:04X}  {:<12}  {:<8}  {:<16} A={:02X} X={:02X} Y={:02X} P={:02X} S={:02X}",
+            registers.pc,
+            self.symbols.describe(registers.pc, SYMBOL_REACH),
+            ins.hex(),
+            ins.text_with(&self.symbols),
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.p,
+            registers.s
+        )
+    }
+
+    pub fn step(&mut self, cpu: &mut CPU6502) -> usize {
+        if self.out.is_some() {
+            let line = self.line(cpu);
+            if let Some(out) = &mut self.out {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+        cpu.step()
+    }
+
+    // Runs for up to `cycles`, returning the breakpoint hit if any. The
+    // first instruction always runs so a stopped CPU can be continued.
+    pub fn run(&mut self, cpu: &mut CPU6502, cycles: usize) -> Option<u16> {
+        let until = cpu.get_cycles() + cycles;
+        self.step(cpu);
+        while cpu.get_cycles() < until {
+            let pc = cpu.registers().pc;
+            if self.breakpoints.contains(&pc) {
+                return Some(pc);
+            }
+            self.step(cpu);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::address_spaces::{AddressMap, AddressSpaces};
+    use super::super::mc6502::CpuVariant;
+    use super::super::test_support::Shared;
+    use super::super::{ram, IoAddressable};
+    use super::*;
+
+    fn build_cpu() -> CPU6502 {
+        // LDA #$C1; JSR ECHO; JMP $0300 with ECHO being an RTS
+        let mut memory = ram::Ram::init_with_size(0x10000);
+        memory.flash(&[0x03, 0x00, 0xA9, 0xC1, 0x20, 0xEF, 0xFF, 0x4C, 0x00, 0x03]);
+        memory.poke(0xFFEF, 0x60);
+        memory.poke(0xFFFD, 0x03);
+        let bus = AddressSpaces::init(vec![AddressMap {
+            addr: [0x0000, 0xffff],
+            component: Box::new(memory),
+            name: String::from("RAM"),
+        }]);
+        let mut cpu = CPU6502::init(bus, CpuVariant::Nmos6502);
+        cpu.reset();
+        cpu.set_registers(super::super::mc6502::Registers {
+            s: 0xFF,
+            ..cpu.registers()
+        });
+        cpu
+    }
+
+    #[test]
+    fn breaks_on_symbols() {
+        let mut cpu = build_cpu();
+        let mut tracer = Tracer::init(Symbols::woz());
+        assert_eq!(Ok(0xFFEF), tracer.set_breakpoint("ECHO"));
+        assert!(tracer.set_breakpoint("NOWHERE").is_err());
+
+        assert_eq!(Some(0xFFEF), tracer.run(&mut cpu, 1000));
+        assert_eq!(0xC1, cpu.registers().a);
+        assert_eq!(Some(0xFFEF), tracer.run(&mut cpu, 1000));
+
+        assert!(tracer.clear_breakpoint("ECHO"));
+        assert_eq!(None, tracer.run(&mut cpu, 100));
+        assert_eq!(0, tracer.breakpoints().count());
+    }
+
+    #[test]
+    fn traces_with_symbols() {
+        let mut cpu = build_cpu();
+        let mut tracer = Tracer::init(Symbols::woz());
+        let out = Shared::default();
+        tracer.trace_to(Box::new(out.clone()));
+        tracer.step(&mut cpu);
+        tracer.step(&mut cpu);
+        tracer.stop_trace();
+        assert!(tracer
+            .line(&cpu)
+            .starts_with("FFEF  ECHO          60        RTS"));
+        tracer.step(&mut cpu);
+
+        let text = String::from_utf8(out.bytes()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(
+            lines[0].starts_with("0300  $0300         A9 C1     LDA #$C1 "),
+            "{}",
+            text
+        );
+        assert!(
+            lines[1].starts_with("0302  $0302         20 EF FF  JSR ECHO "),
+            "{}",
+            text
+        );
+    }
+}
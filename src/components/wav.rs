@@ -0,0 +1,150 @@
+use std::io;
+
+// Minimal RIFF/WAVE PCM codec, enough for cassette images: 8 or 16 bit,
+// any channel count on input (mixed down to mono), 16 bit mono on output.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn le_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn le_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+impl Wav {
+    pub fn init(sample_rate: u32, samples: Vec<i16>) -> Wav {
+        Wav {
+            sample_rate,
+            samples,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Wav> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF/WAVE file"));
+        }
+
+        let mut format: Option<(u16, u16, u32, u16)> = None;
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let size = le_u32(data, pos + 4) as usize;
+            let body = pos + 8;
+            let end = body.saturating_add(size).min(data.len());
+
+            if id == b"fmt " {
+                if end - body < 16 {
+                    return Err(invalid("truncated fmt chunk"));
+                }
+                format = Some((
+                    le_u16(data, body),
+                    le_u16(data, body + 2),
+                    le_u32(data, body + 4),
+                    le_u16(data, body + 14),
+                ));
+            } else if id == b"data" {
+                let (tag, channels, sample_rate, bits) =
+                    format.ok_or_else(|| invalid("data chunk before fmt chunk"))?;
+                if tag != 1 {
+                    return Err(invalid("only PCM WAV files are supported"));
+                }
+                if channels == 0 || (bits != 8 && bits != 16) {
+                    return Err(invalid("only 8 and 16 bit PCM is supported"));
+                }
+
+                let width = (bits / 8) as usize;
+                let frame = width * channels as usize;
+                let samples = data[body..end]
+                    .chunks_exact(frame)
+                    .map(|frame| {
+                        let sum: i32 = frame
+                            .chunks_exact(width)
+                            .map(|s| {
+                                if width == 1 {
+                                    (s[0] as i32 - 0x80) << 8
+                                } else {
+                                    i16::from_le_bytes([s[0], s[1]]) as i32
+                                }
+                            })
+                            .sum();
+                        (sum / channels as i32) as i16
+                    })
+                    .collect();
+
+                return Ok(Wav::init(sample_rate, samples));
+            }
+
+            // Chunks are word aligned
+            pos = body + size + (size & 1);
+        }
+
+        Err(invalid("missing data chunk"))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        out.extend_from_slice(&1u16.to_le_bytes()); // mono
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let wav = Wav::init(22050, vec![0, 1000, -1000, i16::MAX, i16::MIN]);
+        assert_eq!(wav, Wav::decode(&wav.encode()).unwrap());
+    }
+
+    #[test]
+    fn decodes_8bit_stereo() {
+        let mut data = Wav::init(8000, vec![]).encode();
+        // Patch the header for 2 channels, 8 bits
+        data[22] = 2;
+        data[34] = 8;
+        data.truncate(40);
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+
+        let wav = Wav::decode(&data).unwrap();
+        assert_eq!(8000, wav.sample_rate);
+        assert_eq!(vec![0x7F00, -0x8000], wav.samples);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Wav::decode(b"not a wav file at all").is_err());
+    }
+}
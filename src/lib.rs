@@ -0,0 +1,76 @@
+// Apple-1 emulator: a 6502/65C02 core, the PIA wired keyboard and terminal,
+// the cassette interface and the machine profiles around them.
+//
+// `Machine`/`MachineBuilder` cover the common case, the modules below expose
+// the parts for anyone building their own memory map.
+
+mod components;
+
+pub use components::machine::{Machine, MachineBuilder, PIA_ADDR};
+
+pub mod cpu {
+    pub use crate::components::mc6502::{CpuVariant, Registers, CPU6502};
+    pub use crate::components::Clockable;
+}
+
+pub mod bus {
+    pub use crate::components::address_spaces::{AddressMap, AddressSpaces};
+    pub use crate::components::handle::DeviceHandle;
+    pub use crate::components::stats::{AccessCounts, AccessKind, AccessStats};
+    pub use crate::components::{ControlLines, IoAddressable, IoComponent};
+}
+
+pub mod devices {
+    pub use crate::components::aci::{Aci, Tape};
+    pub use crate::components::callback::CallbackDevice;
+    pub use crate::components::display::{Display, DisplayOutput, COLUMNS, CYCLES_PER_CHAR, ROWS};
+    pub use crate::components::feeder::{FeedStep, InputFeeder};
+    pub use crate::components::pia6820::Pia6820;
+    pub use crate::components::ram::Ram;
+    pub use crate::components::rom::Rom;
+    pub use crate::components::terminal::TerminalServer;
+}
+
+pub mod loaders {
+    pub use crate::components::config::{Card, Config, ConfigError, Cpu, Pia, RamRegion, RomImage};
+    pub use crate::components::profiles::{EntryPoint, ImageSlot, Profile, ProfileError, PROFILES};
+    pub use crate::components::wav::Wav;
+}
+
+pub mod timing {
+    pub use crate::components::aci::APPLE1_CLOCK_HZ;
+    pub use crate::components::clock::{
+        Clock, APPLE1_MHZ, MAX_CATCH_UP, MAX_MULTIPLIER, MIN_MULTIPLIER,
+    };
+    pub use crate::components::scheduler::{EventId, Scheduler};
+    pub use crate::components::time::{ManualTime, ScaledTime, SpeedError, SystemTime, TimeSource};
+}
+
+pub mod video {
+    pub use crate::components::frame::{crc32, Frame};
+    pub use crate::components::gif::GifWriter;
+    pub use crate::components::recorder::{Recorder, DEFAULT_INTERVAL};
+    pub use crate::components::render::{
+        cursor_visible, glyph, Phosphor, Renderer, CELL_HEIGHT, CELL_WIDTH, CURSOR_BLINK_CYCLES,
+        FONT, HEIGHT, WIDTH,
+    };
+}
+
+pub mod debug {
+    pub use crate::components::assembler::{AsmError, Assembler, Assembly};
+    pub use crate::components::coverage::{Coverage, Region, RegionCoverage};
+    pub use crate::components::disasm::{
+        decode, disassemble, disassemble_range, opcodes, Instruction, Mode, Opcode,
+    };
+    pub use crate::components::gdb::{listen, GdbStub};
+    pub use crate::components::profiler::{Profiler, RoutineProfile};
+    pub use crate::components::symbols::{SymbolError, Symbols};
+    pub use crate::components::tracer::Tracer;
+}
+
+pub mod testing {
+    pub use crate::components::golden::{
+        assert_golden, compare, compare_with, diff, GoldenError, UPDATE_ENV,
+    };
+    pub use crate::components::runner::{parse_number, Register, Script, ScriptError, ScriptStep};
+}
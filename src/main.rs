@@ -1,12 +1,162 @@
-mod components;
-use components::*;
+use apple1_rst::debug::{listen, GdbStub};
+use apple1_rst::devices::TerminalServer;
+use apple1_rst::loaders::{Config, Profile, PROFILES};
+use apple1_rst::testing::Script;
+use apple1_rst::timing::{Clock, APPLE1_MHZ};
+use apple1_rst::{Machine, MachineBuilder};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+
+// Emulated time between checks for typed lines, 10 ms
+const BATCH_MICROS: f64 = 10_000.0;
+
+fn usage() -> ! {
+    eprintln!("usage: apple1_rst <profile> [slot=image.bin ...]");
+    eprintln!("       apple1_rst <machine.toml>");
+    eprintln!("       apple1_rst headless <script> <profile> [slot=image.bin ...]");
+    eprintln!("       apple1_rst gdb <port> <profile> [slot=image.bin ...]");
+    eprintln!("       apple1_rst serve [--telnet] <port> <profile> [slot=image.bin ...]");
+    for profile in PROFILES {
+        let slots: Vec<&str> = profile.slots.iter().map(|slot| slot.name).collect();
+        eprintln!(
+            "  {:<14} {} (slots: {})",
+            profile.name,
+            profile.description,
+            slots.join(", ")
+        );
+    }
+    process::exit(2);
+}
+
+fn fail<E: std::fmt::Display>(err: E) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+// `<profile> [slot=image.bin ...]` or `<machine.toml>`, with the clock in MHz
+fn parse_machine_args(args: &[String]) -> (MachineBuilder, f64) {
+    if let Some(path) = args.first().filter(|arg| arg.ends_with(".toml")) {
+        if args.len() > 1 {
+            usage();
+        }
+        let config = Config::load(path).unwrap_or_else(|err| fail(err));
+        let builder = MachineBuilder::new()
+            .config(&config)
+            .unwrap_or_else(|err| fail(err));
+        return (builder, config.clock_mhz);
+    }
+
+    let profile = match args.first() {
+        Some(name) => Profile::by_name(name).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            usage()
+        }),
+        None => usage(),
+    };
+
+    let mut builder = MachineBuilder::new().with_profile(profile);
+    for arg in &args[1..] {
+        let (slot, path) = match arg.find('=') {
+            Some(at) => (&arg[..at], &arg[at + 1..]),
+            None => usage(),
+        };
+        let data = fs::read(path).unwrap_or_else(|err| fail(format!("{}: {}", path, err)));
+        builder = builder.image(slot, &data);
+    }
+    (builder, APPLE1_MHZ)
+}
+
+fn headless(args: &[String]) {
+    let path = args.first().unwrap_or_else(|| usage());
+    let source = fs::read_to_string(path).unwrap_or_else(|err| fail(format!("{}: {}", path, err)));
+    let script = Script::parse(&source).unwrap_or_else(|err| fail(format!("{}: {}", path, err)));
+
+    let mut machine = parse_machine_args(&args[1..])
+        .0
+        .build()
+        .unwrap_or_else(|err| fail(err));
+    if let Err(err) = script.run(&mut machine, &mut io::stdout()) {
+        fail(format!("{}: {}", path, err));
+    }
+}
+
+// Waits for a debugger on 127.0.0.1:<port>, one session after another
+fn gdb(args: &[String]) {
+    let port = args
+        .first()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or_else(|| usage());
+    let mut machine = parse_machine_args(&args[1..])
+        .0
+        .build()
+        .unwrap_or_else(|err| fail(err));
+    let listener = listen(port).unwrap_or_else(|err| fail(err));
+    eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+    loop {
+        let served = GdbStub::accept(&listener).and_then(|mut stub| stub.serve(&mut machine.cpu));
+        if let Err(err) = served {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+// Runs the machine in real time with its console on 127.0.0.1:<port>
+fn serve(args: &[String]) {
+    let telnet = args.first().map(String::as_str) == Some("--telnet");
+    let args = if telnet { &args[1..] } else { args };
+    let port = args
+        .first()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or_else(|| usage());
+    let (builder, mhz) = parse_machine_args(&args[1..]);
+    let mut machine = builder.build().unwrap_or_else(|err| fail(err));
+    // Runs indefinitely, the clients get the output from their own queue
+    machine.display.keep_transcript(false);
+    let mut server = TerminalServer::localhost(port)
+        .unwrap_or_else(|err| fail(err))
+        .telnet(telnet);
+    eprintln!("serving the console on 127.0.0.1:{}", port);
+    if let Err(err) = server.run(&mut machine, mhz) {
+        fail(err);
+    }
+}
+
+// Runs the machine in real time at `mhz` on this terminal. Lines typed on
+// stdin are queued for the keyboard once Return is pressed.
+fn interactive(builder: MachineBuilder, mhz: f64) {
+    let Machine { cpu, feeder, .. } = builder.echo(true).build().unwrap_or_else(|err| fail(err));
+    let (lines, typed) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut clock = Clock::init(Box::new(cpu), mhz, (mhz * BATCH_MICROS) as usize)
+        .unwrap_or_else(|err| fail(err));
+    loop {
+        clock.cycle();
+        while let Ok(line) = typed.try_recv() {
+            feeder.type_line(&line);
+        }
+    }
+}
 
 fn main() {
-    let the_mapping = vec![address_spaces::AddressMap {
-        addr: [100, 200],
-        component: Box::new(ram::Ram::init_with_size(100)),
-        name: String::from("RAM"),
-    }];
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("headless") => return headless(&args[1..]),
+        Some("gdb") => return gdb(&args[1..]),
+        Some("serve") => return serve(&args[1..]),
+        _ => {}
+    }
 
-    let _cpu = mc6502::CPU6502::init(address_spaces::AddressSpaces::init(the_mapping));
+    let (builder, mhz) = parse_machine_args(&args);
+    interactive(builder, mhz);
 }